@@ -0,0 +1,34 @@
+//! Records the compiler version for the `process.runtime.*` resource
+//! attributes and the commit for `service.git.sha`.
+use std::{env, path::Path, process::Command};
+
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    if let Some(version) = output(Command::new(rustc).arg("--version")) {
+        println!("cargo:rustc-env=RUSTC_VERSION={version}");
+    }
+    println!("cargo:rerun-if-env-changed=RUSTC");
+
+    // CI builds from a source tarball set SERVICE_GIT_SHA themselves.
+    let sha = env::var("SERVICE_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| output(Command::new("git").args(["rev-parse", "HEAD"])));
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=SERVICE_GIT_SHA={sha}");
+    }
+    println!("cargo:rerun-if-env-changed=SERVICE_GIT_SHA");
+    // Rebuild on commits and checkouts.
+    for path in [".git/HEAD", ".git/refs/heads", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
+
+/// The trimmed standard output of a successful `command`.
+fn output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok().filter(|o| o.status.success())?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned()).filter(|o| !o.is_empty())
+}
@@ -0,0 +1,44 @@
+//! Admin endpoints for operating the service, enabled with
+//! `OtelTempoAdminEndpoints`. They are unauthenticated, so keep them off
+//! public listeners or behind a gateway that checks access.
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use crate::logging::{self, LogFilterError};
+
+/// A router serving `GET` and `PUT /admin/log-level`, to merge into the
+/// application. `PUT` takes filter directives as the body:
+/// `curl -X PUT --data 'axum_otel_tempo=debug,info' localhost:3000/admin/log-level`.
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new().route("/admin/log-level", get(log_level).put(set_log_level))
+}
+
+async fn log_level() -> Response {
+    match logging::log_filter() {
+        Some(directives) => directives.into_response(),
+        None => (StatusCode::NOT_FOUND, "the log filter is not reloadable").into_response(),
+    }
+}
+
+async fn set_log_level(directives: String) -> Response {
+    let directives = directives.trim();
+    match logging::set_log_filter(directives) {
+        Ok(()) => {
+            tracing::info!(directives, "log filter changed");
+            logging::log_filter().unwrap_or_default().into_response()
+        }
+        Err(e @ LogFilterError::Invalid(_)) => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        Err(e @ LogFilterError::NotInstalled) => {
+            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+        }
+    }
+}
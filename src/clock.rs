@@ -0,0 +1,149 @@
+use std::time::SystemTime;
+
+#[cfg(feature = "test-clock")]
+use opentelemetry::{
+    sdk::{export::trace::SpanData, trace::SpanProcessor},
+    trace::TraceResult,
+};
+#[cfg(feature = "test-clock")]
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+#[cfg(feature = "test-clock")]
+use tracing::{span, Subscriber};
+#[cfg(feature = "test-clock")]
+use tracing_opentelemetry::OtelData;
+#[cfg(feature = "test-clock")]
+use tracing_subscriber::{layer, registry::LookupSpan, Layer};
+
+/// The time source of this crate's time dependent components: the sampling
+/// schedule, the export rate limit, the export circuit breaker's cooldown and
+/// the request durations measured by the middleware. Span timestamps are
+/// taken by tracing-opentelemetry unless [`ClockStartTime`] and
+/// [`ClockEndTime`] are installed.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real clock, used unless another one is installed with `set_clock`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[cfg(feature = "test-clock")]
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+/// The current time according to the installed clock.
+#[cfg(not(feature = "test-clock"))]
+pub fn now() -> SystemTime {
+    SystemClock.now()
+}
+
+/// The current time according to the installed clock.
+#[cfg(feature = "test-clock")]
+pub fn now() -> SystemTime {
+    match &*CLOCK.read().unwrap() {
+        Some(clock) => clock.now(),
+        None => SystemClock.now(),
+    }
+}
+
+/// Replaces the clock for the whole process, so tests can control time.
+#[cfg(feature = "test-clock")]
+pub fn set_clock(clock: impl Clock + 'static) {
+    *CLOCK.write().unwrap() = Some(Arc::new(clock));
+}
+
+/// A clock that only moves when told to.
+#[cfg(feature = "test-clock")]
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+#[cfg(feature = "test-clock")]
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        *self.0.lock().unwrap() = time;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(feature = "test-clock")]
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Stamps the start of spans with the installed clock. Add it after
+/// tracing-opentelemetry's layer, and [`ClockEndTime`] to the span
+/// processors, so span durations follow a [`ManualClock`].
+#[cfg(feature = "test-clock")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClockStartTime;
+
+#[cfg(feature = "test-clock")]
+impl<S> Layer<S> for ClockStartTime
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        _attrs: &span::Attributes<'_>,
+        id: &span::Id,
+        ctx: layer::Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+                data.builder.start_time = Some(now());
+            }
+        }
+    }
+}
+
+/// Stamps the end of spans with the installed clock before passing them on,
+/// so processors such as `MinDurationFilter` see [`ManualClock`] durations.
+#[cfg(feature = "test-clock")]
+#[derive(Debug)]
+pub struct ClockEndTime {
+    inner: Box<dyn SpanProcessor>,
+}
+
+#[cfg(feature = "test-clock")]
+impl ClockEndTime {
+    pub fn new(inner: Box<dyn SpanProcessor>) -> Self {
+        Self { inner }
+    }
+}
+
+#[cfg(feature = "test-clock")]
+impl SpanProcessor for ClockEndTime {
+    fn on_start(&self, span: &mut opentelemetry::sdk::trace::Span, cx: &opentelemetry::Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        span.end_time = now();
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,1370 @@
+use axum::http::HeaderName;
+use opentelemetry::{
+    sdk::trace::{Sampler, SpanLimits},
+    KeyValue,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::{self, Debug, Display},
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tracing_subscriber::{filter::ParseError, EnvFilter};
+
+use crate::error::TelemetryError;
+use crate::export::{has_authorization, ExportCompression, ExportProtocol, HttpEncoding};
+use crate::http_trace::{CapturedHeaders, TrustedProxies};
+use crate::logging::LogFormat;
+use crate::middleware::{BodyCapture, RequestSpanFields};
+use crate::oauth::OAuth2Settings;
+use crate::processors::{KeyPolicyMode, OversizedSpanMode, RedactPattern, RedactionRules};
+use crate::propagation::Propagators;
+use crate::reload::Reloadable;
+use crate::resource::{
+    self, CloudAttributes, Detector, ResourcePrecedence, Signal, SignalResources,
+};
+use crate::sampling::{self, RouteSampling, ScheduledSampler};
+use crate::secrets::{self, CredentialFile};
+use crate::tls::ExportTls;
+
+/// Config file read by [`crate::load_settings`] unless `OtelTempoConfigFile`
+/// names another one.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Tempo's distributor truncates attribute values above `max_attribute_bytes`
+/// (2 KiB by default), so values are cut to this length before export.
+pub const TEMPO_MAX_ATTRIBUTE_VALUE_LENGTH: usize = 2048;
+
+/// User agent sent with exports unless `OtelTempoUserAgent` overrides it.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// OTLP/HTTP endpoint of a collector sidecar, used by `OtelTempoLocalCollector`.
+pub const LOCAL_COLLECTOR_ENDPOINT: &str = "http://localhost:4318/v1/traces";
+
+/// OTLP/gRPC endpoint of a collector sidecar, used by `OtelTempoLocalCollector`
+/// when `OtelTempoExportProtocol` is `grpc`.
+pub const LOCAL_COLLECTOR_GRPC_ENDPOINT: &str = "http://localhost:4317";
+
+/// The collector sidecar endpoint for `protocol`.
+pub fn local_collector_endpoint(protocol: ExportProtocol) -> &'static str {
+    match protocol {
+        ExportProtocol::Http => LOCAL_COLLECTOR_ENDPOINT,
+        ExportProtocol::Grpc => LOCAL_COLLECTOR_GRPC_ENDPOINT,
+    }
+}
+
+/// Span file used by [`TelemetryMode::File`] unless `OtelTempoSpanFile` names another.
+pub const DEFAULT_SPAN_FILE: &str = "spans.jsonl";
+
+const DEFAULT_SPAN_FILE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+const DEFAULT_SPAN_FILE_MAX_FILES: usize = 5;
+
+const DEFAULT_BODY_CAPTURE_MAX_BYTES: usize = 4096;
+
+const DEFAULT_BODY_CAPTURE_CONTENT_TYPES: &[&str] = &["application/json", "text/*"];
+
+const DEFAULT_TAIL_SAMPLING_WINDOW: Duration = Duration::from_secs(30);
+
+const DEFAULT_TAIL_SAMPLING_MAX_SPANS: usize = 10_000;
+
+const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DEFAULT_SPILL_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+const DEFAULT_EXPORT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+const DEFAULT_EXPORT_CIRCUIT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Address the service listens on unless `OtelTempoBindAddress` overrides it.
+pub const DEFAULT_BIND_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3000);
+
+pub struct Settings {
+    pub mode: TelemetryMode,
+    /// File spans are written to in [`TelemetryMode::File`].
+    pub span_file: PathBuf,
+    /// Size at which the span file is rotated.
+    pub span_file_max_bytes: u64,
+    /// Number of rotated span files kept.
+    pub span_file_max_files: usize,
+    pub otel_username: String,
+    pub otel_password: String,
+    /// Sent as `Authorization: Bearer <token>` instead of Basic auth, e.g. a
+    /// Grafana Cloud API token. The username and password are not read.
+    pub bearer_token: Option<String>,
+    /// Set when the password or bearer token came from a secret file, which is
+    /// then re-read on SIGHUP.
+    pub credential_file: Option<CredentialFile>,
+    /// Fetch export access tokens with the OAuth2 client credentials flow
+    /// instead of sending fixed credentials.
+    pub oauth2: Option<OAuth2Settings>,
+    /// CA bundle and client certificate for the export connection.
+    pub export_tls: ExportTls,
+    pub otel_endpoint: String,
+    /// Extra headers sent with every export, such as `X-Scope-OrgID` or a
+    /// vendor API key, from `OtelTempoExportHeaders` or
+    /// `OTEL_EXPORTER_OTLP_HEADERS` (`k=v,k2=v2`). An `Authorization` header
+    /// replaces the configured credentials.
+    pub export_headers: HashMap<String, String>,
+    /// Address the service listens on.
+    pub bind_address: SocketAddr,
+    /// Export without credentials to a collector sidecar, which handles auth
+    /// to Tempo itself. The endpoint defaults to [`LOCAL_COLLECTOR_ENDPOINT`]
+    /// and the Tempo username and password are not read.
+    pub local_collector: bool,
+    pub span_limits: SpanLimitsPreset,
+    /// `OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT`: longer string values are truncated.
+    pub attribute_value_length_limit: Option<usize>,
+    /// `OTEL_ATTRIBUTE_COUNT_LIMIT`: attributes kept per span, event and link,
+    /// overriding the preset.
+    pub attribute_count_limit: Option<u32>,
+    pub local_address: Option<IpAddr>,
+    /// User agent of the export client, so collectors can attribute the traffic.
+    pub user_agent: String,
+    /// Export from a dedicated thread instead of the application's runtime.
+    pub export_thread: bool,
+    /// Spans of failed exports kept to retry with the next batch. Unused when
+    /// `spill_dir` is set.
+    pub recovery_buffer_spans: usize,
+    /// Attempts made at each export before it counts as failed. One, the
+    /// default, does not retry.
+    pub export_max_attempts: u32,
+    /// Wait before the first retry, doubled for each next one.
+    pub export_retry_backoff: Duration,
+    /// Consecutive failed exports after which exports pause for
+    /// `export_circuit_cooldown`. Never paused when unset.
+    pub export_circuit_failures: Option<u32>,
+    /// How long exports pause once the circuit opens.
+    pub export_circuit_cooldown: Duration,
+    /// Directory the spans of failed exports are written to, to replay once
+    /// the backend is back. Takes the place of `recovery_buffer_spans`.
+    pub spill_dir: Option<PathBuf>,
+    /// Size the spill directory is kept under by deleting the oldest spans.
+    pub spill_max_bytes: u64,
+    /// Spans the batch processor queues before dropping new ones. The
+    /// `OTEL_BSP_*` variables or SDK defaults apply to the unset `batch_*`
+    /// settings.
+    pub batch_max_queue_size: Option<usize>,
+    /// Spans sent per export, at most `batch_max_queue_size`.
+    pub batch_max_export_batch_size: Option<usize>,
+    /// Time between scheduled exports.
+    pub batch_scheduled_delay: Option<Duration>,
+    /// Time an export may take before it is abandoned.
+    pub batch_export_timeout: Option<Duration>,
+    /// Whether spans are sent over OTLP/HTTP or OTLP/gRPC.
+    pub export_protocol: ExportProtocol,
+    /// Payload encoding of spans sent over OTLP/HTTP. Metrics and logs are
+    /// always sent as protobuf.
+    pub http_encoding: HttpEncoding,
+    /// Compression of OTLP/HTTP request bodies, for all signals. Not applied
+    /// over gRPC.
+    pub export_compression: ExportCompression,
+    /// Send an empty span export at startup and warn when the endpoint
+    /// rejects it, instead of finding out from dropped spans later.
+    pub export_preflight: bool,
+    /// Force a flush on this interval, for seeing spans quickly during development.
+    pub flush_interval: Option<Duration>,
+    /// Force a flush after every this many requests.
+    pub flush_every_requests: Option<u64>,
+    /// Limit on requests handled at once; the time spent waiting for a slot is
+    /// recorded on the request span.
+    pub max_concurrent_requests: Option<usize>,
+    /// Which fields `TraceLayer` records on its request span.
+    pub request_span_fields: RequestSpanFields,
+    /// Vary the sampling ratio by time of day instead of using the
+    /// environment's default sampler.
+    pub sampling_schedule: Option<ScheduledSampler>,
+    /// The sampler to use, overriding the schedule and the environment default.
+    /// Read from `OtelTempoSampler` (e.g. `traceidratio:0.1`) or
+    /// `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`.
+    pub sampler: Option<Sampler>,
+    /// Trace context formats read from requests and written to outgoing ones.
+    pub propagators: Propagators,
+    /// Sample ratios by request route, applied ahead of the sampler.
+    pub route_sampling: Option<RouteSampling>,
+    /// Attach an `x-correlation-id` to each request's span, baggage, logs and response.
+    pub correlation_id: bool,
+    /// Return the trace id in `x-trace-id` and `traceresponse` response headers.
+    pub trace_id_headers: bool,
+    /// Request header listing `traceparent`s the request span links to.
+    pub links_header: Option<HeaderName>,
+    /// Request header to span attribute, set on the request span and every
+    /// span beneath it.
+    pub context_attributes: HashMap<HeaderName, String>,
+    /// Reverse proxies whose `Forwarded`/`X-Forwarded-For` headers name the
+    /// client recorded as `client.address`.
+    pub trusted_proxies: TrustedProxies,
+    /// Request headers recorded on the request span as `http.request.header.<name>`.
+    pub capture_request_headers: Reloadable<CapturedHeaders>,
+    /// Response headers recorded on the request span as `http.response.header.<name>`.
+    pub capture_response_headers: Reloadable<CapturedHeaders>,
+    /// Record request and response bodies of these routes, for debugging.
+    pub body_capture: Option<Reloadable<BodyCapture>>,
+    /// Incoming baggage entries recorded on the request span, by key.
+    pub baggage_attributes: Vec<String>,
+    /// Path parameters recorded on the request span, by name.
+    pub record_path_params: Vec<String>,
+    /// Dotted path of the JSON error body field used as the span status
+    /// description of server errors, e.g. `error.message`.
+    pub error_status_field: Vec<String>,
+    /// Span attribute holding the tenant; when set, spans are exported to the
+    /// Tempo tenant it maps to.
+    pub tenant_attribute: Option<String>,
+    /// Tenant attribute value to Tempo org id. Spans of tenants not listed go
+    /// to the default tenant.
+    pub tenant_org_ids: HashMap<String, String>,
+    /// OTLP/HTTP traces endpoints that also receive every span, without
+    /// credentials, such as a local collector during a migration. Spans are
+    /// sampled, rate limited and processed once for all endpoints; each one
+    /// is counted under its own `telemetry.destination` and spills to its
+    /// own subdirectory of `spill_dir`.
+    pub secondary_endpoints: Vec<String>,
+    /// Attribute key patterns, such as `*.email`, stripped from every span.
+    pub attribute_denylist: Vec<String>,
+    /// Attribute values scrubbed from every span, when redaction is enabled.
+    pub redaction: Option<RedactionRules>,
+    /// What to do with span attributes whose keys break the naming policy.
+    pub attribute_key_policy: KeyPolicyMode,
+    /// Start with logging only instead of panicking when span export cannot be set up.
+    pub fail_open: bool,
+    /// Estimated size above which a single span is truncated or dropped.
+    pub max_span_bytes: Option<usize>,
+    /// How spans above `max_span_bytes` are handled.
+    pub oversized_spans: OversizedSpanMode,
+    /// Add the current span's `trace_flags` and `parent_remote` to log lines.
+    pub log_trace_flags: bool,
+    /// How log lines are written to stdout.
+    pub log_format: LogFormat,
+    /// Filter directives for stdout log lines, instead of `RUST_LOG`.
+    pub log_filter: Option<String>,
+    /// Filter directives for exported spans and log records, instead of `RUST_LOG`.
+    pub export_filter: Option<String>,
+    /// Drop non-root spans shorter than this.
+    pub min_span_duration: Option<Duration>,
+    /// Hard ceiling on exported spans per second.
+    pub max_spans_per_second: Option<f64>,
+    /// Keep only traces with an error or a local root span at least this slow.
+    pub tail_sampling_latency: Option<Duration>,
+    /// How long a trace stays buffered waiting for its root span.
+    pub tail_sampling_window: Duration,
+    /// Spans buffered across all undecided traces before the oldest are dropped.
+    pub tail_sampling_max_spans: usize,
+    /// Also export HTTP server metrics over OTLP, with the same credentials.
+    pub metrics: bool,
+    /// Also export log events over OTLP, with the trace and span they were
+    /// logged in.
+    pub logs: bool,
+    /// Where logs go. Defaults to `/v1/logs` next to the traces endpoint.
+    pub logs_endpoint: Option<String>,
+    /// Serve metrics for scraping on the route from [`crate::prometheus::router`].
+    pub prometheus: bool,
+    /// Serve the routes from [`crate::admin::router`].
+    pub admin_endpoints: bool,
+    /// Serve the routes from [`crate::health::health_routes`] and
+    /// [`crate::health::readiness_routes`].
+    pub health_endpoints: bool,
+    /// Where metrics go. Defaults to `/v1/metrics` next to the traces endpoint.
+    pub metrics_endpoint: Option<String>,
+    /// How often metrics are exported.
+    pub metrics_interval: Duration,
+    /// Emit a heartbeat span on this interval to keep the export path warm.
+    pub heartbeat_interval: Option<Duration>,
+    /// Log the export counters of [`crate::status::telemetry_status`] on this
+    /// interval, warning when spans were dropped or exports failed since the
+    /// last report.
+    pub status_log_interval: Option<Duration>,
+    /// How long [`crate::TelemetryGuard::shutdown`] waits for the final flush.
+    pub shutdown_timeout: Duration,
+    /// The `service.name` resource attribute.
+    pub service_name: Option<String>,
+    /// The `service.namespace` resource attribute, grouping related services.
+    pub service_namespace: Option<String>,
+    /// The `service.instance.id` resource attribute. A random UUID per
+    /// process when unset.
+    pub service_instance_id: Option<String>,
+    /// The `service.version` resource attribute. When unset,
+    /// [`resource::BUILD_SERVICE_VERSION`] fills in, below
+    /// `OTEL_RESOURCE_ATTRIBUTES` and the detectors.
+    pub service_version: Option<String>,
+    /// Emit a `deploy` span at startup, for Grafana to overlay as a deploy marker.
+    pub deploy_event: bool,
+    /// The `environment` resource attribute.
+    pub environment: Option<String>,
+    /// Refuse to start span export with the placeholder service name and
+    /// environment instead of falling back to them.
+    pub require_service_identity: bool,
+    /// Explicitly configured `cloud.*` resource attributes.
+    pub cloud: CloudAttributes,
+    /// `OTEL_RESOURCE_ATTRIBUTES`, merged below the configured attributes
+    /// and, depending on `resource_precedence`, the detected ones.
+    pub resource_attributes: Vec<KeyValue>,
+    /// Resource detectors describing the host, OS, process, container and
+    /// Kubernetes workload.
+    pub resource_detectors: Vec<Detector>,
+    /// Whether configured resource attributes or detected ones win on conflict.
+    pub resource_precedence: ResourcePrecedence,
+    /// Per-signal attributes layered on top of the base resource.
+    pub signal_resources: SignalResources,
+    /// Where each setting above was resolved from, for debugging precedence.
+    pub resolution: Vec<ResolvedSetting>,
+}
+
+/// Settings with every optional feature off and no endpoint or credentials,
+/// for building up in code. [`load_settings`] reads them from the environment.
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: TelemetryMode::default(),
+            span_file: PathBuf::from(DEFAULT_SPAN_FILE),
+            span_file_max_bytes: DEFAULT_SPAN_FILE_MAX_BYTES,
+            span_file_max_files: DEFAULT_SPAN_FILE_MAX_FILES,
+            otel_username: String::new(),
+            otel_password: String::new(),
+            bearer_token: None,
+            credential_file: None,
+            oauth2: None,
+            export_tls: ExportTls::default(),
+            otel_endpoint: String::new(),
+            export_headers: HashMap::new(),
+            bind_address: DEFAULT_BIND_ADDRESS,
+            local_collector: false,
+            span_limits: SpanLimitsPreset::Default,
+            attribute_value_length_limit: None,
+            attribute_count_limit: None,
+            local_address: None,
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            export_thread: false,
+            recovery_buffer_spans: 0,
+            export_max_attempts: 1,
+            export_retry_backoff: DEFAULT_EXPORT_RETRY_BACKOFF,
+            export_circuit_failures: None,
+            export_circuit_cooldown: DEFAULT_EXPORT_CIRCUIT_COOLDOWN,
+            spill_dir: None,
+            spill_max_bytes: DEFAULT_SPILL_MAX_BYTES,
+            batch_max_queue_size: None,
+            batch_max_export_batch_size: None,
+            batch_scheduled_delay: None,
+            batch_export_timeout: None,
+            export_protocol: ExportProtocol::default(),
+            http_encoding: HttpEncoding::default(),
+            export_compression: ExportCompression::default(),
+            export_preflight: false,
+            flush_interval: None,
+            flush_every_requests: None,
+            max_concurrent_requests: None,
+            request_span_fields: RequestSpanFields::default(),
+            sampling_schedule: None,
+            sampler: None,
+            propagators: Propagators::default(),
+            route_sampling: None,
+            correlation_id: false,
+            trace_id_headers: false,
+            links_header: None,
+            context_attributes: HashMap::new(),
+            trusted_proxies: TrustedProxies::default(),
+            capture_request_headers: Reloadable::default(),
+            capture_response_headers: Reloadable::default(),
+            body_capture: None,
+            baggage_attributes: Vec::new(),
+            record_path_params: Vec::new(),
+            error_status_field: Vec::new(),
+            tenant_attribute: None,
+            tenant_org_ids: HashMap::new(),
+            secondary_endpoints: Vec::new(),
+            attribute_denylist: Vec::new(),
+            redaction: None,
+            attribute_key_policy: KeyPolicyMode::Off,
+            fail_open: false,
+            max_span_bytes: None,
+            oversized_spans: OversizedSpanMode::Truncate,
+            log_trace_flags: false,
+            log_format: LogFormat::Full,
+            log_filter: None,
+            export_filter: None,
+            min_span_duration: None,
+            max_spans_per_second: None,
+            tail_sampling_latency: None,
+            tail_sampling_window: DEFAULT_TAIL_SAMPLING_WINDOW,
+            tail_sampling_max_spans: DEFAULT_TAIL_SAMPLING_MAX_SPANS,
+            metrics: false,
+            logs: false,
+            logs_endpoint: None,
+            prometheus: false,
+            admin_endpoints: false,
+            health_endpoints: true,
+            metrics_endpoint: None,
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+            heartbeat_interval: None,
+            status_log_interval: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            service_name: None,
+            service_namespace: None,
+            service_instance_id: None,
+            service_version: None,
+            deploy_event: false,
+            environment: None,
+            require_service_identity: false,
+            cloud: CloudAttributes::default(),
+            resource_attributes: Vec::new(),
+            resource_detectors: Detector::DEFAULT.to_vec(),
+            resource_precedence: ResourcePrecedence::default(),
+            signal_resources: SignalResources::default(),
+            resolution: Vec::new(),
+        }
+    }
+}
+
+/// Where a setting's value came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingSource {
+    Env(&'static str),
+    /// The config file, see [`crate::config`].
+    File,
+    /// The secret file named by this variable.
+    SecretFile(&'static str),
+    Default,
+}
+
+impl Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingSource::Env(var) => write!(f, "env:{var}"),
+            SettingSource::File => f.write_str("file"),
+            SettingSource::SecretFile(var) => write!(f, "secret_file:{var}"),
+            SettingSource::Default => f.write_str("default"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ResolvedSetting {
+    pub name: &'static str,
+    pub source: SettingSource,
+    /// The resolved value, or `<redacted>` for secrets.
+    pub value: String,
+}
+
+/// Where spans go, selected with `TELEMETRY_MODE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TelemetryMode {
+    /// Export over OTLP to Tempo or a collector.
+    #[default]
+    Otlp,
+    /// Print spans to stdout, for local development without Tempo or
+    /// credentials.
+    Stdout,
+    /// Append spans as JSON lines to a rotating local file, see
+    /// [`crate::span_file`].
+    File,
+    /// Install only the log layers and leave the global no-op tracer in
+    /// place, for running with plain logging when tracing is turned off.
+    Disabled,
+}
+
+impl FromStr for TelemetryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "otlp" => Ok(TelemetryMode::Otlp),
+            "stdout" => Ok(TelemetryMode::Stdout),
+            "file" => Ok(TelemetryMode::File),
+            "disabled" => Ok(TelemetryMode::Disabled),
+            other => Err(format!(
+                "expected otlp, stdout, file or disabled, got {other}"
+            )),
+        }
+    }
+}
+
+/// Which span limits to apply to the tracer, selected with `OtelTempoSpanLimits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanLimitsPreset {
+    /// The limits this service has always used.
+    Default,
+    /// [`span_limits_for_tempo`] plus attribute value truncation.
+    Tempo,
+}
+
+impl FromStr for SpanLimitsPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(SpanLimitsPreset::Default),
+            "tempo" => Ok(SpanLimitsPreset::Tempo),
+            other => Err(format!("expected \"default\" or \"tempo\", got {other}")),
+        }
+    }
+}
+
+/// Span limits known to be accepted by a default Tempo installation.
+pub fn span_limits_for_tempo() -> SpanLimits {
+    SpanLimits {
+        max_events_per_span: 128,
+        max_attributes_per_span: 128,
+        max_links_per_span: 128,
+        max_attributes_per_event: 32,
+        max_attributes_per_link: 32,
+    }
+}
+
+/// Reads [`Settings`] from the environment and `.env`, falling back to the
+/// config file for the settings it covers. Fails on the first required setting
+/// that is missing or value that is invalid.
+pub fn load_settings() -> Result<Settings, TelemetryError> {
+    match dotenvy::dotenv() {
+        Ok(path) => println!(".env read successfully from {}", path.display()),
+        Err(e) => println!("Could not load .env file: {e}"),
+    };
+
+    let config_file = env::var("OtelTempoConfigFile").ok();
+    let path = config_file.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
+    let file = FileConfig::load(Path::new(path), config_file.is_some()).map_err(|reason| {
+        TelemetryError::ConfigFile {
+            path: path.to_owned(),
+            reason,
+        }
+    })?;
+    let mut env = EnvReader::with_file(file);
+
+    let mode: TelemetryMode = env.parse("mode", "TELEMETRY_MODE").unwrap_or_default();
+    let local_collector = env
+        .parse("local_collector", "OtelTempoLocalCollector")
+        .unwrap_or(false);
+    let needs_credentials = mode == TelemetryMode::Otlp && !local_collector;
+    let export_protocol: ExportProtocol = env
+        .parse("export_protocol", "OtelTempoExportProtocol")
+        .unwrap_or_default();
+    let export_headers: HashMap<String, String> = env
+        .first(
+            "export_headers",
+            &[
+                "OtelTempoExportHeaders",
+                "OTEL_EXPORTER_OTLP_TRACES_HEADERS",
+                "OTEL_EXPORTER_OTLP_HEADERS",
+            ],
+            true,
+        )
+        .map(|(var, value)| {
+            parse_otlp_headers(&value)
+                .map_err(|reason| TelemetryError::InvalidSetting { var, reason })
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let endpoint = env
+        .first(
+            "otel_endpoint",
+            &[
+                "OtelTempoEndpoint",
+                "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+                "OTEL_EXPORTER_OTLP_ENDPOINT",
+            ],
+            false,
+        )
+        .map(|(var, value)| match (var, export_protocol) {
+            // The signal independent endpoint is a base URL over HTTP, while
+            // gRPC selects the signal by service rather than by path.
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", ExportProtocol::Http) => {
+                format!("{}/v1/traces", value.trim_end_matches('/'))
+            }
+            _ => value,
+        });
+    let bearer_token = if !needs_credentials {
+        None
+    } else {
+        env.first("bearer_token", &["OtelTempoBearerToken"], true)
+            .map(|(_, token)| token)
+    };
+    let oauth2 = match env.first("oauth2_token_url", &["OtelTempoOAuth2TokenUrl"], false) {
+        Some((_, token_url)) if needs_credentials => Some(OAuth2Settings {
+            token_url,
+            client_id: env.required("oauth2_client_id", "OtelTempoOAuth2ClientId"),
+            client_secret: env.secret("oauth2_client_secret", "OtelTempoOAuth2ClientSecret"),
+            scope: env.parse("oauth2_scope", "OtelTempoOAuth2Scope"),
+        }),
+        _ => None,
+    };
+    let (otel_username, otel_password, otel_endpoint) = if mode != TelemetryMode::Otlp {
+        (String::new(), String::new(), endpoint.unwrap_or_default())
+    } else if local_collector {
+        let endpoint =
+            endpoint.unwrap_or_else(|| String::from(local_collector_endpoint(export_protocol)));
+        (String::new(), String::new(), endpoint)
+    } else if bearer_token.is_some() || oauth2.is_some() || has_authorization(&export_headers) {
+        let endpoint = endpoint.ok_or(TelemetryError::MissingSetting("OtelTempoEndpoint"))?;
+        (String::new(), String::new(), endpoint)
+    } else {
+        (
+            env.required("otel_username", "OtelTempoUserName"),
+            env.secret("otel_password", "OtelTempoPassword"),
+            endpoint.ok_or(TelemetryError::MissingSetting("OtelTempoEndpoint"))?,
+        )
+    };
+    let export_tls = ExportTls {
+        ca_file: env
+            .first(
+                "tls_ca_file",
+                &[
+                    "OTEL_EXPORTER_OTLP_TRACES_CERTIFICATE",
+                    "OTEL_EXPORTER_OTLP_CERTIFICATE",
+                ],
+                false,
+            )
+            .map(|(_, path)| PathBuf::from(path)),
+        client_cert_file: env
+            .first(
+                "tls_client_cert_file",
+                &[
+                    "OTEL_EXPORTER_OTLP_TRACES_CLIENT_CERTIFICATE",
+                    "OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE",
+                ],
+                false,
+            )
+            .map(|(_, path)| PathBuf::from(path)),
+        client_key_file: env
+            .first(
+                "tls_client_key_file",
+                &[
+                    "OTEL_EXPORTER_OTLP_TRACES_CLIENT_KEY",
+                    "OTEL_EXPORTER_OTLP_CLIENT_KEY",
+                ],
+                false,
+            )
+            .map(|(_, path)| PathBuf::from(path)),
+    };
+    let credential_file = if let Some(path) = env.secret_files.remove("OtelTempoBearerToken") {
+        Some(CredentialFile::Bearer(path))
+    } else {
+        env.secret_files
+            .remove("OtelTempoPassword")
+            .map(|password_file| CredentialFile::Basic {
+                username: otel_username.clone(),
+                password_file,
+            })
+    };
+    let sampler_arg: Option<String> = env.parse("sampler_arg", "OTEL_TRACES_SAMPLER_ARG");
+
+    let settings = Settings {
+        mode,
+        span_file: env
+            .parse("span_file", "OtelTempoSpanFile")
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SPAN_FILE)),
+        span_file_max_bytes: env
+            .parse("span_file_max_bytes", "OtelTempoSpanFileMaxBytes")
+            .unwrap_or(DEFAULT_SPAN_FILE_MAX_BYTES),
+        span_file_max_files: env
+            .parse("span_file_max_files", "OtelTempoSpanFileMaxFiles")
+            .unwrap_or(DEFAULT_SPAN_FILE_MAX_FILES),
+        otel_username,
+        otel_password,
+        bearer_token,
+        credential_file,
+        oauth2,
+        export_tls,
+        otel_endpoint,
+        export_headers,
+        local_collector,
+        bind_address: env
+            .parse("bind_address", "OtelTempoBindAddress")
+            .unwrap_or(DEFAULT_BIND_ADDRESS),
+        span_limits: env
+            .parse("span_limits", "OtelTempoSpanLimits")
+            .unwrap_or(SpanLimitsPreset::Default),
+        attribute_value_length_limit: env.parse_with(
+            "attribute_value_length_limit",
+            "OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT",
+            parse_limit,
+        ),
+        attribute_count_limit: env.parse_with(
+            "attribute_count_limit",
+            "OTEL_ATTRIBUTE_COUNT_LIMIT",
+            parse_limit,
+        ),
+        local_address: env.parse("local_address", "OtelTempoLocalAddress"),
+        user_agent: env
+            .parse("user_agent", "OtelTempoUserAgent")
+            .unwrap_or_else(|| String::from(DEFAULT_USER_AGENT)),
+        export_thread: env
+            .parse("export_thread", "OtelTempoExportThread")
+            .unwrap_or(false),
+        recovery_buffer_spans: env
+            .parse("recovery_buffer_spans", "OtelTempoRecoveryBufferSpans")
+            .unwrap_or(0),
+        export_max_attempts: env
+            .parse_with(
+                "export_max_attempts",
+                "OtelTempoExportMaxAttempts",
+                parse_limit,
+            )
+            .unwrap_or(1),
+        export_retry_backoff: env
+            .parse("export_retry_backoff_ms", "OtelTempoExportRetryBackoffMs")
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_EXPORT_RETRY_BACKOFF),
+        export_circuit_failures: env.parse_with(
+            "export_circuit_failures",
+            "OtelTempoExportCircuitFailures",
+            parse_limit,
+        ),
+        export_circuit_cooldown: env
+            .parse(
+                "export_circuit_cooldown_secs",
+                "OtelTempoExportCircuitCooldownSecs",
+            )
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_EXPORT_CIRCUIT_COOLDOWN),
+        spill_dir: env.parse("spill_dir", "OtelTempoSpillDir"),
+        spill_max_bytes: env
+            .parse_with("spill_max_bytes", "OtelTempoSpillMaxBytes", parse_limit)
+            .unwrap_or(DEFAULT_SPILL_MAX_BYTES),
+        batch_max_queue_size: env.parse_with(
+            "batch_max_queue_size",
+            "OtelTempoBatchMaxQueueSize",
+            parse_limit,
+        ),
+        batch_max_export_batch_size: env.parse_with(
+            "batch_max_export_batch_size",
+            "OtelTempoBatchMaxExportBatchSize",
+            parse_limit,
+        ),
+        batch_scheduled_delay: env
+            .parse("batch_scheduled_delay_ms", "OtelTempoBatchScheduledDelayMs")
+            .map(Duration::from_millis),
+        batch_export_timeout: env
+            .parse("batch_export_timeout_ms", "OtelTempoBatchExportTimeoutMs")
+            .map(Duration::from_millis),
+        export_protocol,
+        http_encoding: env
+            .parse("http_encoding", "OtelTempoHttpEncoding")
+            .unwrap_or_default(),
+        export_compression: env
+            .parse("export_compression", "OtelTempoExportCompression")
+            .unwrap_or_default(),
+        export_preflight: env
+            .parse("export_preflight", "OtelTempoExportPreflight")
+            .unwrap_or(false),
+        flush_interval: env
+            .parse("flush_interval_ms", "OtelTempoFlushIntervalMs")
+            .map(Duration::from_millis),
+        flush_every_requests: env.parse("flush_every_requests", "OtelTempoFlushEveryRequests"),
+        max_concurrent_requests: env
+            .parse("max_concurrent_requests", "OtelTempoMaxConcurrentRequests"),
+        request_span_fields: env
+            .parse("request_span_fields", "OtelTempoRequestSpanFields")
+            .unwrap_or_default(),
+        sampling_schedule: env.parse("sampling_schedule", "OtelTempoSamplingSchedule"),
+        sampler: env
+            .first(
+                "sampler",
+                &["OtelTempoSampler", "OTEL_TRACES_SAMPLER"],
+                false,
+            )
+            .map(|(var, value)| {
+                match var {
+                    "OtelTempoSampler" => sampling::parse_sampler(&value),
+                    _ => sampling::from_otel_env(&value, sampler_arg.as_deref()),
+                }
+                .map_err(|reason| TelemetryError::InvalidSetting { var, reason })
+            })
+            .transpose()?,
+        propagators: env
+            .first(
+                "propagators",
+                &["OtelTempoPropagators", "OTEL_PROPAGATORS"],
+                false,
+            )
+            .map(|(var, value)| {
+                value
+                    .parse()
+                    .map_err(|reason| TelemetryError::InvalidSetting { var, reason })
+            })
+            .transpose()?
+            .unwrap_or_default(),
+        route_sampling: env.parse("route_sampling", "OtelTempoRouteSampling"),
+        correlation_id: env
+            .parse("correlation_id", "OtelTempoCorrelationId")
+            .unwrap_or(false),
+        trace_id_headers: env
+            .parse("trace_id_headers", "OtelTempoTraceIdHeaders")
+            .unwrap_or(false),
+        links_header: env.parse("links_header", "OtelTempoLinksHeader"),
+        context_attributes: env
+            .parse_with("context_attributes", "OtelTempoContextAttributes", |s| {
+                resource::parse_key_values(s)?
+                    .into_iter()
+                    .map(|kv| {
+                        let header = HeaderName::from_str(kv.key.as_str())
+                            .map_err(|e| format!("{}: {e}", kv.key))?;
+                        Ok((header, kv.value.to_string()))
+                    })
+                    .collect::<Result<HashMap<_, _>, String>>()
+            })
+            .unwrap_or_default(),
+        trusted_proxies: env
+            .parse("trusted_proxies", "OtelTempoTrustedProxies")
+            .unwrap_or_default(),
+        capture_request_headers: env
+            .parse("capture_request_headers", "OtelTempoCaptureRequestHeaders")
+            .map(Reloadable::new)
+            .unwrap_or_default(),
+        capture_response_headers: env
+            .parse(
+                "capture_response_headers",
+                "OtelTempoCaptureResponseHeaders",
+            )
+            .map(Reloadable::new)
+            .unwrap_or_default(),
+        body_capture: env
+            .parse_with("capture_body_routes", "OtelTempoCaptureBodyRoutes", |s| {
+                Ok::<_, String>(parse_list(s))
+            })
+            .filter(|routes| !routes.is_empty())
+            .map(|routes| BodyCapture {
+                routes,
+                max_bytes: env
+                    .parse("capture_body_max_bytes", "OtelTempoCaptureBodyMaxBytes")
+                    .unwrap_or(DEFAULT_BODY_CAPTURE_MAX_BYTES),
+                content_types: env
+                    .parse_with(
+                        "capture_body_content_types",
+                        "OtelTempoCaptureBodyContentTypes",
+                        |s| Ok::<_, String>(parse_list(s)),
+                    )
+                    .unwrap_or_else(|| {
+                        DEFAULT_BODY_CAPTURE_CONTENT_TYPES
+                            .iter()
+                            .map(|s| String::from(*s))
+                            .collect()
+                    }),
+            })
+            .map(Reloadable::new),
+        baggage_attributes: env
+            .parse_with("baggage_attributes", "OtelTempoBaggageAttributes", |s| {
+                Ok::<_, String>(parse_list(s))
+            })
+            .unwrap_or_default(),
+        record_path_params: env
+            .parse_with("record_path_params", "OtelTempoRecordPathParams", |s| {
+                Ok::<_, String>(parse_list(s))
+            })
+            .unwrap_or_default(),
+        error_status_field: env
+            .parse_with("error_status_field", "OtelTempoErrorStatusField", |s| {
+                Ok::<_, String>(s.split('.').map(str::to_owned).collect())
+            })
+            .unwrap_or_default(),
+        tenant_attribute: env.parse("tenant_attribute", "OtelTempoTenantAttribute"),
+        tenant_org_ids: env
+            .parse_with("tenant_org_ids", "OtelTempoTenantOrgIds", |s| {
+                resource::parse_key_values(s).map(|pairs| {
+                    pairs
+                        .into_iter()
+                        .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+                        .collect()
+                })
+            })
+            .unwrap_or_default(),
+        secondary_endpoints: env
+            .parse_with("secondary_endpoints", "OtelTempoSecondaryEndpoints", |s| {
+                Ok::<_, String>(parse_list(s))
+            })
+            .unwrap_or_default(),
+        attribute_denylist: env
+            .parse_with("attribute_denylist", "OtelTempoAttributeDenylist", |s| {
+                Ok::<_, String>(parse_list(s))
+            })
+            .unwrap_or_default(),
+        redaction: env
+            .parse("redaction", "OtelTempoRedaction")
+            .unwrap_or(false)
+            .then(|| RedactionRules {
+                keys: env
+                    .parse_with("redact_keys", "OtelTempoRedactKeys", |s| {
+                        Ok::<_, String>(parse_list(s))
+                    })
+                    .unwrap_or_default(),
+                // Whitespace separated, since commas are common in regexes.
+                patterns: env
+                    .parse_with("redact_patterns", "OtelTempoRedactPatterns", |s| {
+                        s.split_whitespace()
+                            .map(str::parse)
+                            .collect::<Result<Vec<RedactPattern>, _>>()
+                    })
+                    .unwrap_or_else(RedactPattern::defaults),
+            }),
+        attribute_key_policy: env
+            .parse("attribute_key_policy", "OtelTempoAttributeKeyPolicy")
+            .unwrap_or(KeyPolicyMode::Off),
+        fail_open: env.parse("fail_open", "OtelTempoFailOpen").unwrap_or(false),
+        log_trace_flags: env
+            .parse("log_trace_flags", "OtelTempoLogTraceFlags")
+            .unwrap_or(false),
+        log_format: env
+            .parse("log_format", "OtelTempoLogFormat")
+            .unwrap_or_default(),
+        log_filter: env.parse_with("log_filter", "OtelTempoLogFilter", filter_directives),
+        export_filter: env.parse_with("export_filter", "OtelTempoExportFilter", filter_directives),
+        max_span_bytes: env.parse("max_span_bytes", "OtelTempoMaxSpanBytes"),
+        oversized_spans: env
+            .parse("oversized_spans", "OtelTempoOversizedSpans")
+            .unwrap_or(OversizedSpanMode::Truncate),
+        min_span_duration: env
+            .parse("min_span_duration_us", "OtelTempoMinSpanDurationUs")
+            .map(Duration::from_micros),
+        max_spans_per_second: env.parse_with(
+            "max_spans_per_second",
+            "OtelTempoMaxSpansPerSecond",
+            |value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|e| e.to_string())
+                    .and_then(sampling::parse_rate)
+            },
+        ),
+        tail_sampling_latency: env
+            .parse("tail_sampling_latency_ms", "OtelTempoTailSamplingLatencyMs")
+            .map(Duration::from_millis),
+        tail_sampling_window: env
+            .parse("tail_sampling_window_ms", "OtelTempoTailSamplingWindowMs")
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_TAIL_SAMPLING_WINDOW),
+        tail_sampling_max_spans: env
+            .parse("tail_sampling_max_spans", "OtelTempoTailSamplingMaxSpans")
+            .unwrap_or(DEFAULT_TAIL_SAMPLING_MAX_SPANS),
+        metrics: env.parse("metrics", "OtelTempoMetrics").unwrap_or(false),
+        logs: env.parse("logs", "OtelTempoLogs").unwrap_or(false),
+        logs_endpoint: env.parse("logs_endpoint", "OtelTempoLogsEndpoint"),
+        prometheus: env
+            .parse("prometheus", "OtelTempoPrometheus")
+            .unwrap_or(false),
+        admin_endpoints: env
+            .parse("admin_endpoints", "OtelTempoAdminEndpoints")
+            .unwrap_or(false),
+        health_endpoints: env
+            .parse("health_endpoints", "OtelTempoHealthEndpoints")
+            .unwrap_or(true),
+        metrics_endpoint: env.parse("metrics_endpoint", "OtelTempoMetricsEndpoint"),
+        metrics_interval: env
+            .parse("metrics_interval_secs", "OtelTempoMetricsIntervalSecs")
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_METRICS_INTERVAL),
+        heartbeat_interval: env
+            .parse("heartbeat_interval_secs", "OtelTempoHeartbeatIntervalSecs")
+            .map(Duration::from_secs),
+        status_log_interval: env
+            .parse("status_log_interval_secs", "OtelTempoStatusLogIntervalSecs")
+            .map(Duration::from_secs),
+        shutdown_timeout: env
+            .parse("shutdown_timeout_secs", "OtelTempoShutdownTimeoutSecs")
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+        service_name: env.parse("service_name", "OTEL_SERVICE_NAME"),
+        service_namespace: env.parse("service_namespace", "OtelTempoServiceNamespace"),
+        service_instance_id: env.parse("service_instance_id", "OtelTempoServiceInstanceId"),
+        service_version: env.parse("service_version", "OtelTempoServiceVersion"),
+        deploy_event: env
+            .parse("deploy_event", "OtelTempoDeployEvent")
+            .unwrap_or(false),
+        environment: env.parse("environment", "OtelTempoEnvironment"),
+        require_service_identity: env
+            .parse("require_service_identity", "OtelTempoNoDefaults")
+            .unwrap_or(false),
+        cloud: CloudAttributes {
+            provider: env.parse("cloud_provider", "OtelTempoCloudProvider"),
+            region: env.parse("cloud_region", "OtelTempoCloudRegion"),
+            availability_zone: env
+                .parse("cloud_availability_zone", "OtelTempoCloudAvailabilityZone"),
+        },
+        resource_attributes: env
+            .parse_with(
+                "resource_attributes",
+                "OTEL_RESOURCE_ATTRIBUTES",
+                resource::parse_resource_attributes,
+            )
+            .unwrap_or_default(),
+        resource_detectors: env
+            .parse_with(
+                "resource_detectors",
+                "OtelTempoResourceDetectors",
+                resource::parse_detectors,
+            )
+            .unwrap_or_else(|| Detector::DEFAULT.to_vec()),
+        resource_precedence: env
+            .parse("resource_precedence", "OtelTempoResourcePrecedence")
+            .unwrap_or_default(),
+        signal_resources: [
+            (
+                Signal::Traces,
+                "traces_resource_attributes",
+                "OtelTempoTracesResourceAttributes",
+            ),
+            (
+                Signal::Metrics,
+                "metrics_resource_attributes",
+                "OtelTempoMetricsResourceAttributes",
+            ),
+            (
+                Signal::Logs,
+                "logs_resource_attributes",
+                "OtelTempoLogsResourceAttributes",
+            ),
+        ]
+        .into_iter()
+        .fold(
+            SignalResources::default(),
+            |resources, (signal, name, var)| match env.parse_with(
+                name,
+                var,
+                resource::parse_key_values,
+            ) {
+                Some(attributes) => resources.with_override(signal, attributes),
+                None => resources,
+            },
+        ),
+        resolution: env.resolved,
+    };
+
+    match env.error {
+        Some(e) => Err(e),
+        None => Ok(settings),
+    }
+}
+
+/// Parses a limit, which must be a positive integer.
+fn parse_limit<T>(s: &str) -> Result<T, String>
+where
+    T: FromStr + Default + PartialEq,
+    T::Err: Display,
+{
+    match s.parse::<T>() {
+        Ok(limit) if limit == T::default() => Err(String::from("must be greater than zero")),
+        Ok(limit) => Ok(limit),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses the `key=value` list of `OTEL_EXPORTER_OTLP_HEADERS`, whose values
+/// are percent encoded.
+fn parse_otlp_headers(s: &str) -> Result<HashMap<String, String>, String> {
+    resource::parse_key_values(s)?
+        .into_iter()
+        .map(|kv| {
+            Ok((
+                kv.key.to_string(),
+                resource::percent_decode(kv.value.as_str().as_ref())?,
+            ))
+        })
+        .collect()
+}
+
+fn filter_directives(directives: &str) -> Result<String, ParseError> {
+    EnvFilter::try_new(directives).map(|_| directives.to_owned())
+}
+
+/// Splits a comma separated list, skipping empty entries.
+fn parse_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The variable naming a file to read the secret setting `var` from, for
+/// Docker and Kubernetes secrets mounted as files.
+fn secret_file_var(var: &str) -> Option<&'static str> {
+    match var {
+        "OtelTempoPassword" => Some("OtelTempoPassword_FILE"),
+        "OtelTempoBearerToken" => Some("OtelTempoBearerToken_FILE"),
+        "OtelTempoOAuth2ClientSecret" => Some("OtelTempoOAuth2ClientSecret_FILE"),
+        _ => None,
+    }
+}
+
+/// Reads settings from the environment, falling back to the config file, and
+/// remembers where each one came from. Missing or invalid settings read as
+/// unset, and the first of them is kept in `error`.
+#[derive(Default)]
+struct EnvReader {
+    file: FileConfig,
+    resolved: Vec<ResolvedSetting>,
+    error: Option<TelemetryError>,
+    /// Secret files read so far, keyed by the setting they stand in for.
+    secret_files: HashMap<&'static str, PathBuf>,
+}
+
+impl EnvReader {
+    fn with_file(file: FileConfig) -> Self {
+        Self {
+            file,
+            ..Self::default()
+        }
+    }
+
+    fn fail(&mut self, error: TelemetryError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn lookup(&mut self, var: &'static str) -> Option<(String, SettingSource)> {
+        if let Ok(value) = env::var(var) {
+            return Some((value, SettingSource::Env(var)));
+        }
+        if let Some(file_var) = secret_file_var(var) {
+            if let Some((path, _)) = self.lookup(file_var) {
+                let path = PathBuf::from(path);
+                return match secrets::read_secret_file(&path) {
+                    Ok(value) => {
+                        self.secret_files.insert(var, path);
+                        Some((value, SettingSource::SecretFile(file_var)))
+                    }
+                    Err(reason) => {
+                        self.fail(TelemetryError::InvalidSetting {
+                            var: file_var,
+                            reason,
+                        });
+                        None
+                    }
+                };
+            }
+        }
+        self.file
+            .value_for(var)
+            .map(|value| (value, SettingSource::File))
+    }
+
+    /// The value of the first of `vars` that is set, and which one it was.
+    fn first(
+        &mut self,
+        name: &'static str,
+        vars: &[&'static str],
+        secret: bool,
+    ) -> Option<(&'static str, String)> {
+        for &var in vars {
+            if let Some((value, source)) = self.lookup(var) {
+                let shown = if secret {
+                    String::from("<redacted>")
+                } else {
+                    value.clone()
+                };
+                self.record(name, source, shown);
+                return Some((var, value));
+            }
+        }
+        self.record(name, SettingSource::Default, String::from("<default>"));
+        None
+    }
+
+    fn required(&mut self, name: &'static str, var: &'static str) -> String {
+        match self.lookup(var) {
+            Some((value, source)) => {
+                self.record(name, source, value.clone());
+                value
+            }
+            None => {
+                self.fail(TelemetryError::MissingSetting(var));
+                String::new()
+            }
+        }
+    }
+
+    fn secret(&mut self, name: &'static str, var: &'static str) -> String {
+        match self.lookup(var) {
+            Some((value, source)) => {
+                self.record(name, source, String::from("<redacted>"));
+                value
+            }
+            None => {
+                self.fail(TelemetryError::MissingSetting(var));
+                String::new()
+            }
+        }
+    }
+
+    fn parse<T>(&mut self, name: &'static str, var: &'static str) -> Option<T>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        self.parse_with(name, var, str::parse)
+    }
+
+    fn parse_with<T, E, F>(&mut self, name: &'static str, var: &'static str, f: F) -> Option<T>
+    where
+        T: Debug,
+        E: Display,
+        F: FnOnce(&str) -> Result<T, E>,
+    {
+        match self.lookup(var) {
+            Some((value, source)) => match f(&value) {
+                Ok(parsed) => {
+                    self.record(name, source, format!("{parsed:?}"));
+                    Some(parsed)
+                }
+                Err(e) => {
+                    self.fail(TelemetryError::InvalidSetting {
+                        var,
+                        reason: e.to_string(),
+                    });
+                    None
+                }
+            },
+            None => {
+                self.record(name, SettingSource::Default, String::from("<default>"));
+                None
+            }
+        }
+    }
+
+    fn record(&mut self, name: &'static str, source: SettingSource, value: String) {
+        self.resolved.push(ResolvedSetting {
+            name,
+            source,
+            value,
+        });
+    }
+}
+
+/// Settings read from the config file. Each one stands in for the environment
+/// variable noted on it, and that variable wins when both are set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// `OtelTempoEndpoint`.
+    pub endpoint: Option<String>,
+    /// `OtelTempoUserName`.
+    pub username: Option<String>,
+    /// `OtelTempoPassword`.
+    pub password: Option<String>,
+    /// `OtelTempoPassword_FILE`.
+    pub password_file: Option<String>,
+    /// `OtelTempoBearerToken`.
+    pub bearer_token: Option<String>,
+    /// `OtelTempoBearerToken_FILE`.
+    pub bearer_token_file: Option<String>,
+    /// `OtelTempoSampler`, e.g. `"parentbased_traceidratio:0.25"`.
+    pub sampler: Option<String>,
+    /// `OtelTempoSamplingSchedule`, e.g. `"08:00-18:00=1.0,*=0.1"`.
+    pub sampling_schedule: Option<String>,
+    /// `OtelTempoRouteSampling`, as a table of route pattern to ratio.
+    pub route_sampling: Option<BTreeMap<String, f64>>,
+    /// `OtelTempoHttpEncoding`: `protobuf` or `json`.
+    pub http_encoding: Option<String>,
+    /// `OtelTempoExportCompression`: `none` or `gzip`.
+    pub export_compression: Option<String>,
+    /// `OtelTempoExportPreflight`.
+    pub export_preflight: Option<bool>,
+    /// `OTEL_SERVICE_NAME`.
+    pub service_name: Option<String>,
+    /// `OtelTempoServiceNamespace`.
+    pub service_namespace: Option<String>,
+    /// `OtelTempoEnvironment`.
+    pub environment: Option<String>,
+    /// `OtelTempoBindAddress`.
+    pub bind_address: Option<SocketAddr>,
+    /// The `OtelTempoRedact*` settings.
+    pub redaction: Option<RedactionConfig>,
+    /// The `[resource]` table.
+    pub resource: Option<ResourceConfig>,
+    /// `OtelTempoLogFilter`.
+    pub log_filter: Option<String>,
+    /// `OtelTempoCaptureRequestHeaders`.
+    pub capture_request_headers: Option<Vec<String>>,
+    /// `OtelTempoCaptureResponseHeaders`.
+    pub capture_response_headers: Option<Vec<String>>,
+    /// `OtelTempoCaptureBodyRoutes`.
+    pub capture_body_routes: Option<Vec<String>>,
+}
+
+/// The `[redaction]` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionConfig {
+    /// `OtelTempoRedaction`.
+    #[serde(default)]
+    pub enabled: bool,
+    /// `OtelTempoRedactKeys`.
+    pub keys: Option<Vec<String>>,
+    /// `OtelTempoRedactPatterns`: `email`, `card` or regular expressions.
+    pub patterns: Option<Vec<String>>,
+}
+
+/// The `[resource]` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceConfig {
+    /// `OtelTempoResourceDetectors`.
+    pub detectors: Option<Vec<String>>,
+}
+
+impl FileConfig {
+    /// Reads `path`. A missing file is only an error when `required`.
+    pub fn load(path: &Path, required: bool) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// The value standing in for environment variable `var`.
+    pub fn value_for(&self, var: &str) -> Option<String> {
+        match var {
+            "OtelTempoEndpoint" => self.endpoint.clone(),
+            "OtelTempoUserName" => self.username.clone(),
+            "OtelTempoPassword" => self.password.clone(),
+            "OtelTempoPassword_FILE" => self.password_file.clone(),
+            "OtelTempoBearerToken" => self.bearer_token.clone(),
+            "OtelTempoBearerToken_FILE" => self.bearer_token_file.clone(),
+            "OtelTempoSampler" => self.sampler.clone(),
+            "OtelTempoSamplingSchedule" => self.sampling_schedule.clone(),
+            "OtelTempoRouteSampling" => self.route_sampling.as_ref().map(|routes| {
+                routes
+                    .iter()
+                    .map(|(route, ratio)| format!("{route}={ratio}"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            "OtelTempoHttpEncoding" => self.http_encoding.clone(),
+            "OtelTempoExportCompression" => self.export_compression.clone(),
+            "OtelTempoExportPreflight" => self.export_preflight.map(|p| p.to_string()),
+            "OTEL_SERVICE_NAME" => self.service_name.clone(),
+            "OtelTempoServiceNamespace" => self.service_namespace.clone(),
+            "OtelTempoEnvironment" => self.environment.clone(),
+            "OtelTempoBindAddress" => self.bind_address.map(|addr| addr.to_string()),
+            "OtelTempoRedaction" => self.redaction.as_ref().map(|r| r.enabled.to_string()),
+            "OtelTempoRedactKeys" => self
+                .redaction
+                .as_ref()
+                .and_then(|r| r.keys.as_ref())
+                .map(|keys| keys.join(",")),
+            "OtelTempoRedactPatterns" => self
+                .redaction
+                .as_ref()
+                .and_then(|r| r.patterns.as_ref())
+                .map(|patterns| patterns.join(" ")),
+            "OtelTempoResourceDetectors" => self
+                .resource
+                .as_ref()
+                .and_then(|r| r.detectors.as_ref())
+                .map(|detectors| detectors.join(",")),
+            "OtelTempoLogFilter" => self.log_filter.clone(),
+            "OtelTempoCaptureRequestHeaders" => {
+                self.capture_request_headers.as_ref().map(|h| h.join(","))
+            }
+            "OtelTempoCaptureResponseHeaders" => {
+                self.capture_response_headers.as_ref().map(|h| h.join(","))
+            }
+            "OtelTempoCaptureBodyRoutes" => self.capture_body_routes.as_ref().map(|r| r.join(",")),
+            _ => None,
+        }
+    }
+}
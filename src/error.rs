@@ -0,0 +1,74 @@
+use opentelemetry::{logs::LogError, metrics::MetricsError, trace::TraceError};
+use std::{error::Error, fmt, path::PathBuf};
+
+/// Why telemetry could not be set up.
+#[derive(Debug)]
+pub enum TelemetryError {
+    /// A required setting is not set. Holds the environment variable.
+    MissingSetting(&'static str),
+    /// A setting has a value that cannot be used.
+    InvalidSetting { var: &'static str, reason: String },
+    /// The config file could not be read or parsed.
+    ConfigFile { path: String, reason: String },
+    /// A TLS certificate or key file could not be read or parsed.
+    TlsFile { path: PathBuf, reason: String },
+    /// The export endpoint is not an http or https URL.
+    InvalidEndpoint(String),
+    /// The HTTP client used for exports could not be built.
+    ExportClient(reqwest::Error),
+    /// The span exporter could not be built.
+    Exporter(TraceError),
+    /// The metric exporter could not be built.
+    MetricExporter(MetricsError),
+    /// The log exporter could not be built.
+    LogExporter(LogError),
+    /// Another global tracing subscriber was installed first.
+    SubscriberAlreadySet,
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::MissingSetting(var) => write!(f, "{var} not set"),
+            TelemetryError::InvalidSetting { var, reason } => {
+                write!(f, "{var} is not valid: {reason}")
+            }
+            TelemetryError::ConfigFile { path, reason } => {
+                write!(f, "config file {path} is not valid: {reason}")
+            }
+            TelemetryError::TlsFile { path, reason } => {
+                write!(f, "TLS file {} is not valid: {reason}", path.display())
+            }
+            TelemetryError::InvalidEndpoint(endpoint) => {
+                write!(f, "export endpoint {endpoint} is not an http or https URL")
+            }
+            TelemetryError::ExportClient(e) => write!(f, "failed to build export client: {e}"),
+            TelemetryError::Exporter(e) => write!(f, "failed to build span exporter: {e}"),
+            TelemetryError::MetricExporter(e) => {
+                write!(f, "failed to build metric exporter: {e}")
+            }
+            TelemetryError::LogExporter(e) => write!(f, "failed to build log exporter: {e}"),
+            TelemetryError::SubscriberAlreadySet => {
+                f.write_str("a global tracing subscriber is already set")
+            }
+        }
+    }
+}
+
+impl Error for TelemetryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TelemetryError::ExportClient(e) => Some(e),
+            TelemetryError::Exporter(e) => Some(e),
+            TelemetryError::MetricExporter(e) => Some(e),
+            TelemetryError::LogExporter(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TraceError> for TelemetryError {
+    fn from(e: TraceError) -> Self {
+        TelemetryError::Exporter(e)
+    }
+}
@@ -0,0 +1,1211 @@
+use async_trait::async_trait;
+use axum::http::{uri::PathAndQuery, HeaderMap, Uri};
+use base64::{engine::general_purpose, Engine};
+use flate2::{write::GzEncoder, Compression};
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        metrics::{reader::DefaultTemporalitySelector, PeriodicReader},
+        trace::{self, BatchConfig, BatchSpanProcessor},
+    },
+    trace::{TraceError, TraceResult},
+    Key,
+};
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+use opentelemetry_otlp::{
+    LogExporter, LogExporterBuilder, MetricsExporterBuilder, SpanExporterBuilder, WithExportConfig,
+};
+use opentelemetry_proto::tonic::collector::{
+    logs::v1::ExportLogsServiceResponse,
+    metrics::v1::ExportMetricsServiceResponse,
+    trace::v1::{ExportTraceServiceRequest, ExportTraceServiceResponse},
+};
+use prost::Message;
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fmt,
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tonic::{
+    codec::ProstCodec,
+    metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap},
+    service::Interceptor,
+    transport::{Channel, Endpoint},
+    Status,
+};
+
+use crate::clock;
+use crate::config::Settings;
+use crate::error::TelemetryError;
+use crate::metrics;
+use crate::oauth;
+use crate::otlp_json;
+use crate::resource::Signal;
+use crate::secrets::FileCredentials;
+use crate::spill::DiskSpill;
+use crate::status::{CountingExporter, Destination, QueueCounter};
+
+/// Builds the reqwest client used for export. Use it for the application's
+/// own calls to the same backend to get identical network configuration, and
+/// only for those: it presents the export client certificate, when one is
+/// configured, to every server it connects to.
+pub fn build_export_client(settings: &Settings) -> Result<reqwest::Client, TelemetryError> {
+    let builder = reqwest::Client::builder()
+        .local_address(settings.local_address)
+        .user_agent(&settings.user_agent);
+    settings
+        .export_tls
+        .apply(builder)?
+        .build()
+        .map_err(TelemetryError::ExportClient)
+}
+
+/// Header Tempo uses to select the tenant in multi-tenant installations.
+pub const TEMPO_TENANT_HEADER: &str = "X-Scope-OrgID";
+
+/// Payload encoding for OTLP over HTTP.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpEncoding {
+    #[default]
+    Protobuf,
+    Json,
+}
+
+impl FromStr for HttpEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "protobuf" => Ok(HttpEncoding::Protobuf),
+            "json" => Ok(HttpEncoding::Json),
+            other => Err(format!("expected protobuf or json, got {other}")),
+        }
+    }
+}
+
+/// Compression of OTLP/HTTP request bodies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl FromStr for ExportCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ExportCompression::None),
+            "gzip" => Ok(ExportCompression::Gzip),
+            other => Err(format!("expected none or gzip, got {other}")),
+        }
+    }
+}
+
+/// Transport used to send OTLP to the backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportProtocol {
+    #[default]
+    Http,
+    Grpc,
+}
+
+impl FromStr for ExportProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http" => Ok(ExportProtocol::Http),
+            "grpc" => Ok(ExportProtocol::Grpc),
+            other => Err(format!("expected http or grpc, got {other}")),
+        }
+    }
+}
+
+/// Supplies headers that change over the exporter's lifetime, such as a
+/// refreshed access token. They are added to every export request.
+pub trait HeaderProvider: Send + Sync + fmt::Debug {
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)>;
+}
+
+/// Adds a [`HeaderProvider`]'s headers to OTLP/gRPC requests as metadata.
+#[derive(Clone)]
+pub struct HeaderInterceptor(pub Arc<dyn HeaderProvider>);
+
+impl Interceptor for HeaderInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        for (name, value) in self.0.headers() {
+            let name = AsciiMetadataKey::from_bytes(name.as_str().as_bytes())
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let value = AsciiMetadataValue::try_from(value.as_bytes())
+                .map_err(|e| Status::internal(e.to_string()))?;
+            request.metadata_mut().insert(name, value);
+        }
+        Ok(request)
+    }
+}
+
+/// The HTTP client handed to the OTLP exporter. Wraps the reqwest client so
+/// requests can be re-encoded and collector responses inspected instead of
+/// only checking the status.
+#[derive(Clone, Debug)]
+pub struct ExportClient {
+    inner: reqwest::Client,
+    encoding: HttpEncoding,
+    signal: Signal,
+    compression: ExportCompression,
+    header_provider: Option<Arc<dyn HeaderProvider>>,
+}
+
+impl ExportClient {
+    pub fn new(inner: reqwest::Client, encoding: HttpEncoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            signal: Signal::Traces,
+            compression: ExportCompression::None,
+            header_provider: None,
+        }
+    }
+
+    /// The signal this client exports, used to read the collector's partial
+    /// success responses. Defaults to traces.
+    pub fn with_signal(mut self, signal: Signal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: ExportCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_header_provider(mut self, provider: Option<Arc<dyn HeaderProvider>>) -> Self {
+        self.header_provider = provider;
+        self
+    }
+}
+
+#[async_trait]
+impl HttpClient for ExportClient {
+    async fn send(&self, mut request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        // The OTLP exporter always encodes protobuf; transcode it when JSON was asked for.
+        if self.encoding == HttpEncoding::Json {
+            let decoded = ExportTraceServiceRequest::decode(request.body().as_slice())?;
+            *request.body_mut() = otlp_json::encode_request(&decoded);
+            request
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+        if self.compression == ExportCompression::Gzip {
+            *request.body_mut() = gzip(request.body());
+            request
+                .headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+        if let Some(provider) = &self.header_provider {
+            request.headers_mut().extend(provider.headers());
+        }
+
+        let response = self.inner.send(request).await?;
+
+        if response.status().is_success() {
+            log_partial_success(self.signal, self.encoding, response.body());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Gzips `data` at the default level.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a `Vec` cannot fail.
+    encoder.write_all(data).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+/// Collectors report items they accepted the request for but still dropped in
+/// the `partial_success` field of an otherwise successful response. Each
+/// signal has its own response message and name for the rejected count.
+fn log_partial_success(signal: Signal, encoding: HttpEncoding, body: &[u8]) {
+    let (rejected, message) = match encoding {
+        HttpEncoding::Protobuf => {
+            let partial = match signal {
+                Signal::Traces => ExportTraceServiceResponse::decode(body)
+                    .ok()
+                    .and_then(|response| response.partial_success)
+                    .map(|partial| (partial.rejected_spans, partial.error_message)),
+                Signal::Metrics => ExportMetricsServiceResponse::decode(body)
+                    .ok()
+                    .and_then(|response| response.partial_success)
+                    .map(|partial| (partial.rejected_data_points, partial.error_message)),
+                Signal::Logs => ExportLogsServiceResponse::decode(body)
+                    .ok()
+                    .and_then(|response| response.partial_success)
+                    .map(|partial| (partial.rejected_log_records, partial.error_message)),
+            };
+            let Some(partial) = partial else {
+                return;
+            };
+            partial
+        }
+        HttpEncoding::Json => {
+            let Ok(response) = serde_json::from_slice::<serde_json::Value>(body) else {
+                return;
+            };
+            let partial = &response["partialSuccess"];
+            let key = match signal {
+                Signal::Traces => "rejectedSpans",
+                Signal::Metrics => "rejectedDataPoints",
+                Signal::Logs => "rejectedLogRecords",
+            };
+            // int64 fields may arrive as JSON numbers or strings.
+            let rejected = match &partial[key] {
+                serde_json::Value::Number(n) => n.as_i64().unwrap_or_default(),
+                serde_json::Value::String(s) => s.parse().unwrap_or_default(),
+                _ => 0,
+            };
+            let message = partial["errorMessage"].as_str().unwrap_or_default();
+            (rejected, message.to_owned())
+        }
+    };
+
+    if rejected > 0 || !message.is_empty() {
+        match signal {
+            Signal::Traces => tracing::warn!(
+                rejected_spans = rejected,
+                message = %message,
+                "Collector partially rejected exported spans"
+            ),
+            Signal::Metrics => tracing::warn!(
+                rejected_data_points = rejected,
+                message = %message,
+                "Collector partially rejected exported metric data points"
+            ),
+            Signal::Logs => tracing::warn!(
+                rejected_log_records = rejected,
+                message = %message,
+                "Collector partially rejected exported log records"
+            ),
+        }
+    }
+}
+
+/// Maps a span's tenant attribute value to the Tempo org id it is exported to.
+pub type TenantRouter = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Routes only the tenants listed in `org_ids`. The attribute value can come
+/// from the request, so it is never used as an org id itself.
+pub fn org_id_router(org_ids: HashMap<String, String>) -> TenantRouter {
+    Arc::new(move |tenant| org_ids.get(tenant).cloned())
+}
+
+/// Most org ids a [`TenantRoutingExporter`] keeps an exporter for.
+pub const MAX_TENANT_EXPORTERS: usize = 64;
+
+/// Builds an exporter that sends to the given org id, or to the default
+/// tenant when `None`.
+pub type ExporterFactory =
+    Box<dyn Fn(Option<&str>) -> TraceResult<Box<dyn SpanExporter>> + Send + Sync>;
+
+/// Splits each batch by the tenant attribute of its spans and exports every
+/// group through an exporter carrying that tenant's `X-Scope-OrgID`.
+///
+/// Exporters are created on first use and reused for later batches. Spans
+/// without the attribute, or whose tenant the router does not map, go to the
+/// default tenant. Once [`MAX_TENANT_EXPORTERS`] org ids have an exporter,
+/// spans routed to any further org id fail to export rather than grow the
+/// map.
+pub struct TenantRoutingExporter {
+    tenant_key: Key,
+    router: TenantRouter,
+    factory: ExporterFactory,
+    exporters: HashMap<Option<String>, Box<dyn SpanExporter>>,
+}
+
+impl TenantRoutingExporter {
+    pub fn new(tenant_key: impl Into<Key>, router: TenantRouter, factory: ExporterFactory) -> Self {
+        Self {
+            tenant_key: tenant_key.into(),
+            router,
+            factory,
+            exporters: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for TenantRoutingExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantRoutingExporter")
+            .field("tenant_key", &self.tenant_key)
+            .field("tenants", &self.exporters.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl SpanExporter for TenantRoutingExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let mut groups: HashMap<Option<String>, Vec<SpanData>> = HashMap::new();
+        for span in batch {
+            let org_id = span
+                .attributes
+                .get(&self.tenant_key)
+                .and_then(|tenant| (self.router)(&tenant.as_str()));
+            groups.entry(org_id).or_default().push(span);
+        }
+
+        let mut exports = Vec::with_capacity(groups.len());
+        for (org_id, spans) in groups {
+            let tenants = self.exporters.keys().filter(|key| key.is_some()).count();
+            let exporter = match self.exporters.entry(org_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry)
+                    if entry.key().is_some() && tenants >= MAX_TENANT_EXPORTERS =>
+                {
+                    let e = TraceError::from(format!(
+                        "more than {MAX_TENANT_EXPORTERS} tenants, dropping spans for {}",
+                        entry.key().as_deref().unwrap_or_default()
+                    ));
+                    exports.push(Box::pin(future::ready(Err(e))) as BoxFuture<_>);
+                    continue;
+                }
+                Entry::Vacant(entry) => match (self.factory)(entry.key().as_deref()) {
+                    Ok(exporter) => entry.insert(exporter),
+                    Err(e) => {
+                        exports.push(Box::pin(future::ready(Err(e))) as BoxFuture<_>);
+                        continue;
+                    }
+                },
+            };
+            exports.push(exporter.export(spans));
+        }
+
+        Box::pin(async move { future::join_all(exports).await.into_iter().collect() })
+    }
+
+    fn shutdown(&mut self) {
+        for exporter in self.exporters.values_mut() {
+            exporter.shutdown();
+        }
+    }
+}
+
+/// Longest wait between two attempts of a [`RetryingExporter`].
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+static EXPORT_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of export attempts retried by a [`RetryingExporter`] since startup.
+pub fn export_retries() -> u64 {
+    EXPORT_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Retries a failed export until `max_attempts` have been made, waiting
+/// `initial_backoff` before the first retry and twice as long before each
+/// next one, up to [`MAX_RETRY_BACKOFF`]. Each wait is jittered between half
+/// and all of it, so instances failing together do not retry in step. The
+/// batch processor's export timeout bounds all attempts together.
+#[derive(Debug)]
+pub struct RetryingExporter<E> {
+    inner: Arc<Mutex<E>>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl<E> RetryingExporter<E> {
+    pub fn new(inner: E, max_attempts: u32, initial_backoff: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            max_attempts,
+            initial_backoff,
+        }
+    }
+}
+
+impl<E: SpanExporter + 'static> SpanExporter for RetryingExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if self.max_attempts <= 1 {
+            return self.inner.lock().unwrap().export(batch);
+        }
+
+        let export = self.inner.lock().unwrap().export(batch.clone());
+        let inner = self.inner.clone();
+        let max_attempts = self.max_attempts;
+        let mut backoff = self.initial_backoff;
+
+        Box::pin(async move {
+            let mut result = export.await;
+            for attempt in 2..=max_attempts {
+                let Err(e) = &result else {
+                    break;
+                };
+                let wait = backoff.mul_f64(rand::random::<f64>() / 2.0 + 0.5);
+                tracing::debug!(attempt, "Span export failed, retrying in {wait:?}: {e}");
+                tokio::time::sleep(wait).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+
+                EXPORT_RETRIES.fetch_add(1, Ordering::Relaxed);
+                let export = inner.lock().unwrap().export(batch.clone());
+                result = export.await;
+            }
+            if let Err(e) = &result {
+                tracing::warn!(
+                    spans = batch.len(),
+                    attempts = max_attempts,
+                    "Span export failed on every attempt: {e}"
+                );
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.lock().unwrap().shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.lock().unwrap().force_flush()
+    }
+}
+
+static CIRCUIT_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether a [`CircuitBreaker`] is currently holding exports back.
+pub fn export_circuit_open() -> bool {
+    CIRCUIT_OPEN.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+enum CircuitState {
+    Closed { failures: u32 },
+    Open { until: SystemTime },
+    HalfOpen,
+}
+
+/// Stops exporting for `cooldown` after `max_failures` exports in a row
+/// failed, failing batches at once instead so a dead endpoint adds neither
+/// latency nor a connection error per batch. After the cooldown a single
+/// trial export is let through: success closes the circuit, failure opens it
+/// for another cooldown. Wrap it in a [`RecoveryBuffer`] or disk spill to
+/// keep the spans of the batches it turns away.
+#[derive(Debug)]
+pub struct CircuitBreaker<E> {
+    inner: E,
+    max_failures: u32,
+    cooldown: Duration,
+    state: Arc<Mutex<CircuitState>>,
+}
+
+impl<E> CircuitBreaker<E> {
+    pub fn new(inner: E, max_failures: u32, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            max_failures,
+            cooldown,
+            state: Arc::new(Mutex::new(CircuitState::Closed { failures: 0 })),
+        }
+    }
+
+    /// Whether the next export may go through, moving an open circuit whose
+    /// cooldown has passed to half-open.
+    fn admit(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            CircuitState::Closed { .. } => true,
+            CircuitState::Open { until } if clock::now() >= until => {
+                *state = CircuitState::HalfOpen;
+                true
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen => false,
+        }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for CircuitBreaker<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if !self.admit() {
+            return Box::pin(future::ready(Err(TraceError::Other(Box::new(CircuitOpen)))));
+        }
+
+        let export = self.inner.export(batch);
+        let state = self.state.clone();
+        let max_failures = self.max_failures;
+        let cooldown = self.cooldown;
+
+        Box::pin(async move {
+            let result = export.await;
+            let mut state = state.lock().unwrap();
+            match (&result, &*state) {
+                (Ok(()), CircuitState::HalfOpen) => {
+                    tracing::info!("Span export recovered, closing the circuit");
+                    *state = CircuitState::Closed { failures: 0 };
+                    CIRCUIT_OPEN.store(false, Ordering::Relaxed);
+                }
+                (Ok(()), _) => *state = CircuitState::Closed { failures: 0 },
+                (Err(_), CircuitState::Closed { failures }) if failures + 1 < max_failures => {
+                    *state = CircuitState::Closed {
+                        failures: failures + 1,
+                    };
+                }
+                (Err(e), _) => {
+                    if !export_circuit_open() {
+                        tracing::warn!(
+                            "Span export failed {max_failures} times in a row, pausing exports for {cooldown:?}: {e}"
+                        );
+                    }
+                    *state = CircuitState::Open {
+                        until: clock::now() + cooldown,
+                    };
+                    CIRCUIT_OPEN.store(true, Ordering::Relaxed);
+                }
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
+
+/// The error of an export turned away by an open [`CircuitBreaker`].
+#[derive(Debug)]
+pub struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("span export circuit is open, skipping export")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+static RECOVERY_EVICTED_SPANS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of spans evicted from a full [`RecoveryBuffer`] since startup.
+pub fn recovery_evicted_spans() -> u64 {
+    RECOVERY_EVICTED_SPANS.load(Ordering::Relaxed)
+}
+
+/// Keeps the spans of failed exports, up to `capacity`, and sends them again
+/// with the next batch, so a brief backend outage does not lose every trace.
+/// When full, the oldest spans are evicted first. A capacity of zero keeps
+/// nothing and passes batches straight through.
+#[derive(Debug)]
+pub struct RecoveryBuffer<E> {
+    inner: E,
+    capacity: usize,
+    pending: Arc<Mutex<VecDeque<SpanData>>>,
+}
+
+impl<E> RecoveryBuffer<E> {
+    pub fn new(inner: E, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            pending: Arc::default(),
+        }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for RecoveryBuffer<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if self.capacity == 0 {
+            return self.inner.export(batch);
+        }
+
+        let mut spans: Vec<_> = self.pending.lock().unwrap().drain(..).collect();
+        spans.extend(batch);
+        let export = self.inner.export(spans.clone());
+        let pending = self.pending.clone();
+        let capacity = self.capacity;
+
+        Box::pin(async move {
+            let result = export.await;
+            if result.is_err() {
+                let mut pending = pending.lock().unwrap();
+                // Spans of exports that failed meanwhile are newer than these.
+                for span in spans.into_iter().rev() {
+                    pending.push_front(span);
+                }
+                let evicted = pending.len().saturating_sub(capacity);
+                pending.drain(..evicted);
+                RECOVERY_EVICTED_SPANS.fetch_add(evicted as u64, Ordering::Relaxed);
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        let lost = self.pending.lock().unwrap().len();
+        if lost > 0 {
+            tracing::warn!(
+                lost,
+                "Spans kept for retry were not exported before shutdown"
+            );
+        }
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
+
+/// Converts export headers to gRPC metadata, which requires lowercase keys.
+fn grpc_metadata(headers: &HashMap<String, String>) -> Result<MetadataMap, TraceError> {
+    let mut metadata = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes())
+            .map_err(|e| TraceError::from(format!("invalid export header {name}: {e}")))?;
+        let value = HeaderValue::from_str(value).map_err(|e| {
+            TraceError::from(format!("invalid value for export header {name}: {e}"))
+        })?;
+        metadata.insert(name, value);
+    }
+    Ok(MetadataMap::from_headers(metadata))
+}
+
+pub(crate) fn has_authorization(headers: &HashMap<String, String>) -> bool {
+    headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("authorization"))
+}
+
+/// Exports on the application's runtime, or on a dedicated thread running its
+/// own runtime so a slow exporter cannot take time from request handling.
+pub(crate) fn batch_processor<E: SpanExporter + 'static>(
+    exporter: E,
+    settings: &Settings,
+) -> Box<dyn trace::SpanProcessor> {
+    let exporter = CountingExporter::new(
+        RetryingExporter::new(
+            exporter,
+            settings.export_max_attempts,
+            settings.export_retry_backoff,
+        ),
+        Destination::Primary,
+    );
+    let spill_dir = settings.spill_dir.clone();
+    match settings.export_circuit_failures {
+        Some(failures) => spilling_batch_processor(
+            CircuitBreaker::new(exporter, failures, settings.export_circuit_cooldown),
+            settings,
+            spill_dir,
+            Destination::Primary,
+        ),
+        None => spilling_batch_processor(exporter, settings, spill_dir, Destination::Primary),
+    }
+}
+
+/// Builds the processor exporting over OTLP/HTTP to the `index`th secondary
+/// endpoint, without the primary endpoint's credentials.
+pub(crate) fn secondary_processor(
+    settings: &Settings,
+    index: usize,
+    endpoint: &str,
+) -> Result<Box<dyn trace::SpanProcessor>, TelemetryError> {
+    let client = build_export_client(settings)?;
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_http_client(
+                ExportClient::new(client, settings.http_encoding)
+                    .with_compression(settings.export_compression),
+            )
+            .with_endpoint(endpoint)
+            .with_timeout(Duration::from_secs(3)),
+    )
+    .build_span_exporter()?;
+    Ok(secondary_batch_processor(
+        exporter, settings, index, endpoint,
+    ))
+}
+
+/// A [`batch_processor`] for the `index`th secondary endpoint, counted apart
+/// from the primary one and spilling to its own subdirectory. The circuit
+/// breaker is left out, as its state is reported as the primary endpoint's.
+fn secondary_batch_processor<E: SpanExporter + 'static>(
+    exporter: E,
+    settings: &Settings,
+    index: usize,
+    endpoint: &str,
+) -> Box<dyn trace::SpanProcessor> {
+    let destination = Destination::secondary(endpoint);
+    let exporter = CountingExporter::new(
+        RetryingExporter::new(
+            exporter,
+            settings.export_max_attempts,
+            settings.export_retry_backoff,
+        ),
+        destination.clone(),
+    );
+    let spill_dir = settings
+        .spill_dir
+        .as_ref()
+        .map(|dir| dir.join(format!("secondary-{index}")));
+    spilling_batch_processor(exporter, settings, spill_dir, destination)
+}
+
+/// Keeps the spans of failed exports in `spill_dir` when set, or else in a
+/// [`RecoveryBuffer`], never in both.
+fn spilling_batch_processor<E: SpanExporter + 'static>(
+    exporter: E,
+    settings: &Settings,
+    spill_dir: Option<PathBuf>,
+    destination: Destination,
+) -> Box<dyn trace::SpanProcessor> {
+    match spill_dir {
+        Some(dir) => retrying_batch_processor(
+            DiskSpill::new(exporter, dir, settings.spill_max_bytes),
+            settings,
+            destination,
+        ),
+        None => retrying_batch_processor(
+            RecoveryBuffer::new(exporter, settings.recovery_buffer_spans),
+            settings,
+            destination,
+        ),
+    }
+}
+
+fn retrying_batch_processor<E: SpanExporter + 'static>(
+    exporter: E,
+    settings: &Settings,
+    destination: Destination,
+) -> Box<dyn trace::SpanProcessor> {
+    let config = batch_config(settings);
+    if settings.export_thread {
+        Box::new(QueueCounter::new(
+            BatchSpanProcessor::builder(exporter, opentelemetry::runtime::TokioCurrentThread)
+                .with_batch_config(config)
+                .build(),
+            destination,
+        ))
+    } else {
+        Box::new(QueueCounter::new(
+            BatchSpanProcessor::builder(exporter, opentelemetry::runtime::Tokio)
+                .with_batch_config(config)
+                .build(),
+            destination,
+        ))
+    }
+}
+
+/// The SDK's batch config, which reads `OTEL_BSP_*`, with the `batch_*`
+/// settings applied over it.
+fn batch_config(settings: &Settings) -> BatchConfig {
+    let mut config = BatchConfig::default();
+    // The batch size is capped at the queue size, so the queue is set first.
+    if let Some(size) = settings.batch_max_queue_size {
+        config = config.with_max_queue_size(size);
+    }
+    if let Some(size) = settings.batch_max_export_batch_size {
+        config = config.with_max_export_batch_size(size);
+    }
+    if let Some(delay) = settings.batch_scheduled_delay {
+        config = config.with_scheduled_delay(delay);
+    }
+    if let Some(timeout) = settings.batch_export_timeout {
+        config = config.with_max_export_timeout(timeout);
+    }
+    config
+}
+
+/// Headers added to every export request. Shared by the span and metric
+/// exporters so both authenticate the same way.
+#[derive(Clone)]
+pub(crate) struct ExportAuth {
+    headers: HashMap<String, String>,
+    provider: Option<Arc<dyn HeaderProvider>>,
+    /// How requests authenticate, for log messages.
+    mode: &'static str,
+}
+
+/// Resolves the configured credentials into export headers, starting the
+/// token refresh or credential file reload they need.
+pub(crate) fn export_auth(
+    settings: &Settings,
+    client: &reqwest::Client,
+) -> Result<ExportAuth, TelemetryError> {
+    let mut header_map = settings.export_headers.clone();
+
+    let header_provider: Option<Arc<dyn HeaderProvider>> =
+        match (&settings.oauth2, &settings.credential_file) {
+            (Some(oauth2), _) => Some(oauth::start(oauth2.clone(), client.clone())),
+            (None, Some(file)) if !has_authorization(&header_map) => {
+                let credentials =
+                    Arc::new(FileCredentials::load(file.clone()).map_err(|reason| {
+                        TelemetryError::InvalidSetting {
+                            var: file.var(),
+                            reason,
+                        }
+                    })?);
+                credentials.reload_on_sighup();
+                Some(credentials)
+            }
+            _ => None,
+        };
+
+    let mode = if settings.oauth2.is_some() {
+        "oauth2"
+    } else if header_provider.is_some() {
+        "credential file"
+    } else if has_authorization(&header_map) {
+        "Authorization header"
+    } else if settings.local_collector {
+        "none"
+    } else {
+        let (mode, authorization) = match &settings.bearer_token {
+            Some(token) => ("bearer", format!("Bearer {token}")),
+            None => (
+                "basic",
+                format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(format!(
+                        "{}:{}",
+                        settings.otel_username, settings.otel_password
+                    ))
+                ),
+            ),
+        };
+        header_map.insert(String::from("Authorization"), authorization);
+        mode
+    };
+
+    Ok(ExportAuth {
+        headers: header_map,
+        provider: header_provider,
+        mode,
+    })
+}
+
+/// Checks that `endpoint` is an http or https URL.
+fn check_endpoint(endpoint: &str) -> Result<(), TelemetryError> {
+    let is_http_url = endpoint
+        .parse::<Uri>()
+        .is_ok_and(|uri| matches!(uri.scheme_str(), Some("http" | "https")));
+    if is_http_url {
+        Ok(())
+    } else {
+        Err(TelemetryError::InvalidEndpoint(endpoint.to_owned()))
+    }
+}
+
+/// A lazily connected gRPC channel to `endpoint`.
+fn grpc_channel(settings: &Settings, endpoint: &str) -> Result<Channel, TelemetryError> {
+    Ok(Endpoint::from_shared(endpoint.to_owned())
+        .map_err(|_| TelemetryError::InvalidEndpoint(endpoint.to_owned()))?
+        .timeout(Duration::from_secs(3))
+        .connect_with_connector_lazy(settings.export_tls.grpc_connector()?))
+}
+
+/// Builds the processor exporting over OTLP to the configured endpoint.
+pub(crate) fn otlp_processor(
+    settings: &Settings,
+    client: reqwest::Client,
+    auth: ExportAuth,
+) -> Result<Box<dyn trace::SpanProcessor>, TelemetryError> {
+    let protocol = settings.export_protocol;
+    check_endpoint(&settings.otel_endpoint)?;
+
+    let grpc_channel = match protocol {
+        ExportProtocol::Http => None,
+        ExportProtocol::Grpc => Some(grpc_channel(settings, &settings.otel_endpoint)?),
+    };
+    let endpoint = settings.otel_endpoint.clone();
+    let encoding = settings.http_encoding;
+    let compression = settings.export_compression;
+    let ExportAuth {
+        headers: header_map,
+        provider: header_provider,
+        ..
+    } = auth;
+
+    let build_exporter = move |org_id: Option<&str>| {
+        let mut headers = header_map.clone();
+        if let Some(org_id) = org_id {
+            headers.insert(String::from(TEMPO_TENANT_HEADER), org_id.to_owned());
+        }
+        let builder = match protocol {
+            ExportProtocol::Http => SpanExporterBuilder::from(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_http_client(
+                        ExportClient::new(client.clone(), encoding)
+                            .with_compression(compression)
+                            .with_header_provider(header_provider.clone()),
+                    )
+                    .with_headers(headers)
+                    .with_endpoint(&endpoint)
+                    .with_timeout(Duration::from_secs(3)),
+            ),
+            ExportProtocol::Grpc => {
+                let mut builder = opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_channel(
+                        grpc_channel
+                            .clone()
+                            .expect("gRPC channel is built for gRPC"),
+                    )
+                    .with_metadata(grpc_metadata(&headers)?)
+                    .with_endpoint(&endpoint)
+                    .with_timeout(Duration::from_secs(3));
+                if let Some(provider) = &header_provider {
+                    builder = builder.with_interceptor(HeaderInterceptor(provider.clone()));
+                }
+                SpanExporterBuilder::from(builder)
+            }
+        };
+        builder.build_span_exporter()
+    };
+
+    let processor = match &settings.tenant_attribute {
+        Some(tenant_attribute) => {
+            let router = org_id_router(settings.tenant_org_ids.clone());
+            batch_processor(
+                TenantRoutingExporter::new(
+                    tenant_attribute.clone(),
+                    router,
+                    Box::new(move |org_id| {
+                        build_exporter(org_id).map(|e| Box::new(e) as Box<dyn SpanExporter>)
+                    }),
+                ),
+                settings,
+            )
+        }
+        None => batch_processor(build_exporter(None)?, settings),
+    };
+
+    Ok(processor)
+}
+
+/// Time the export preflight may take before it counts as failed.
+const EXPORT_PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends an empty span export to the configured endpoint, so a wrong URL or
+/// rejected credentials are reported once at startup rather than showing up
+/// later as dropped spans.
+pub(crate) fn export_preflight(
+    settings: &Settings,
+    client: reqwest::Client,
+    auth: ExportAuth,
+) -> Result<BoxFuture<'static, ()>, TelemetryError> {
+    let endpoint = settings.otel_endpoint.clone();
+    let protocol = settings.export_protocol;
+    let grpc_channel = match protocol {
+        ExportProtocol::Http => None,
+        ExportProtocol::Grpc => Some(grpc_channel(settings, &endpoint)?),
+    };
+    let client = ExportClient::new(client, settings.http_encoding)
+        .with_compression(settings.export_compression)
+        .with_header_provider(auth.provider.clone());
+
+    Ok(Box::pin(async move {
+        let check = async {
+            match grpc_channel {
+                Some(channel) => grpc_preflight(channel, &auth).await,
+                None => http_preflight(&client, &endpoint, &auth).await,
+            }
+        };
+        let outcome = tokio::time::timeout(EXPORT_PREFLIGHT_TIMEOUT, check)
+            .await
+            .unwrap_or_else(|_| Err(format!("no response within {EXPORT_PREFLIGHT_TIMEOUT:?}")));
+        match outcome {
+            Ok(status) => tracing::info!(
+                endpoint,
+                ?protocol,
+                auth = auth.mode,
+                status,
+                "Export preflight succeeded"
+            ),
+            Err(reason) => tracing::warn!(
+                endpoint,
+                ?protocol,
+                auth = auth.mode,
+                "Export preflight failed, spans will likely be dropped: {reason}"
+            ),
+        }
+    }))
+}
+
+/// Posts an empty `ExportTraceServiceRequest`, returning the response status.
+async fn http_preflight(
+    client: &ExportClient,
+    endpoint: &str,
+    auth: &ExportAuth,
+) -> Result<String, String> {
+    let mut request = Request::post(endpoint)
+        .header(CONTENT_TYPE, "application/x-protobuf")
+        .body(Vec::new())
+        .map_err(|e| e.to_string())?;
+    for (name, value) in &auth.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+        let value = HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+        request.headers_mut().insert(name, value);
+    }
+    let response = client.send(request).await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.to_string())
+    } else {
+        Err(format!(
+            "status {status}: {}",
+            String::from_utf8_lossy(response.body()).trim()
+        ))
+    }
+}
+
+/// Calls `TraceService/Export` with an empty request, returning the status.
+async fn grpc_preflight(channel: Channel, auth: &ExportAuth) -> Result<String, String> {
+    let mut request = tonic::Request::new(());
+    *request.metadata_mut() = grpc_metadata(&auth.headers).map_err(|e| e.to_string())?;
+    if let Some(provider) = &auth.provider {
+        request = HeaderInterceptor(provider.clone())
+            .call(request)
+            .map_err(|status| status.message().to_owned())?;
+    }
+    let (metadata, extensions, ()) = request.into_parts();
+    let request =
+        tonic::Request::from_parts(metadata, extensions, ExportTraceServiceRequest::default());
+
+    let mut grpc = tonic::client::Grpc::new(channel);
+    grpc.ready().await.map_err(|e| e.to_string())?;
+    let codec = ProstCodec::<ExportTraceServiceRequest, ExportTraceServiceResponse>::default();
+    let path =
+        PathAndQuery::from_static("/opentelemetry.proto.collector.trace.v1.TraceService/Export");
+    match grpc.unary(request, path, codec).await {
+        Ok(_) => Ok(String::from("OK")),
+        Err(status) => Err(format!("status {:?}: {}", status.code(), status.message())),
+    }
+}
+
+/// Builds the reader exporting metrics over OTLP on an interval, to the
+/// metrics endpoint next to the traces endpoint unless one is configured.
+pub(crate) fn otlp_metric_reader(
+    settings: &Settings,
+    client: reqwest::Client,
+    auth: ExportAuth,
+) -> Result<PeriodicReader, TelemetryError> {
+    let endpoint = match &settings.metrics_endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => signal_endpoint(&settings.otel_endpoint, settings.export_protocol, "metrics"),
+    };
+    check_endpoint(&endpoint)?;
+
+    let exporter = match settings.export_protocol {
+        ExportProtocol::Http => MetricsExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_http_client(
+                    // The JSON transcoding only understands spans.
+                    ExportClient::new(client, HttpEncoding::Protobuf)
+                        .with_signal(Signal::Metrics)
+                        .with_compression(settings.export_compression)
+                        .with_header_provider(auth.provider),
+                )
+                .with_headers(auth.headers)
+                .with_endpoint(&endpoint)
+                .with_timeout(Duration::from_secs(3)),
+        ),
+        ExportProtocol::Grpc => {
+            let mut builder = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_channel(grpc_channel(settings, &endpoint)?)
+                .with_metadata(grpc_metadata(&auth.headers)?)
+                .with_endpoint(&endpoint)
+                .with_timeout(Duration::from_secs(3));
+            if let Some(provider) = auth.provider {
+                builder = builder.with_interceptor(HeaderInterceptor(provider));
+            }
+            MetricsExporterBuilder::from(builder)
+        }
+    };
+
+    let exporter = exporter
+        .build_metrics_exporter(
+            Box::new(DefaultTemporalitySelector::new()),
+            Box::new(metrics::aggregation),
+        )
+        .map_err(TelemetryError::MetricExporter)?;
+    Ok(PeriodicReader::builder(
+        metrics::WithExemplars(exporter),
+        opentelemetry::runtime::Tokio,
+    )
+    .with_interval(settings.metrics_interval)
+    .build())
+}
+
+/// Builds the exporter sending logs over OTLP, to the logs endpoint next to
+/// the traces endpoint unless one is configured.
+pub(crate) fn otlp_log_exporter(
+    settings: &Settings,
+    client: reqwest::Client,
+    auth: ExportAuth,
+) -> Result<LogExporter, TelemetryError> {
+    let endpoint = match &settings.logs_endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => signal_endpoint(&settings.otel_endpoint, settings.export_protocol, "logs"),
+    };
+    check_endpoint(&endpoint)?;
+
+    let builder = match settings.export_protocol {
+        ExportProtocol::Http => LogExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_http_client(
+                    ExportClient::new(client, HttpEncoding::Protobuf)
+                        .with_signal(Signal::Logs)
+                        .with_compression(settings.export_compression)
+                        .with_header_provider(auth.provider),
+                )
+                .with_headers(auth.headers)
+                .with_endpoint(&endpoint)
+                .with_timeout(Duration::from_secs(3)),
+        ),
+        ExportProtocol::Grpc => {
+            let mut builder = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_channel(grpc_channel(settings, &endpoint)?)
+                .with_metadata(grpc_metadata(&auth.headers)?)
+                .with_endpoint(&endpoint)
+                .with_timeout(Duration::from_secs(3));
+            if let Some(provider) = auth.provider {
+                builder = builder.with_interceptor(HeaderInterceptor(provider));
+            }
+            LogExporterBuilder::from(builder)
+        }
+    };
+    builder
+        .build_log_exporter()
+        .map_err(TelemetryError::LogExporter)
+}
+
+/// The endpoint for `signal` next to the traces endpoint. OTLP/HTTP puts each
+/// signal under its own path, while OTLP/gRPC serves them all on one endpoint.
+fn signal_endpoint(traces_endpoint: &str, protocol: ExportProtocol, signal: &str) -> String {
+    match protocol {
+        ExportProtocol::Grpc => traces_endpoint.to_owned(),
+        ExportProtocol::Http => {
+            let base = traces_endpoint
+                .strip_suffix("/v1/traces")
+                .unwrap_or(traces_endpoint.trim_end_matches('/'));
+            format!("{base}/v1/{signal}")
+        }
+    }
+}
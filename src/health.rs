@@ -0,0 +1,138 @@
+//! Health endpoints for orchestrators, enabled with `OtelTempoHealthEndpoints`
+//! (on by default).
+use axum::{
+    extract::State,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+
+use crate::{resource, status};
+
+/// How long a reachability check of the OTLP endpoint is reused.
+pub const READINESS_CHECK_TTL: Duration = Duration::from_secs(10);
+
+/// How long the reachability check waits for the connection.
+const READINESS_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Starts the uptime clock. [`crate::init_telemetry`] calls this, otherwise
+/// uptime counts from the first health check.
+pub fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// A router serving `GET /healthz` and `GET /livez`, to merge into the
+/// application outside the tracing layers so probes do not create traces.
+/// Both answer `200` with the build and uptime for as long as the process
+/// serves requests.
+pub fn health_routes<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/healthz", get(health))
+        .route("/livez", get(health))
+}
+
+async fn health() -> Json<Value> {
+    let started = *STARTED.get_or_init(Instant::now);
+    Json(json!({
+        "status": "ok",
+        "version": resource::BUILD_SERVICE_VERSION,
+        "git_sha": resource::BUILD_GIT_SHA,
+        "uptime_seconds": started.elapsed().as_secs(),
+    }))
+}
+
+/// A router serving `GET /readyz`. Ready while `endpoint`, the OTLP endpoint
+/// spans are exported to, accepts connections and exports succeed. Answers
+/// `503` with the reason while the endpoint is unreachable, the export circuit
+/// is open, or the latest exports failed, so orchestration surfaces telemetry
+/// outages. Without an endpoint, when spans are not exported over OTLP, it is
+/// always ready.
+///
+/// The connection check is cached for [`READINESS_CHECK_TTL`], so frequent
+/// probes do not each open a connection to Tempo.
+pub fn readiness_routes<S>(endpoint: Option<String>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/readyz", get(ready))
+        .with_state(Arc::new(Readiness {
+            endpoint,
+            last_check: Mutex::new(None),
+        }))
+}
+
+struct Readiness {
+    endpoint: Option<String>,
+    last_check: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl Readiness {
+    async fn reachable(&self, endpoint: &str) -> Result<(), String> {
+        if let Some((checked, result)) = &*self.last_check.lock().unwrap() {
+            if checked.elapsed() < READINESS_CHECK_TTL {
+                return result.clone();
+            }
+        }
+        let result = connect(endpoint).await;
+        *self.last_check.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result
+    }
+}
+
+/// Opens, and drops, a TCP connection to the endpoint's host and port.
+async fn connect(endpoint: &str) -> Result<(), String> {
+    let uri: Uri = endpoint.parse().map_err(|e| format!("{e}"))?;
+    let host = uri.host().ok_or("the endpoint has no host")?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+    match tokio::time::timeout(READINESS_CHECK_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("cannot connect to {host}:{port}: {e}")),
+        Err(_) => Err(format!(
+            "connecting to {host}:{port} timed out after {READINESS_CHECK_TIMEOUT:?}"
+        )),
+    }
+}
+
+async fn ready(State(readiness): State<Arc<Readiness>>) -> Response {
+    let Some(endpoint) = &readiness.endpoint else {
+        return Json(json!({ "status": "ready", "export": "disabled" })).into_response();
+    };
+    if let Err(error) = readiness.reachable(endpoint).await {
+        return unavailable("unreachable", error);
+    }
+
+    let status = status::telemetry_status();
+    if status.export_circuit_open {
+        return unavailable("degraded", String::from("span export circuit is open"));
+    }
+    if status.consecutive_export_failures > 0 {
+        let error = status.last_export_error.unwrap_or_default();
+        return unavailable("degraded", format!("span export is failing: {error}"));
+    }
+    Json(json!({ "status": "ready", "export": "ok" })).into_response()
+}
+
+fn unavailable(status: &str, error: String) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": status, "error": error })),
+    )
+        .into_response()
+}
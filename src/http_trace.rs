@@ -0,0 +1,476 @@
+//! An HTTP server span following the OpenTelemetry HTTP semantic conventions,
+//! in place of tower-http's generic `request` span:
+//! `TraceLayer::new_for_http().make_span_with(HttpMakeSpan::new(fields))`,
+//! or [`layer`] for the whole set.
+use axum::{
+    extract::{ConnectInfo, MatchedPath},
+    http::{header, HeaderMap, HeaderName, Request, Response, Version},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, ServerErrorsFailureClass, SharedClassifier},
+    trace::{
+        DefaultOnBodyChunk, DefaultOnEos, DefaultOnRequest, MakeSpan, OnFailure, OnResponse,
+        TraceLayer,
+    },
+};
+use tracing::{field, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::Settings;
+use crate::middleware::{CorrelationId, ErrorMessage, RequestSpanFields, CORRELATION_ID_KEY};
+use crate::reload::Reloadable;
+use crate::span;
+
+/// Headers never copied onto spans, whatever the capture settings say.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// `TraceLayer` recording semantic convention HTTP server spans.
+pub type HttpTraceLayer = TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    HttpMakeSpan,
+    DefaultOnRequest,
+    HttpOnResponse,
+    DefaultOnBodyChunk,
+    DefaultOnEos,
+    HttpOnFailure,
+>;
+
+/// A `TraceLayer` with [`HttpMakeSpan`], [`HttpOnResponse`] and
+/// [`HttpOnFailure`], configured from `settings`.
+pub fn layer(settings: &Settings) -> HttpTraceLayer {
+    TraceLayer::new_for_http()
+        .make_span_with(
+            HttpMakeSpan::new(settings.request_span_fields)
+                .with_trusted_proxies(settings.trusted_proxies.clone())
+                .with_captured_headers(settings.capture_request_headers.clone()),
+        )
+        .on_response(HttpOnResponse::new(
+            settings.capture_response_headers.clone(),
+        ))
+        .on_failure(HttpOnFailure)
+}
+
+/// Header names whose values are copied onto spans as
+/// `http.request.header.<name>` or `http.response.header.<name>`. Sensitive
+/// headers such as `authorization` and `cookie` are left out even when listed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CapturedHeaders(Vec<HeaderName>);
+
+impl CapturedHeaders {
+    fn record(&self, span: &Span, prefix: &str, headers: &HeaderMap) {
+        for name in &self.0 {
+            record_header(span, prefix, headers, name);
+        }
+    }
+}
+
+/// Records the values of the `name` header as `{prefix}.{name}`, joined by
+/// `, ` when it is repeated.
+fn record_header(span: &Span, prefix: &str, headers: &HeaderMap, name: &HeaderName) {
+    let values: Vec<_> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if !values.is_empty() {
+        span.set_attribute(format!("{prefix}.{name}"), values.join(", "));
+    }
+}
+
+impl FromStr for CapturedHeaders {
+    type Err = String;
+
+    /// Parses a comma separated list of header names, such as
+    /// `content-type,x-request-id`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut names = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let name: HeaderName = name
+                .parse()
+                .map_err(|_| format!("invalid header name {name}"))?;
+            if !SENSITIVE_HEADERS.contains(&name.as_str()) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        Ok(Self(names))
+    }
+}
+
+/// Makes the server span of each request, named after the method and matched
+/// route template such as `GET /users/:id`, recording the request attributes
+/// selected by [`RequestSpanFields`] along with `http.route`, `url.scheme`,
+/// `server.address`, `server.port`, `client.address` and
+/// `user_agent.original`.
+///
+/// `client.address` is the peer address from axum's `ConnectInfo`, so serve
+/// with `into_make_service_with_connect_info::<SocketAddr>()`. When the peer
+/// is a trusted proxy, the client is taken from the `Forwarded` or
+/// `X-Forwarded-For` header instead.
+#[derive(Clone, Debug)]
+pub struct HttpMakeSpan {
+    fields: RequestSpanFields,
+    trusted_proxies: TrustedProxies,
+    captured_headers: Reloadable<CapturedHeaders>,
+}
+
+impl HttpMakeSpan {
+    pub fn new(fields: RequestSpanFields) -> Self {
+        Self {
+            fields,
+            trusted_proxies: TrustedProxies::default(),
+            captured_headers: Reloadable::default(),
+        }
+    }
+
+    /// Proxies whose forwarding headers are believed.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Request headers copied onto the span.
+    pub fn with_captured_headers(
+        mut self,
+        captured_headers: impl Into<Reloadable<CapturedHeaders>>,
+    ) -> Self {
+        self.captured_headers = captured_headers.into();
+        self
+    }
+}
+
+impl<B> MakeSpan<B> for HttpMakeSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let span = tracing::debug_span!(
+            target: "tower_http::trace::make_span",
+            "request",
+            otel.kind = "server",
+            otel.name = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            http.request.method = field::Empty,
+            http.route = field::Empty,
+            url.path = field::Empty,
+            url.query = field::Empty,
+            url.scheme = field::Empty,
+            server.address = field::Empty,
+            server.port = field::Empty,
+            client.address = field::Empty,
+            user_agent.original = field::Empty,
+            network.protocol.version = field::Empty,
+            http.response.status_code = field::Empty,
+            "error.type" = field::Empty,
+            correlation.id = field::Empty,
+        );
+        // Skip the header parsing when the filter turned the span off.
+        if span.is_disabled() {
+            return span;
+        }
+
+        // Continue the caller's trace.
+        span.set_parent(span::extract_context(request.headers()));
+
+        // Named `{method} {route}` after the route template, never the raw
+        // path, to keep span names low cardinality. The route is recorded
+        // before the span is sampled, for `RouteSampler`.
+        let method = request.method().as_str();
+        match request.extensions().get::<MatchedPath>() {
+            Some(route) => {
+                span.record("otel.name", format!("{method} {}", route.as_str()));
+                span.record("http.route", route.as_str());
+            }
+            None => {
+                span.record("otel.name", method);
+            }
+        }
+        if let Some(CorrelationId(id)) = request.extensions().get() {
+            span.record(CORRELATION_ID_KEY, id.as_str());
+        }
+
+        let uri = request.uri();
+        span.record("url.scheme", uri.scheme_str().unwrap_or("http"));
+        let authority = uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| {
+                request
+                    .headers()
+                    .get(header::HOST)
+                    .and_then(|host| host.to_str().ok())
+            });
+        if let Some((address, port)) = authority.map(split_host_port) {
+            span.record("server.address", address);
+            if let Some(port) = port {
+                span.record("server.port", i64::from(port));
+            }
+        }
+
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if let Some(client) =
+            peer.map(|peer| client_address(peer, request.headers(), &self.trusted_proxies))
+        {
+            span.record("client.address", client);
+        }
+        if let Some(user_agent) = request
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+        {
+            span.record("user_agent.original", user_agent);
+        }
+
+        if self.fields.method {
+            span.record("http.request.method", method);
+        }
+        if self.fields.uri {
+            span.record("url.path", uri.path());
+            if let Some(query) = uri.query() {
+                span.record("url.query", query);
+            }
+        }
+        if self.fields.version {
+            if let Some(version) = protocol_version(request.version()) {
+                span.record("network.protocol.version", version);
+            }
+        }
+        if self.fields.headers {
+            // Under the same keys as captured headers, so the two never clash.
+            for name in request.headers().keys() {
+                if !SENSITIVE_HEADERS.contains(&name.as_str()) {
+                    record_header(&span, "http.request.header", request.headers(), name);
+                }
+            }
+        } else {
+            self.captured_headers
+                .get()
+                .record(&span, "http.request.header", request.headers());
+        }
+
+        span
+    }
+}
+
+/// Records `http.response.status_code`, sets the span status to error for
+/// 5xx responses, and logs the finished request like tower-http's default.
+/// 4xx responses leave the status unset, as the HTTP semantic conventions
+/// have server spans do; the `axum::rejection` events explaining them are
+/// already recorded as span events.
+#[derive(Clone, Debug, Default)]
+pub struct HttpOnResponse {
+    captured_headers: Reloadable<CapturedHeaders>,
+}
+
+impl HttpOnResponse {
+    /// Also copies `captured_headers` of the response onto the span.
+    pub fn new(captured_headers: impl Into<Reloadable<CapturedHeaders>>) -> Self {
+        Self {
+            captured_headers: captured_headers.into(),
+        }
+    }
+}
+
+impl<B> OnResponse<B> for HttpOnResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        self.captured_headers
+            .get()
+            .record(span, "http.response.header", response.headers());
+        let status = response.status().as_u16();
+        span.record("http.response.status_code", i64::from(status));
+        if response.status().is_server_error() {
+            span.record("otel.status_code", "ERROR");
+            // After the code, which would otherwise clear the message.
+            if let Some(ErrorMessage(message)) = response.extensions().get() {
+                span.record("otel.status_message", message.as_str());
+            }
+        }
+        tracing::debug!(
+            target: "tower_http::trace::on_response",
+            latency = %format_args!("{} ms", latency.as_millis()),
+            status,
+            "finished processing request"
+        );
+    }
+}
+
+/// Records `error.type` for server errors, the status code or the error the
+/// service failed with. A service error also becomes the span's error status
+/// message. Logs the failure like tower-http's default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpOnFailure;
+
+impl OnFailure<ServerErrorsFailureClass> for HttpOnFailure {
+    fn on_failure(
+        &mut self,
+        failure_classification: ServerErrorsFailureClass,
+        latency: Duration,
+        span: &Span,
+    ) {
+        match &failure_classification {
+            ServerErrorsFailureClass::StatusCode(status) => {
+                span.record("error.type", status.as_u16().to_string());
+            }
+            ServerErrorsFailureClass::Error(message) => {
+                span.record("error.type", "_OTHER");
+                span.record("otel.status_message", message.as_str());
+            }
+        }
+        tracing::error!(
+            target: "tower_http::trace::on_failure",
+            classification = %failure_classification,
+            latency = %format_args!("{} ms", latency.as_millis()),
+            "response failed"
+        );
+    }
+}
+
+/// Addresses and CIDR ranges of reverse proxies in front of the service.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|&(network, prefix)| match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    /// Parses a comma separated list of addresses and CIDR ranges, such as
+    /// `10.0.0.0/8,127.0.0.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut proxies = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (addr, prefix) = match entry.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (entry, None),
+            };
+            let addr: IpAddr = addr
+                .parse()
+                .map_err(|_| format!("invalid proxy address {entry}"))?;
+            let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse()
+                    .ok()
+                    .filter(|prefix| *prefix <= max_prefix)
+                    .ok_or_else(|| format!("invalid prefix length in {entry}"))?,
+                None => max_prefix,
+            };
+            proxies.push((addr.to_canonical(), prefix));
+        }
+        Ok(Self(proxies))
+    }
+}
+
+/// The client behind `peer`: the peer itself unless it is a trusted proxy, in
+/// which case the last hop in the forwarding headers that is not a trusted
+/// proxy. `Forwarded` wins over `X-Forwarded-For`.
+fn client_address(peer: IpAddr, headers: &HeaderMap, trusted: &TrustedProxies) -> String {
+    if !trusted.contains(peer) {
+        return peer.to_canonical().to_string();
+    }
+
+    let forwarded = forwarded_for(headers);
+    let hops = if forwarded.is_empty() {
+        headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|hop| hop.trim().to_owned())
+            .filter(|hop| !hop.is_empty())
+            .collect()
+    } else {
+        forwarded
+    };
+
+    hops.iter()
+        .rev()
+        .find(|hop| {
+            hop.parse::<IpAddr>()
+                .map_or(true, |ip| !trusted.contains(ip))
+        })
+        .or(hops.first())
+        .cloned()
+        .unwrap_or_else(|| peer.to_canonical().to_string())
+}
+
+/// The `for` addresses of the `Forwarded` header (RFC 7239), without ports.
+fn forwarded_for(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| strip_port(value.trim().trim_matches('"')).to_owned())
+            })
+        })
+        .collect()
+}
+
+/// Drops the port from `ip:port` and `[ipv6]:port`, and the brackets from
+/// `[ipv6]`.
+fn strip_port(addr: &str) -> &str {
+    if let Some(rest) = addr.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match addr.split_once(':') {
+        Some((host, port)) if !port.contains(':') => host,
+        _ => addr,
+    }
+}
+
+/// Splits `host[:port]`, dropping the brackets around IPv6 addresses.
+fn split_host_port(authority: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, port)) => (host, port.strip_prefix(':').and_then(|p| p.parse().ok())),
+            None => (rest, None),
+        };
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()),
+        None => (authority, None),
+    }
+}
+
+fn protocol_version(version: Version) -> Option<&'static str> {
+    match version {
+        Version::HTTP_09 => Some("0.9"),
+        Version::HTTP_10 => Some("1.0"),
+        Version::HTTP_11 => Some("1.1"),
+        Version::HTTP_2 => Some("2"),
+        Version::HTTP_3 => Some("3"),
+        _ => None,
+    }
+}
@@ -0,0 +1,39 @@
+//! Sends the traces of an axum service to Grafana Tempo over OTLP.
+//!
+//! Call [`init_telemetry`] with [`load_settings`] at startup and keep the
+//! returned [`TelemetryGuard`] alive until the server has shut down.
+
+pub mod admin;
+pub mod clock;
+pub mod config;
+pub mod error;
+pub mod export;
+pub mod health;
+pub mod http_trace;
+pub mod logging;
+pub mod logs;
+pub mod metrics;
+pub mod middleware;
+pub mod oauth;
+pub mod otlp_json;
+pub mod processors;
+pub mod prometheus;
+pub mod propagation;
+pub mod reload;
+pub mod resource;
+pub mod sampling;
+pub mod secrets;
+pub mod span;
+pub mod span_file;
+pub mod spill;
+pub mod startup;
+pub mod status;
+pub mod tail_sampling;
+pub mod telemetry;
+pub mod tls;
+
+pub use config::{load_settings, Settings};
+pub use error::TelemetryError;
+pub use startup::{force_flush, init_telemetry, TelemetryGuard};
+pub use status::{telemetry_status, TelemetryStatus};
+pub use telemetry::TelemetryBuilder;
@@ -0,0 +1,308 @@
+use opentelemetry::trace::{
+    SamplingDecision, SpanContext, TraceContextExt, TraceFlags, TraceId, TraceState,
+};
+use serde_json::{Map, Value};
+use std::{error::Error, fmt, str::FromStr, sync::Mutex};
+use tracing::{field::Field, Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    filter::ParseError,
+    fmt::{
+        format::{Format, Full, Writer},
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields,
+    },
+    registry::LookupSpan,
+    reload, EnvFilter, Registry,
+};
+
+/// The console filter installed by [`crate::init_telemetry`], for
+/// [`set_log_filter`] to swap.
+static LOG_FILTER: Mutex<Option<reload::Handle<EnvFilter, Registry>>> = Mutex::new(None);
+
+/// Makes the console filter replaceable at runtime.
+pub(crate) fn reloadable(filter: EnvFilter) -> reload::Layer<EnvFilter, Registry> {
+    let (layer, handle) = reload::Layer::new(filter);
+    *LOG_FILTER.lock().unwrap() = Some(handle);
+    layer
+}
+
+/// The console filter directives in effect.
+pub fn log_filter() -> Option<String> {
+    let handle = LOG_FILTER.lock().unwrap().clone()?;
+    handle.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replaces the console filter directives, e.g. `axum_otel_tempo=debug,info`,
+/// without a restart. Exported spans and log records keep their own filter.
+pub fn set_log_filter(directives: &str) -> Result<(), LogFilterError> {
+    replace_log_filter(EnvFilter::try_new(directives).map_err(LogFilterError::Invalid)?)
+}
+
+pub(crate) fn replace_log_filter(filter: EnvFilter) -> Result<(), LogFilterError> {
+    let handle = LOG_FILTER.lock().unwrap().clone();
+    handle
+        .ok_or(LogFilterError::NotInstalled)?
+        .reload(filter)
+        .map_err(|_| LogFilterError::NotInstalled)
+}
+
+/// Why [`set_log_filter`] failed.
+#[derive(Debug)]
+pub enum LogFilterError {
+    /// The directives could not be parsed.
+    Invalid(ParseError),
+    /// The subscriber was not installed by this crate.
+    NotInstalled,
+}
+
+impl fmt::Display for LogFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFilterError::Invalid(e) => write!(f, "cannot parse filter directives: {e}"),
+            LogFilterError::NotInstalled => f.write_str("the log filter is not reloadable"),
+        }
+    }
+}
+
+impl Error for LogFilterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LogFilterError::Invalid(e) => Some(e),
+            LogFilterError::NotInstalled => None,
+        }
+    }
+}
+
+/// How log lines are written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The default `tracing_subscriber` line.
+    #[default]
+    Full,
+    /// Multi-line, for reading locally.
+    Pretty,
+    /// One short line, without span fields.
+    Compact,
+    /// One JSON object per line, see [`JsonFormat`].
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "expected full, pretty, compact or json, got {other}"
+            )),
+        }
+    }
+}
+
+/// The default fmt line with the current span's `trace_flags` and
+/// `parent_remote` after the timestamp, to tell why a log line's trace may be
+/// missing from Tempo: unsampled traces (`trace_flags=00`) are never exported.
+pub struct TraceFlagsFormat {
+    inner: Format<Full, ()>,
+}
+
+impl Default for TraceFlagsFormat {
+    fn default() -> Self {
+        Self {
+            inner: Format::default().without_time(),
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for TraceFlagsFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        SystemTime.format_time(&mut writer)?;
+        writer.write_char(' ')?;
+
+        let span = ctx.lookup_current();
+        let extensions = span.as_ref().map(|span| span.extensions());
+        if let Some(data) = extensions.as_ref().and_then(|ext| ext.get::<OtelData>()) {
+            if let Some(flags) = trace_flags(data) {
+                write!(writer, "trace_flags={:02x} ", flags.to_u8())?;
+            }
+            let parent = data.parent_cx.span();
+            write!(
+                writer,
+                "parent_remote={} ",
+                parent.span_context().is_remote()
+            )?;
+        }
+
+        self.inner.format_event(ctx, writer, event)
+    }
+}
+
+/// One JSON object per line, with the current span's `trace_id` and `span_id`
+/// so Loki can link each line to its trace in Tempo:
+/// `{"timestamp":..,"level":"INFO","target":..,"message":..,"fields":{..},"span":..,"trace_id":..,"span_id":..}`.
+#[derive(Debug, Default)]
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            rfc3339(std::time::SystemTime::now()).into(),
+        );
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        if let Some(message) = visitor.message {
+            line.insert("message".into(), message);
+        }
+        if !visitor.fields.is_empty() {
+            line.insert("fields".into(), Value::Object(visitor.fields));
+        }
+
+        if let Some(span) = ctx.parent_span() {
+            line.insert("span".into(), span.name().into());
+            let extensions = span.extensions();
+            if let Some(span_context) = extensions.get::<OtelData>().and_then(span_context) {
+                line.insert(
+                    "trace_id".into(),
+                    span_context.trace_id().to_string().into(),
+                );
+                line.insert("span_id".into(), span_context.span_id().to_string().into());
+            }
+        }
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[derive(Default)]
+struct JsonVisitor {
+    message: Option<Value>,
+    fields: Map<String, Value>,
+}
+
+impl JsonVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else {
+            self.fields.insert(field.name().to_owned(), value);
+        }
+    }
+}
+
+impl tracing::field::Visit for JsonVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}").into());
+    }
+}
+
+/// UTC with microseconds, as `2023-09-14T08:15:02.123456Z`.
+fn rfc3339(time: std::time::SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        secs_of_day / 3_600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+/// The context a span will be exported with, once `tracing_opentelemetry`
+/// has assigned its ids. The sampled flag is only set when already decided.
+pub(crate) fn span_context(data: &OtelData) -> Option<SpanContext> {
+    let trace_id = match data.builder.trace_id {
+        Some(trace_id) => trace_id,
+        None => data.parent_cx.span().span_context().trace_id(),
+    };
+    if trace_id == TraceId::INVALID {
+        return None;
+    }
+    Some(SpanContext::new(
+        trace_id,
+        data.builder.span_id?,
+        trace_flags(data).unwrap_or_default(),
+        false,
+        TraceState::default(),
+    ))
+}
+
+/// The flags a span will be exported with, when they are known yet.
+pub(crate) fn trace_flags(data: &OtelData) -> Option<TraceFlags> {
+    let parent = data.parent_cx.span();
+    let parent = parent.span_context();
+    match &data.builder.sampling_result {
+        Some(result) if result.decision == SamplingDecision::RecordAndSample => {
+            Some(TraceFlags::SAMPLED)
+        }
+        Some(_) => Some(TraceFlags::default()),
+        // Not sampled yet, so it will follow a valid parent.
+        None => parent.is_valid().then(|| parent.trace_flags()),
+    }
+}
@@ -0,0 +1,148 @@
+//! Exports `tracing` events as OTLP log records when `OtelTempoLogs` is set,
+//! so they land in Loki or another logs backend with the trace and span id of
+//! the span they were logged in.
+use opentelemetry::{
+    logs::{AnyValue, LogRecord, Logger, Severity},
+    Key,
+};
+use std::{fmt, time::SystemTime};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::logging::span_context;
+
+/// Targets of the crates doing the export. Their events are not exported, so
+/// a failing export cannot feed itself.
+const EXPORT_TARGETS: &[&str] = &["opentelemetry", "hyper", "reqwest", "h2", "tonic", "tower"];
+
+/// Emits every event it sees to `logger` as a log record. Add it after the
+/// `tracing_opentelemetry` layer so the span ids are assigned.
+#[derive(Debug)]
+pub struct OtelLogLayer<L> {
+    logger: L,
+}
+
+impl<L: Logger> OtelLogLayer<L> {
+    pub fn new(logger: L) -> Self {
+        Self { logger }
+    }
+}
+
+impl<S, L> Layer<S> for OtelLogLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Logger + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if EXPORT_TARGETS
+            .iter()
+            .any(|target| is_within(metadata.target(), target))
+        {
+            return;
+        }
+
+        let mut visitor = RecordVisitor::default();
+        event.record(&mut visitor);
+        let mut attributes = visitor.attributes;
+        attributes.push((Key::new("target"), metadata.target().to_owned().into()));
+        if let Some(module) = metadata.module_path() {
+            attributes.push((Key::new("code.namespace"), module.to_owned().into()));
+        }
+        if let Some(file) = metadata.file() {
+            attributes.push((Key::new("code.filepath"), file.to_owned().into()));
+        }
+        if let Some(line) = metadata.line() {
+            attributes.push((Key::new("code.lineno"), AnyValue::Int(line.into())));
+        }
+
+        let now = SystemTime::now();
+        let mut record = LogRecord::builder()
+            .with_timestamp(now)
+            .with_observed_timestamp(now)
+            .with_severity_number(severity(metadata.level()))
+            .with_severity_text(metadata.level().as_str())
+            .with_attributes(attributes);
+        if let Some(body) = visitor.message {
+            record = record.with_body(body.into());
+        }
+        if let Some(span_context) = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<OtelData>().and_then(span_context))
+        {
+            record = record.with_span_context(&span_context);
+        }
+
+        self.logger.emit(record.build());
+    }
+}
+
+/// Whether `target` is `module` or one of its submodules.
+fn is_within(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+fn severity(level: &Level) -> Severity {
+    match *level {
+        Level::TRACE => Severity::Trace,
+        Level::DEBUG => Severity::Debug,
+        Level::INFO => Severity::Info,
+        Level::WARN => Severity::Warn,
+        Level::ERROR => Severity::Error,
+    }
+}
+
+/// Collects an event's `message` as the body and its other fields as
+/// attributes.
+#[derive(Default)]
+struct RecordVisitor {
+    message: Option<String>,
+    attributes: Vec<(Key, AnyValue)>,
+}
+
+impl RecordVisitor {
+    fn push(&mut self, field: &Field, value: AnyValue) {
+        self.attributes.push((Key::new(field.name()), value));
+    }
+}
+
+impl tracing::field::Visit for RecordVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, AnyValue::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.push(field, AnyValue::Int(value)),
+            Err(_) => self.push(field, value.to_string().into()),
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, AnyValue::Double(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, AnyValue::Boolean(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_owned());
+        } else {
+            self.push(field, value.to_owned().into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{value:?}");
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else {
+            self.push(field, value.into());
+        }
+    }
+}
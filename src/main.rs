@@ -1,33 +1,156 @@
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state};
 use axum::response::Html;
 use axum::routing::get;
-use axum::Router;
-use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
-use std::net::TcpListener;
+use axum::{Extension, Router};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
-use tower_http::trace::TraceLayer;
-use tracing::instrument;
-mod startup;
+use tower::limit::ConcurrencyLimitLayer;
+use tracing::{instrument, Instrument};
+
+use axum_otel_tempo::config::TelemetryMode;
+use axum_otel_tempo::metrics::HttpMetrics;
+use axum_otel_tempo::middleware::{self, ErrorMessage};
+use axum_otel_tempo::startup::ReloadHandle;
+use axum_otel_tempo::{admin, health, http_trace, prometheus, span, TelemetryBuilder};
 
 #[tokio::main]
 async fn main() {
-    startup::init();
+    let telemetry = match TelemetryBuilder::from_env().and_then(TelemetryBuilder::install) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("Failed to set up telemetry: {e}");
+            std::process::exit(1);
+        }
+    };
+    let settings = &telemetry.settings;
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(handler))
-        .layer(TraceLayer::new_for_http())
-        .layer(OtelInResponseLayer::default())
-        .layer(OtelAxumLayer::default());
+        .route("/downstream", get(downstream))
+        .route("/users/:id", get(user))
+        .with_state(Downstream {
+            client: reqwest::Client::new(),
+            url: self_url(settings.bind_address).into(),
+        });
+
+    if let Some(capture) = &settings.body_capture {
+        app = app.layer(from_fn_with_state(
+            capture.clone(),
+            middleware::capture_bodies,
+        ));
+    }
+
+    app = app.layer(middleware::catch_panic());
+
+    if settings.trace_id_headers {
+        app = app.layer(from_fn(middleware::trace_id_headers));
+    }
+
+    if settings.metrics || settings.prometheus {
+        app = app.layer(from_fn_with_state(
+            Arc::new(HttpMetrics::global()),
+            middleware::record_http_metrics,
+        ));
+    }
+
+    if !settings.record_path_params.is_empty() {
+        let names: Arc<[String]> = settings.record_path_params.clone().into();
+        app = app.layer(from_fn_with_state(names, middleware::record_path_params));
+    }
+
+    if !settings.baggage_attributes.is_empty() {
+        let keys: Arc<[String]> = settings.baggage_attributes.clone().into();
+        app = app.layer(from_fn_with_state(keys, middleware::record_baggage));
+    }
+
+    if !settings.context_attributes.is_empty() {
+        let headers = Arc::new(settings.context_attributes.clone());
+        app = app.layer(from_fn_with_state(headers, middleware::context_attributes));
+    }
+
+    if let Some(header) = &settings.links_header {
+        app = app.layer(from_fn_with_state(header.clone(), middleware::record_links));
+    }
+
+    if !settings.error_status_field.is_empty() {
+        let path: Arc<[String]> = settings.error_status_field.clone().into();
+        app = app.layer(from_fn_with_state(path, middleware::record_error_status));
+    }
+
+    if settings.max_concurrent_requests.is_some() {
+        app = app.layer(from_fn(middleware::record_queue_duration));
+    }
+
+    let on_request_complete: middleware::RequestCompleteHook = Arc::new(|summary| {
+        tracing::debug!(
+            route = summary.route.as_deref().unwrap_or("<unmatched>"),
+            method = %summary.method,
+            status = summary.status.as_u16(),
+            duration_ms = summary.duration.as_secs_f64() * 1000.0,
+            trace_id = %summary.trace_id,
+            "request complete"
+        );
+    });
+    app = app.layer(from_fn_with_state(
+        on_request_complete,
+        middleware::request_complete,
+    ));
+
+    app = app.layer(http_trace::layer(settings));
+
+    if settings.correlation_id {
+        app = app.layer(from_fn(middleware::correlation_id));
+    }
+
+    // Merged after the tracing layers so scrapes, probes and admin calls are
+    // not traced.
+    if settings.prometheus {
+        app = app.merge(prometheus::router());
+    }
+
+    if settings.health_endpoints {
+        let export_endpoint = (telemetry.is_exporting() && settings.mode == TelemetryMode::Otlp)
+            .then(|| settings.otel_endpoint.clone());
+        app = app
+            .merge(health::health_routes())
+            .merge(health::readiness_routes(export_endpoint));
+    }
+
+    if settings.admin_endpoints {
+        app = app.merge(admin::router());
+    }
 
-    let listener = TcpListener::bind("127.0.0.1:3000").unwrap();
-    tracing::info!("listening on {}", listener.local_addr().unwrap());
+    if let Some(max) = settings.max_concurrent_requests {
+        app = app
+            .layer(ConcurrencyLimitLayer::new(max))
+            .layer(from_fn(middleware::mark_queue_entry));
+    }
+
+    if let Some(every) = settings.flush_every_requests {
+        app = app.layer(from_fn_with_state(every, middleware::flush_every));
+    }
+
+    tokio::spawn(reload_on_hangup(telemetry.reload_handle()));
 
-    axum::Server::from_tcp(listener.into())
+    let listener = TcpListener::bind(settings.bind_address).unwrap();
+    tracing::info!(
+        trace_export = telemetry.is_exporting(),
+        "listening on {}",
+        listener.local_addr().unwrap()
+    );
+
+    axum::Server::from_tcp(listener)
         .expect("Failed to create server from listener")
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    telemetry.shutdown().await;
 }
 
 #[instrument]
@@ -37,8 +160,96 @@ async fn handler() -> Html<&'static str> {
 
 #[instrument]
 async fn sub_function() -> &'static str {
+    let started = std::time::Instant::now();
     sleep(Duration::from_millis(100)).await;
-    "<h1>Hi again world</h1>"
+    let body = "<h1>Hi again world</h1>";
+
+    if span::is_recording() {
+        span::record_f64(
+            "sleep.duration_ms",
+            started.elapsed().as_secs_f64() * 1000.0,
+        );
+        span::record_i64("response.length", body.len() as i64);
+    }
+
+    body
+}
+
+/// Looks up a user by numeric id, rejecting other ids with a 400 recorded as a
+/// span event. Fails for 0 and panics for 13, to show both on the span.
+#[instrument]
+async fn user(Path(id): Path<u32>) -> Result<String, StatusCode> {
+    match id {
+        0 => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        13 => panic!("user {id} is unlucky"),
+        id => Ok(format!("user {id}")),
+    }
+}
+
+/// The client `/downstream` calls this server back with, and the URL of `/`.
+#[derive(Clone)]
+struct Downstream {
+    client: reqwest::Client,
+    url: Arc<str>,
+}
+
+/// The URL of `/` on this server when listening on `addr`, over loopback
+/// when bound to all interfaces.
+fn self_url(mut addr: SocketAddr) -> String {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    format!("http://{addr}/")
+}
+
+/// Calls `/` on this server to demonstrate a client span propagating its context.
+#[instrument(skip_all)]
+async fn downstream(
+    State(Downstream { client, url }): State<Downstream>,
+) -> Result<Html<String>, (StatusCode, Extension<ErrorMessage>)> {
+    let client_span = span::client_span(&Method::GET, &url);
+    let mut headers = HeaderMap::new();
+    span::inject_context(&client_span, &mut headers);
+
+    let response = client
+        .get(&*url)
+        .headers(headers)
+        .send()
+        .instrument(client_span.clone())
+        .await
+        .map_err(bad_gateway)?;
+    client_span.record("http.response.status_code", response.status().as_u16());
+
+    response.text().await.map(Html).map_err(bad_gateway)
+}
+
+fn bad_gateway(e: reqwest::Error) -> (StatusCode, Extension<ErrorMessage>) {
+    (
+        StatusCode::BAD_GATEWAY,
+        Extension(ErrorMessage(format!("downstream request failed: {e}"))),
+    )
+}
+
+/// Reloads the configuration on every SIGHUP, without touching the server, so
+/// in-flight requests carry on.
+async fn reload_on_hangup(handle: ReloadHandle) {
+    #[cfg(unix)]
+    {
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("failed to install signal handler");
+        while hangup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading configuration");
+            if let Err(e) = handle.reload() {
+                tracing::warn!("Configuration reload failed, keeping the current one: {e}");
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    drop(handle);
 }
 
 async fn shutdown_signal() {
@@ -65,5 +276,4 @@ async fn shutdown_signal() {
     }
 
     tracing::warn!("signal received, starting graceful shutdown");
-    opentelemetry::global::shutdown_tracer_provider();
 }
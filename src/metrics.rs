@@ -0,0 +1,196 @@
+//! HTTP server metrics, exported over OTLP next to the spans when
+//! `OtelTempoMetrics` is set. Without it the instruments record into the
+//! no-op global meter provider.
+//!
+//! The request duration histogram carries the trace id of a sampled request
+//! per bucket as an exemplar, so Grafana can jump from a latency spike to the
+//! trace in Tempo.
+use async_trait::async_trait;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Meter, Result as MetricsResult, Unit},
+    sdk::{
+        metrics::{
+            data::{self, Exemplar, ResourceMetrics, Temporality},
+            exporter::PushMetricsExporter,
+            reader::{AggregationSelector, DefaultAggregationSelector, TemporalitySelector},
+            Aggregation, InstrumentKind,
+        },
+        AttributeSet,
+    },
+    trace::SpanContext,
+    KeyValue,
+};
+use std::{collections::HashMap, sync::Mutex, time::SystemTime};
+
+/// Name of the meter the HTTP instruments are created on.
+pub const METER_NAME: &str = "axum_otel_tempo";
+
+/// Name of the request duration histogram.
+pub const REQUEST_DURATION: &str = "http.server.request.duration";
+
+/// The latest exemplar in each request duration bucket, per attribute set.
+type Reservoir = HashMap<AttributeSet, Vec<Option<Exemplar<f64>>>>;
+
+static EXEMPLARS: Mutex<Option<Reservoir>> = Mutex::new(None);
+
+/// Bucket boundaries in seconds recommended by the semantic conventions for
+/// `http.server.request.duration`.
+const DURATION_BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
+];
+
+/// The instruments recorded for every request by
+/// [`crate::middleware::record_http_metrics`].
+#[derive(Clone, Debug)]
+pub struct HttpMetrics {
+    duration: Histogram<f64>,
+    requests: Counter<u64>,
+}
+
+impl HttpMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            duration: meter
+                .f64_histogram(REQUEST_DURATION)
+                .with_description("Duration of HTTP server requests.")
+                .with_unit(Unit::new("s"))
+                .init(),
+            requests: meter
+                .u64_counter("http.server.request.count")
+                .with_description("Number of HTTP server requests.")
+                .with_unit(Unit::new("{request}"))
+                .init(),
+        }
+    }
+
+    /// The instruments on the global meter provider, so create them after
+    /// telemetry is installed.
+    pub fn global() -> Self {
+        Self::new(&global::meter(METER_NAME))
+    }
+
+    /// Records one finished request. Unmatched requests have no `route`, and
+    /// leave `http.route` off rather than recording every path seen. A
+    /// sampled `span_context` becomes the exemplar for the duration's bucket.
+    pub fn record(
+        &self,
+        method: &str,
+        route: Option<&str>,
+        status: u16,
+        seconds: f64,
+        span_context: &SpanContext,
+    ) {
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", method.to_owned()),
+            KeyValue::new("http.response.status_class", status_class(status)),
+        ];
+        if let Some(route) = route {
+            attributes.push(KeyValue::new("http.route", route.to_owned()));
+        }
+        self.duration.record(seconds, &attributes);
+        self.requests.add(1, &attributes);
+        if span_context.is_sampled() {
+            record_exemplar(&attributes, seconds, span_context);
+        }
+    }
+}
+
+fn record_exemplar(attributes: &[KeyValue], seconds: f64, span_context: &SpanContext) {
+    let bucket = DURATION_BUCKETS.partition_point(|&bound| bound < seconds);
+    let mut exemplars = EXEMPLARS.lock().unwrap();
+    let buckets = exemplars
+        .get_or_insert_with(HashMap::new)
+        .entry(AttributeSet::from(attributes))
+        .or_insert_with(|| vec![None; DURATION_BUCKETS.len() + 1]);
+    buckets[bucket] = Some(Exemplar {
+        filtered_attributes: Vec::new(),
+        time: SystemTime::now(),
+        value: seconds,
+        span_id: span_context.span_id().to_bytes(),
+        trace_id: span_context.trace_id().to_bytes(),
+    });
+}
+
+/// Adds the recorded exemplars to the request duration histogram in
+/// `metrics`. The SDK collects histograms without exemplars.
+pub(crate) fn attach_exemplars(metrics: &mut ResourceMetrics) {
+    let exemplars = EXEMPLARS.lock().unwrap();
+    let Some(exemplars) = exemplars.as_ref() else {
+        return;
+    };
+    for metric in metrics
+        .scope_metrics
+        .iter_mut()
+        .flat_map(|scope| &mut scope.metrics)
+        .filter(|metric| metric.name == REQUEST_DURATION)
+    {
+        let Some(histogram) = metric.data.as_any().downcast_ref::<data::Histogram<f64>>() else {
+            continue;
+        };
+        let data_points = histogram
+            .data_points
+            .iter()
+            .map(|point| {
+                let mut point = point.clone();
+                if let Some(buckets) = exemplars.get(&point.attributes) {
+                    point.exemplars = buckets.iter().flatten().cloned().collect();
+                }
+                point
+            })
+            .collect();
+        metric.data = Box::new(data::Histogram {
+            data_points,
+            temporality: histogram.temporality,
+        });
+    }
+}
+
+/// Wraps a push exporter to add exemplars to what it exports.
+#[derive(Debug)]
+pub(crate) struct WithExemplars<E>(pub E);
+
+impl<E: TemporalitySelector> TemporalitySelector for WithExemplars<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for WithExemplars<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.0.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for WithExemplars<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        attach_exemplars(metrics);
+        self.0.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.0.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.0.shutdown()
+    }
+}
+
+/// `2xx` style class of a status code.
+fn status_class(status: u16) -> String {
+    format!("{}xx", status / 100)
+}
+
+/// The SDK's default aggregations, with histogram buckets suited to durations
+/// in seconds instead of milliseconds.
+pub(crate) fn aggregation(kind: InstrumentKind) -> Aggregation {
+    match kind {
+        InstrumentKind::Histogram => Aggregation::ExplicitBucketHistogram {
+            boundaries: DURATION_BUCKETS.to_vec(),
+            record_min_max: true,
+        },
+        kind => DefaultAggregationSelector::new().aggregation(kind),
+    }
+}
@@ -0,0 +1,609 @@
+use axum::{
+    body::{self, Body, Bytes, Full, HttpBody},
+    extract::{MatchedPath, RawPathParams, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use opentelemetry::{
+    baggage::BaggageExt,
+    propagation::TextMapPropagator,
+    sdk::propagation::TraceContextPropagator,
+    trace::{FutureExt, TraceContextExt, TraceId},
+    Context, Key, KeyValue,
+};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Once,
+    },
+    time::{Duration, SystemTime},
+};
+use tower_http::catch_panic::CatchPanicLayer;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{clock, metrics::HttpMetrics, reload::Reloadable, span, startup};
+
+static FLUSH_EVERY_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Forces a span flush after every `every` requests. Must be the outermost
+/// layer so the request span has already ended when the flush runs.
+pub async fn flush_every<B>(State(every): State<u64>, req: Request<B>, next: Next<B>) -> Response {
+    let response = next.run(req).await;
+
+    // Each request gets its own count, so exactly one in `every` flushes even
+    // when requests finish at once.
+    let count = FLUSH_EVERY_REQUESTS.fetch_add(1, Ordering::Relaxed) + 1;
+    if count.is_multiple_of(every.max(1)) {
+        tokio::task::spawn_blocking(startup::force_flush);
+    }
+
+    response
+}
+
+/// What [`request_complete`] reports about each finished request.
+#[derive(Clone, Debug)]
+pub struct RequestSpanSummary {
+    /// The matched route template, such as `/users/:id`.
+    pub route: Option<String>,
+    pub method: Method,
+    pub status: StatusCode,
+    pub duration: Duration,
+    /// The request span's trace id, invalid when tracing is off.
+    pub trace_id: TraceId,
+}
+
+/// Called with the summary of every completed request.
+pub type RequestCompleteHook = Arc<dyn Fn(&RequestSpanSummary) + Send + Sync>;
+
+/// Time since `since` on the crate's clock, zero if the clock went back.
+fn elapsed(since: SystemTime) -> Duration {
+    clock::now().duration_since(since).unwrap_or_default()
+}
+
+/// Passes a [`RequestSpanSummary`] of every request to `hook` once the
+/// response is ready, for feeding request outcomes into SLO tracking or other
+/// systems without reading exported traces. Must run inside `TraceLayer`.
+pub async fn request_complete<B>(
+    State(hook): State<RequestCompleteHook>,
+    route: Option<MatchedPath>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = clock::now();
+    let method = req.method().clone();
+    let response = next.run(req).await;
+
+    hook(&RequestSpanSummary {
+        route: route.map(|route| route.as_str().to_owned()),
+        method,
+        status: response.status(),
+        duration: elapsed(started),
+        trace_id: Span::current().context().span().span_context().trace_id(),
+    });
+
+    response
+}
+
+/// Records the request duration and count on `metrics`, keyed by method,
+/// route and status class, with the request span as the duration exemplar.
+/// Must run inside `TraceLayer`.
+pub async fn record_http_metrics<B>(
+    State(metrics): State<Arc<HttpMetrics>>,
+    route: Option<MatchedPath>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = clock::now();
+    let method = req.method().clone();
+    let cx = Span::current().context();
+    let response = next.run(req).await;
+
+    metrics.record(
+        method.as_str(),
+        route.as_ref().map(MatchedPath::as_str),
+        response.status().as_u16(),
+        elapsed(started).as_secs_f64(),
+        cx.span().span_context(),
+    );
+
+    response
+}
+
+/// Response header carrying the request's trace id, for pasting into Tempo
+/// search.
+pub static TRACE_ID_HEADER: HeaderName = HeaderName::from_static("x-trace-id");
+
+/// W3C Trace Context Level 2 response header, `00-{trace-id}-{span-id}-{flags}`.
+pub static TRACE_RESPONSE_HEADER: HeaderName = HeaderName::from_static("traceresponse");
+
+/// Writes the current span's trace id to the `x-trace-id` and `traceresponse`
+/// response headers, so callers can quote it when reporting a problem. Must
+/// run inside `TraceLayer` or `OtelAxumLayer`.
+pub async fn trace_id_headers<B>(req: Request<B>, next: Next<B>) -> Response {
+    let cx = Span::current().context();
+    let mut response = next.run(req).await;
+
+    let span = cx.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() {
+        let trace_id = span_context.trace_id();
+        let traceresponse = format!(
+            "00-{trace_id}-{}-{:02x}",
+            span_context.span_id(),
+            span_context.trace_flags().to_u8()
+        );
+        let headers = response.headers_mut();
+        if let Ok(value) = HeaderValue::from_str(&trace_id.to_string()) {
+            headers.insert(TRACE_ID_HEADER.clone(), value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&traceresponse) {
+            headers.insert(TRACE_RESPONSE_HEADER.clone(), value);
+        }
+    }
+
+    response
+}
+
+thread_local! {
+    /// Backtrace of the latest panic on this thread, for [`catch_panic`].
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Turns handler panics into 500 responses, recording each on the current span
+/// as an `exception` event with `exception.type`, `exception.message` and
+/// `exception.stacktrace`. Must run inside `TraceLayer` so the event lands on
+/// the request span.
+///
+/// Installs a panic hook, once, that captures the backtrace before handing
+/// over to the previous hook.
+pub fn catch_panic() -> CatchPanicLayer<fn(Box<dyn Any + Send + 'static>) -> Response> {
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            PANIC_BACKTRACE.with(|backtrace| {
+                *backtrace.borrow_mut() = Some(Backtrace::force_capture());
+            });
+            previous(info);
+        }));
+    });
+    CatchPanicLayer::custom(record_panic as fn(_) -> _)
+}
+
+fn record_panic(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("Box<dyn Any>"));
+    let stacktrace = PANIC_BACKTRACE
+        .with(|backtrace| backtrace.borrow_mut().take())
+        .map(|backtrace| backtrace.to_string())
+        .unwrap_or_default();
+
+    tracing::error!(
+        exception.message = %message,
+        "exception.type" = "panic",
+        exception.stacktrace = %stacktrace,
+        "exception"
+    );
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// When a request arrived, before waiting for a concurrency limit slot.
+#[derive(Clone, Copy, Debug)]
+struct QueueEntered(SystemTime);
+
+/// Timestamps the request before it queues for the concurrency limit. Must be
+/// the layer directly outside the limit.
+pub async fn mark_queue_entry<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    req.extensions_mut().insert(QueueEntered(clock::now()));
+    next.run(req).await
+}
+
+/// Records how long the request waited for the concurrency limit as
+/// `http.server.queue_duration_ms`, telling an overloaded server apart from a
+/// slow handler. Must run inside `TraceLayer`.
+pub async fn record_queue_duration<B>(req: Request<B>, next: Next<B>) -> Response {
+    if let Some(QueueEntered(entered)) = req.extensions().get().copied() {
+        if span::is_recording() {
+            span::record_f64(
+                "http.server.queue_duration_ms",
+                elapsed(entered).as_secs_f64() * 1000.0,
+            );
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Records the named path parameters of the matched route on the request span
+/// as `http.route.param.<name>`. Only list parameters with low cardinality or
+/// that are safe to store; everything else stays out of the span. Must run
+/// inside `TraceLayer`.
+pub async fn record_path_params<B>(
+    State(names): State<Arc<[String]>>,
+    params: Option<RawPathParams>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(params) = params.filter(|_| span::is_recording()) {
+        let span = Span::current();
+        for (name, value) in &params {
+            if names.iter().any(|n| n == name) {
+                span.set_attribute(
+                    Key::new(format!("http.route.param.{name}")),
+                    value.to_owned(),
+                );
+            }
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Records the listed entries of the request's W3C baggage on the request
+/// span, keyed by the baggage key, and keeps all incoming baggage in the
+/// request's context so it is passed on to downstream calls. Must run inside
+/// `TraceLayer`.
+pub async fn record_baggage<B>(
+    State(keys): State<Arc<[String]>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let incoming = span::extract_context(req.headers());
+    let baggage = incoming.baggage();
+    if baggage.is_empty() {
+        return next.run(req).await;
+    }
+
+    if span::is_recording() {
+        let span = Span::current();
+        for key in keys.iter().map(|key| Key::new(key.clone())) {
+            if let Some(value) = baggage.get(key.clone()) {
+                span.set_attribute(key, value.clone());
+            }
+        }
+    }
+
+    let cx = Context::current_with_baggage(
+        baggage
+            .iter()
+            .map(|(key, (value, _))| KeyValue::new(key.clone(), value.clone())),
+    );
+    next.run(req).with_context(cx).await
+}
+
+/// Copies request headers onto the request span and all of its descendants,
+/// keyed by the attribute each header maps to. Must run inside `TraceLayer`.
+pub async fn context_attributes<B>(
+    State(headers): State<Arc<HashMap<HeaderName, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let attributes: Vec<_> = headers
+        .iter()
+        .filter_map(|(header, key)| {
+            let value = req.headers().get(header)?.to_str().ok()?;
+            Some(KeyValue::new(key.clone(), value.to_owned()))
+        })
+        .collect();
+    if !attributes.is_empty() {
+        span::set_context_attributes(req.headers(), attributes);
+    }
+
+    next.run(req).await
+}
+
+/// Links the request span to the upstream traces listed in the `header`
+/// request header, a comma separated list of W3C `traceparent` values. Use it
+/// for endpoints that process a batch of messages from different traces.
+/// Must run inside `TraceLayer`.
+pub async fn record_links<B>(
+    State(header): State<HeaderName>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if span::is_recording() {
+        let span = Span::current();
+        let propagator = TraceContextPropagator::new();
+        let traceparents = req
+            .headers()
+            .get_all(&header)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for traceparent in traceparents {
+            let carrier =
+                HashMap::from([(String::from("traceparent"), traceparent.trim().to_owned())]);
+            let linked = propagator.extract(&carrier).span().span_context().clone();
+            if linked.is_valid() {
+                span.add_link(linked);
+            }
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Largest JSON error body [`record_error_status`] buffers to look for the
+/// status description.
+const MAX_ERROR_BODY_BYTES: u64 = 64 * 1024;
+
+/// A response extension handlers can set to describe why the request failed.
+/// [`crate::http_trace::HttpOnResponse`] records it as the span status
+/// message of server errors. Takes precedence over the description found in
+/// the response body.
+#[derive(Clone, Debug)]
+pub struct ErrorMessage(pub String);
+
+/// Sets the span status description of server error responses without an
+/// [`ErrorMessage`] to the field at the dotted `path` (e.g. `error.message`)
+/// of a JSON response body, by adding it as the [`ErrorMessage`].
+///
+/// Must run inside `TraceLayer`, where [`crate::http_trace::HttpOnResponse`]
+/// marks the span as an error with that message.
+pub async fn record_error_status<B>(
+    State(path): State<Arc<[String]>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(req).await;
+    if !response.status().is_server_error() || !span::is_recording() {
+        return response;
+    }
+
+    if response.extensions().get::<ErrorMessage>().is_some() {
+        return response;
+    }
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let is_small = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_ERROR_BODY_BYTES);
+    if !is_json || !is_small {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read error response body: {e}");
+            return Response::from_parts(parts, body::boxed(Full::default()));
+        }
+    };
+
+    let description = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|json| {
+            path.iter()
+                .try_fold(&json, |value, field| value.get(field))
+                .map(|value| match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+        });
+    let mut response = Response::from_parts(parts, body::boxed(Full::from(bytes)));
+    if let Some(description) = description {
+        response.extensions_mut().insert(ErrorMessage(description));
+    }
+    response
+}
+
+/// Largest body [`capture_bodies`] buffers. Bodies of unknown or larger size
+/// pass through uncaptured.
+const MAX_CAPTURED_BODY_BUFFER_BYTES: u64 = 1024 * 1024;
+
+/// Which request and response bodies [`capture_bodies`] records.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodyCapture {
+    /// Route templates to capture, exact or ending in `*` to match a prefix.
+    pub routes: Vec<String>,
+    /// Bytes of each body recorded, the rest is cut off.
+    pub max_bytes: usize,
+    /// Media types to capture, exact or ending in `*`, e.g. `text/*`.
+    pub content_types: Vec<String>,
+}
+
+impl BodyCapture {
+    fn captures_route(&self, route: &str) -> bool {
+        self.routes
+            .iter()
+            .any(|pattern| matches_pattern(pattern, route))
+    }
+
+    fn captures(&self, headers: &HeaderMap) -> bool {
+        let media_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        media_type.is_some_and(|media_type| {
+            self.content_types
+                .iter()
+                .any(|pattern| matches_pattern(&pattern.to_ascii_lowercase(), &media_type))
+        })
+    }
+
+    /// Buffers `body` when it is capturable, recording up to `max_bytes` of
+    /// it on the current span as `{prefix}.body` and its length as
+    /// `{prefix}.body.size`. Hands `body` back untouched otherwise.
+    async fn record<B>(&self, prefix: &str, headers: &HeaderMap, body: B) -> Result<Bytes, B>
+    where
+        B: HttpBody<Data = Bytes>,
+        B::Error: Display,
+    {
+        let fits = body
+            .size_hint()
+            .upper()
+            .is_some_and(|len| len <= MAX_CAPTURED_BODY_BUFFER_BYTES);
+        if !fits || !self.captures(headers) {
+            return Err(body);
+        }
+
+        let bytes = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!("Failed to read {prefix} body: {e}");
+                return Ok(Bytes::new());
+            }
+        };
+        let span = Span::current();
+        let shown = &bytes[..bytes.len().min(self.max_bytes)];
+        span.set_attribute(
+            Key::new(format!("{prefix}.body")),
+            String::from_utf8_lossy(shown).into_owned(),
+        );
+        span.set_attribute(Key::new(format!("{prefix}.body.size")), bytes.len() as i64);
+        Ok(bytes)
+    }
+}
+
+/// Whether `value` equals `pattern`, or starts with it when it ends in `*`.
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    }
+}
+
+/// Records the start of request and response bodies on the current span as
+/// `http.request.body` and `http.response.body`, for debugging serialization
+/// issues. Only for the routes, and the bodies of the content types, that
+/// `capture` selects. Bodies can hold personal data, so keep this to debugging
+/// sessions. Must run inside `TraceLayer`.
+pub async fn capture_bodies(
+    State(capture): State<Reloadable<BodyCapture>>,
+    route: Option<MatchedPath>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let capture = capture.get();
+    let capturing =
+        route.is_some_and(|route| capture.captures_route(route.as_str())) && span::is_recording();
+    if !capturing {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match capture.record("http.request", &parts.headers, body).await {
+        Ok(bytes) => Body::from(bytes),
+        Err(body) => body,
+    };
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    let body = match capture.record("http.response", &parts.headers, body).await {
+        Ok(bytes) => body::boxed(Full::from(bytes)),
+        Err(body) => body,
+    };
+    Response::from_parts(parts, body)
+}
+
+/// Request and response header carrying the correlation id.
+pub static CORRELATION_ID_HEADER: HeaderName = HeaderName::from_static("x-correlation-id");
+
+/// Span attribute and baggage key holding the correlation id.
+pub const CORRELATION_ID_KEY: &str = "correlation.id";
+
+/// A user facing id tying a request's logs, trace and response together.
+#[derive(Clone, Debug)]
+pub struct CorrelationId(pub String);
+
+/// Takes the correlation id from the `x-correlation-id` request header, or
+/// generates one, and attaches it everywhere the request is observed: as the
+/// `correlation.id` attribute of the `TraceLayer` request span and so on its
+/// log lines, as baggage in the request's context, and on the response header.
+///
+/// Must run outside `TraceLayer`, which reads the id when the span starts.
+pub async fn correlation_id<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let id = req
+        .headers()
+        .get(&CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+    req.extensions_mut().insert(CorrelationId(id.clone()));
+
+    let cx = Context::current_with_baggage([KeyValue::new(CORRELATION_ID_KEY, id.clone())]);
+    let mut response = next.run(req).with_context(cx).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response
+            .headers_mut()
+            .insert(CORRELATION_ID_HEADER.clone(), value);
+    }
+
+    response
+}
+
+/// Selects which request attributes [`HttpMakeSpan`](crate::http_trace::HttpMakeSpan)
+/// records on the HTTP server span. The route, scheme, server address and
+/// response status are always recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestSpanFields {
+    /// `http.request.method`.
+    pub method: bool,
+    /// `url.path` and `url.query`.
+    pub uri: bool,
+    /// `network.protocol.version`.
+    pub version: bool,
+    /// `http.request.header.<name>` for every request header but the
+    /// sensitive ones, such as `authorization`. Off by default.
+    pub headers: bool,
+}
+
+impl Default for RequestSpanFields {
+    fn default() -> Self {
+        Self {
+            method: true,
+            uri: true,
+            version: true,
+            headers: false,
+        }
+    }
+}
+
+impl FromStr for RequestSpanFields {
+    type Err = String;
+
+    /// Parses a comma separated list such as `method,uri`, or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Self {
+            method: false,
+            uri: false,
+            version: false,
+            headers: false,
+        };
+        for field in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            match field {
+                "method" => fields.method = true,
+                "uri" => fields.uri = true,
+                "version" => fields.version = true,
+                "headers" => fields.headers = true,
+                "none" => {}
+                other => return Err(format!("unknown request span field {other}")),
+            }
+        }
+        Ok(fields)
+    }
+}
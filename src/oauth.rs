@@ -0,0 +1,113 @@
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::export::HeaderProvider;
+
+/// Refresh this long before the token expires, so no export races its expiry.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Shortest wait between refreshes, for tokens with very short lifetimes.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Wait before retrying a failed token request.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Lifetime assumed for tokens whose response has no `expires_in`.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Client credentials for fetching export access tokens from an OAuth2 token
+/// endpoint.
+#[derive(Clone)]
+pub struct OAuth2Settings {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Space separated scopes to request, if the token endpoint needs them.
+    pub scope: Option<String>,
+}
+
+/// The current access token, sent as `Authorization: Bearer <token>` with
+/// every export. Empty until the first token arrives.
+#[derive(Debug, Default)]
+pub struct OAuth2Token {
+    authorization: RwLock<Option<HeaderValue>>,
+}
+
+impl HeaderProvider for OAuth2Token {
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        self.authorization
+            .read()
+            .unwrap()
+            .iter()
+            .map(|value| (AUTHORIZATION, value.clone()))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// Fetches a token right away and refreshes it before it expires, on a
+/// background task that lives as long as the runtime.
+pub fn start(settings: OAuth2Settings, client: reqwest::Client) -> Arc<OAuth2Token> {
+    let token = Arc::new(OAuth2Token::default());
+    tokio::spawn(refresh(settings, client, token.clone()));
+    token
+}
+
+async fn refresh(settings: OAuth2Settings, client: reqwest::Client, token: Arc<OAuth2Token>) {
+    loop {
+        let wait = match fetch(&settings, &client).await {
+            Ok(response) => {
+                match HeaderValue::from_str(&format!("Bearer {}", response.access_token)) {
+                    Ok(mut value) => {
+                        value.set_sensitive(true);
+                        *token.authorization.write().unwrap() = Some(value);
+                    }
+                    Err(e) => tracing::warn!("OAuth2 access token is not a valid header: {e}"),
+                }
+                response
+                    .expires_in
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_TOKEN_LIFETIME)
+                    .saturating_sub(REFRESH_MARGIN)
+                    .max(MIN_REFRESH_INTERVAL)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to fetch OAuth2 access token: {e}");
+                RETRY_DELAY
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+async fn fetch(
+    settings: &OAuth2Settings,
+    client: &reqwest::Client,
+) -> Result<TokenResponse, String> {
+    let mut form = vec![("grant_type", "client_credentials")];
+    if let Some(scope) = &settings.scope {
+        form.push(("scope", scope));
+    }
+
+    let body = client
+        .post(&settings.token_url)
+        .basic_auth(&settings.client_id, Some(&settings.client_secret))
+        .form(&form)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
@@ -0,0 +1,130 @@
+//! OTLP/JSON encoding of trace export requests, following the protobuf JSON
+//! mapping with the OTLP exceptions: ids are hex strings and enums are numbers.
+use opentelemetry_proto::tonic::{
+    collector::trace::v1::ExportTraceServiceRequest,
+    common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue},
+    resource::v1::Resource,
+    trace::v1::{span, ResourceSpans, ScopeSpans, Span, Status},
+};
+use serde_json::{json, Value};
+
+pub fn encode_request(request: &ExportTraceServiceRequest) -> Vec<u8> {
+    let value = json!({
+        "resourceSpans": request.resource_spans.iter().map(resource_spans).collect::<Vec<_>>(),
+    });
+    serde_json::to_vec(&value).expect("JSON values always serialize")
+}
+
+fn resource_spans(rs: &ResourceSpans) -> Value {
+    json!({
+        "resource": rs.resource.as_ref().map(resource),
+        "scopeSpans": rs.scope_spans.iter().map(scope_spans).collect::<Vec<_>>(),
+        "schemaUrl": rs.schema_url,
+    })
+}
+
+fn resource(resource: &Resource) -> Value {
+    json!({
+        "attributes": attributes(&resource.attributes),
+        "droppedAttributesCount": resource.dropped_attributes_count,
+    })
+}
+
+fn scope_spans(ss: &ScopeSpans) -> Value {
+    json!({
+        "scope": ss.scope.as_ref().map(scope),
+        "spans": ss.spans.iter().map(span).collect::<Vec<_>>(),
+        "schemaUrl": ss.schema_url,
+    })
+}
+
+fn scope(scope: &InstrumentationScope) -> Value {
+    json!({
+        "name": scope.name,
+        "version": scope.version,
+        "attributes": attributes(&scope.attributes),
+        "droppedAttributesCount": scope.dropped_attributes_count,
+    })
+}
+
+fn span(span: &Span) -> Value {
+    json!({
+        "traceId": hex(&span.trace_id),
+        "spanId": hex(&span.span_id),
+        "traceState": span.trace_state,
+        "parentSpanId": hex(&span.parent_span_id),
+        "name": span.name,
+        "kind": span.kind,
+        "startTimeUnixNano": span.start_time_unix_nano.to_string(),
+        "endTimeUnixNano": span.end_time_unix_nano.to_string(),
+        "attributes": attributes(&span.attributes),
+        "droppedAttributesCount": span.dropped_attributes_count,
+        "events": span.events.iter().map(event).collect::<Vec<_>>(),
+        "droppedEventsCount": span.dropped_events_count,
+        "links": span.links.iter().map(link).collect::<Vec<_>>(),
+        "droppedLinksCount": span.dropped_links_count,
+        "status": span.status.as_ref().map(status),
+    })
+}
+
+fn event(event: &span::Event) -> Value {
+    json!({
+        "timeUnixNano": event.time_unix_nano.to_string(),
+        "name": event.name,
+        "attributes": attributes(&event.attributes),
+        "droppedAttributesCount": event.dropped_attributes_count,
+    })
+}
+
+fn link(link: &span::Link) -> Value {
+    json!({
+        "traceId": hex(&link.trace_id),
+        "spanId": hex(&link.span_id),
+        "traceState": link.trace_state,
+        "attributes": attributes(&link.attributes),
+        "droppedAttributesCount": link.dropped_attributes_count,
+    })
+}
+
+fn status(status: &Status) -> Value {
+    json!({
+        "message": status.message,
+        "code": status.code,
+    })
+}
+
+fn attributes(attributes: &[KeyValue]) -> Value {
+    attributes
+        .iter()
+        .map(|kv| {
+            json!({
+                "key": kv.key,
+                "value": kv.value.as_ref().map(any_value),
+            })
+        })
+        .collect()
+}
+
+fn any_value(value: &AnyValue) -> Value {
+    match &value.value {
+        Some(any_value::Value::StringValue(v)) => json!({ "stringValue": v }),
+        Some(any_value::Value::BoolValue(v)) => json!({ "boolValue": v }),
+        Some(any_value::Value::IntValue(v)) => json!({ "intValue": v.to_string() }),
+        Some(any_value::Value::DoubleValue(v)) => json!({ "doubleValue": v }),
+        Some(any_value::Value::ArrayValue(v)) => json!({
+            "arrayValue": { "values": v.values.iter().map(any_value).collect::<Vec<_>>() },
+        }),
+        Some(any_value::Value::KvlistValue(v)) => json!({
+            "kvlistValue": { "values": attributes(&v.values) },
+        }),
+        Some(any_value::Value::BytesValue(v)) => {
+            use base64::{engine::general_purpose, Engine};
+            json!({ "bytesValue": general_purpose::STANDARD.encode(v) })
+        }
+        None => json!({}),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
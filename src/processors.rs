@@ -0,0 +1,850 @@
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{EvictedHashMap, EvictedQueue, Span, SpanProcessor},
+    },
+    trace::{Event, Link, Span as _, SpanContext, SpanId, SpanKind, Status, TraceResult},
+    Array, Context, Key, KeyValue, StringValue, Value,
+};
+use regex::{Captures, Regex};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::clock;
+use crate::config::{Settings, SpanLimitsPreset, TEMPO_MAX_ATTRIBUTE_VALUE_LENGTH};
+use crate::sampling::TokenBucket;
+use crate::tail_sampling::TailSampler;
+
+static RATE_LIMITED_SPANS: AtomicU64 = AtomicU64::new(0);
+static OVERSIZED_SPANS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of spans dropped by [`RateLimitProcessor`] since startup.
+pub fn rate_limited_spans() -> u64 {
+    RATE_LIMITED_SPANS.load(Ordering::Relaxed)
+}
+
+/// Total number of spans dropped by [`OversizedSpanGuard`] since startup.
+pub fn oversized_spans() -> u64 {
+    OVERSIZED_SPANS.load(Ordering::Relaxed)
+}
+
+/// Lets a chain of boxed processors be handed to the tracer provider, which
+/// only accepts concrete [`SpanProcessor`] types.
+#[derive(Debug)]
+pub struct BoxedProcessor(pub Box<dyn SpanProcessor>);
+
+impl SpanProcessor for BoxedProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.0.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        self.0.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.0.shutdown()
+    }
+}
+
+/// Hands every span to each of several processors, such as the batch
+/// processors of the primary and secondary endpoints, so the processors
+/// wrapping it run once for all of them.
+#[derive(Debug)]
+pub struct FanOut(pub Vec<Box<dyn SpanProcessor>>);
+
+impl SpanProcessor for FanOut {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        for processor in &self.0 {
+            processor.on_start(span, cx);
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if let Some((last, rest)) = self.0.split_last() {
+            for processor in rest {
+                processor.on_end(span.clone());
+            }
+            last.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.0
+            .iter()
+            .map(|processor| processor.force_flush())
+            .fold(Ok(()), Result::and)
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.0
+            .iter_mut()
+            .map(|processor| processor.shutdown())
+            .fold(Ok(()), Result::and)
+    }
+}
+
+/// Attributes carried in a span's parent context and added to the span when
+/// it starts. See [`crate::span::set_context_attributes`].
+#[derive(Clone, Debug, Default)]
+pub struct ContextAttributes(pub Vec<KeyValue>);
+
+/// Adds the [`ContextAttributes`] of a span's parent context to the span,
+/// overriding attributes of the same key set on the span itself.
+#[derive(Debug)]
+pub struct ContextAttributesProcessor {
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl ContextAttributesProcessor {
+    pub fn new(inner: Box<dyn SpanProcessor>) -> Self {
+        Self { inner }
+    }
+}
+
+impl SpanProcessor for ContextAttributesProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if let Some(ContextAttributes(attributes)) = cx.get() {
+            for kv in attributes {
+                span.set_attribute(kv.clone());
+            }
+        }
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Truncates string attribute values of the span, its events and its links to
+/// a maximum length before handing the span to the wrapped processor.
+#[derive(Debug)]
+pub struct TruncateAttributes {
+    inner: Box<dyn SpanProcessor>,
+    max_value_length: usize,
+}
+
+impl TruncateAttributes {
+    pub fn new(inner: Box<dyn SpanProcessor>, max_value_length: usize) -> Self {
+        Self {
+            inner,
+            max_value_length,
+        }
+    }
+}
+
+impl SpanProcessor for TruncateAttributes {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let max = self.max_value_length;
+        let truncate = |kv: KeyValue| Some(KeyValue::new(kv.key, truncate_value(kv.value, max)));
+        map_attributes(&mut span, truncate);
+        map_event_attributes(&mut span, truncate);
+        map_link_attributes(&mut span, truncate);
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// How [`AttributeKeyPolicy`] treats keys that break the naming policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyPolicyMode {
+    Off,
+    /// Export the attribute but log a warning.
+    Log,
+    /// Remove the attribute and log a warning.
+    Drop,
+}
+
+impl FromStr for KeyPolicyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(KeyPolicyMode::Off),
+            "log" => Ok(KeyPolicyMode::Log),
+            "drop" => Ok(KeyPolicyMode::Drop),
+            other => Err(format!("expected off, log or drop, got {other}")),
+        }
+    }
+}
+
+/// Checks attribute keys against the naming policy: lowercase, dot separated
+/// namespaces of `[a-z0-9_]`, e.g. `http.response.status_code`.
+#[derive(Debug)]
+pub struct AttributeKeyPolicy {
+    inner: Box<dyn SpanProcessor>,
+    mode: KeyPolicyMode,
+}
+
+impl AttributeKeyPolicy {
+    pub fn new(inner: Box<dyn SpanProcessor>, mode: KeyPolicyMode) -> Self {
+        Self { inner, mode }
+    }
+}
+
+impl SpanProcessor for AttributeKeyPolicy {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let name = span.name.clone();
+        let mode = self.mode;
+        map_attributes(&mut span, |kv| {
+            if is_conforming_key(&kv.key) {
+                return Some(kv);
+            }
+            tracing::warn!(
+                span = %name,
+                key = %kv.key,
+                dropped = mode == KeyPolicyMode::Drop,
+                "Span attribute key does not follow the naming policy"
+            );
+            (mode != KeyPolicyMode::Drop).then_some(kv)
+        });
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+fn is_conforming_key(key: &Key) -> bool {
+    key.as_str().split('.').all(|segment| {
+        !segment.is_empty()
+            && segment
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+    })
+}
+
+/// Strips attributes whose keys match any of a list of patterns from spans and
+/// their events, as a last line of defence for data that must never leave the
+/// process. A `*` in a pattern matches any run of characters, so `*.email`
+/// matches `user.email` and `customer.contact.email`.
+#[derive(Debug)]
+pub struct AttributeDenylist {
+    inner: Box<dyn SpanProcessor>,
+    patterns: Vec<String>,
+}
+
+impl AttributeDenylist {
+    pub fn new(inner: Box<dyn SpanProcessor>, patterns: Vec<String>) -> Self {
+        Self { inner, patterns }
+    }
+
+    fn allows(&self, key: &Key) -> bool {
+        !self
+            .patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, key.as_str()))
+    }
+}
+
+impl SpanProcessor for AttributeDenylist {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        map_attributes(&mut span, |kv| self.allows(&kv.key).then_some(kv));
+        map_event_attributes(&mut span, |kv| self.allows(&kv.key).then_some(kv));
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Replacement for attribute values scrubbed by [`Redactor`].
+pub const REDACTED: &str = "[REDACTED]";
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}";
+const CARD_NUMBER_PATTERN: &str = r"\b\d(?:[ -]?\d){12,18}\b";
+
+/// Something [`Redactor`] scrubs out of string attribute values.
+#[derive(Clone, Debug)]
+pub enum RedactPattern {
+    /// Email addresses.
+    Email,
+    /// Runs of 13 to 19 digits, optionally split by spaces or dashes, that
+    /// pass the Luhn check. Ids and timestamps rarely do.
+    CardNumber,
+    /// Any match of a regular expression.
+    Regex(Regex),
+}
+
+impl RedactPattern {
+    /// The patterns used when none are configured.
+    pub fn defaults() -> Vec<Self> {
+        vec![RedactPattern::Email, RedactPattern::CardNumber]
+    }
+
+    fn compile(&self) -> Regex {
+        match self {
+            RedactPattern::Email => Regex::new(EMAIL_PATTERN).expect("valid email pattern"),
+            RedactPattern::CardNumber => {
+                Regex::new(CARD_NUMBER_PATTERN).expect("valid card number pattern")
+            }
+            RedactPattern::Regex(regex) => regex.clone(),
+        }
+    }
+}
+
+impl FromStr for RedactPattern {
+    type Err = String;
+
+    /// Parses `email`, `card`, or a regular expression.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(RedactPattern::Email),
+            "card" => Ok(RedactPattern::CardNumber),
+            regex => Regex::new(regex)
+                .map(RedactPattern::Regex)
+                .map_err(|e| format!("invalid redaction pattern {regex}: {e}")),
+        }
+    }
+}
+
+/// What [`Redactor`] scrubs: whole values of attributes whose keys match
+/// `keys`, and any part of a string value matching `patterns`.
+#[derive(Clone, Debug)]
+pub struct RedactionRules {
+    /// Attribute key patterns, with `*` as in [`AttributeDenylist`].
+    pub keys: Vec<String>,
+    pub patterns: Vec<RedactPattern>,
+}
+
+/// Replaces sensitive attribute values on spans and their events with
+/// [`REDACTED`] before export. Unlike [`AttributeDenylist`] the attribute is
+/// kept, so it is still visible that a value was there.
+#[derive(Debug)]
+pub struct Redactor {
+    inner: Box<dyn SpanProcessor>,
+    keys: Vec<String>,
+    patterns: Vec<(RedactPattern, Regex)>,
+}
+
+impl Redactor {
+    pub fn new(inner: Box<dyn SpanProcessor>, rules: &RedactionRules) -> Self {
+        Self {
+            inner,
+            keys: rules.keys.clone(),
+            patterns: rules
+                .patterns
+                .iter()
+                .map(|pattern| (pattern.clone(), pattern.compile()))
+                .collect(),
+        }
+    }
+
+    fn redact(&self, kv: KeyValue) -> KeyValue {
+        if self
+            .keys
+            .iter()
+            .any(|pattern| glob_match(pattern, kv.key.as_str()))
+        {
+            return KeyValue::new(kv.key, REDACTED);
+        }
+        let value = match kv.value {
+            Value::String(s) => Value::String(self.scrub(s)),
+            Value::Array(Array::String(values)) => Value::Array(Array::String(
+                values.into_iter().map(|s| self.scrub(s)).collect(),
+            )),
+            value => value,
+        };
+        KeyValue::new(kv.key, value)
+    }
+
+    fn scrub(&self, value: StringValue) -> StringValue {
+        let mut scrubbed = Cow::Borrowed(value.as_str());
+        for (pattern, regex) in &self.patterns {
+            let replaced = regex.replace_all(&scrubbed, |captures: &Captures| {
+                let found = &captures[0];
+                match pattern {
+                    RedactPattern::CardNumber if !luhn_valid(found) => found.to_owned(),
+                    _ => String::from(REDACTED),
+                }
+            });
+            if let Cow::Owned(replaced) = replaced {
+                scrubbed = Cow::Owned(replaced);
+            }
+        }
+        match scrubbed {
+            Cow::Borrowed(_) => value,
+            Cow::Owned(scrubbed) => scrubbed.into(),
+        }
+    }
+}
+
+impl SpanProcessor for Redactor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        map_attributes(&mut span, |kv| Some(self.redact(kv)));
+        map_event_attributes(&mut span, |kv| Some(self.redact(kv)));
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Whether the digits in `s` pass the Luhn checksum used by card numbers.
+fn luhn_valid(s: &str) -> bool {
+    let sum: u32 = s
+        .bytes()
+        .rev()
+        .filter(u8::is_ascii_digit)
+        .map(|b| u32::from(b - b'0'))
+        .enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len())
+                .filter(|&i| text.is_char_boundary(i))
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+    }
+}
+
+/// Drops spans shorter than a minimum duration. Root spans, server spans and
+/// spans with an error status are always kept.
+///
+/// Children of a dropped span show up in Tempo with a missing parent, so keep
+/// the threshold below anything whose subtree matters.
+#[derive(Debug)]
+pub struct MinDurationFilter {
+    inner: Box<dyn SpanProcessor>,
+    min_duration: Duration,
+}
+
+impl MinDurationFilter {
+    pub fn new(inner: Box<dyn SpanProcessor>, min_duration: Duration) -> Self {
+        Self {
+            inner,
+            min_duration,
+        }
+    }
+}
+
+impl SpanProcessor for MinDurationFilter {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let always_keep = span.parent_span_id == SpanId::INVALID
+            || span.span_kind == SpanKind::Server
+            || matches!(span.status, Status::Error { .. });
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+
+        if always_keep || duration >= self.min_duration {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// What [`OversizedSpanGuard`] does with a span above the size limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizedSpanMode {
+    /// Truncate attribute values, dropping the span if it is still too large.
+    Truncate,
+    /// Drop the span.
+    Drop,
+}
+
+impl FromStr for OversizedSpanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(OversizedSpanMode::Truncate),
+            "drop" => Ok(OversizedSpanMode::Drop),
+            other => Err(format!("expected truncate or drop, got {other}")),
+        }
+    }
+}
+
+/// Keeps a single huge span from getting its whole batch rejected by the
+/// collector. Spans whose estimated size exceeds `max_bytes` are truncated or
+/// dropped, and logged either way.
+#[derive(Debug)]
+pub struct OversizedSpanGuard {
+    inner: Box<dyn SpanProcessor>,
+    max_bytes: usize,
+    max_value_length: usize,
+    mode: OversizedSpanMode,
+}
+
+impl OversizedSpanGuard {
+    pub fn new(
+        inner: Box<dyn SpanProcessor>,
+        max_bytes: usize,
+        max_value_length: usize,
+        mode: OversizedSpanMode,
+    ) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            max_value_length,
+            mode,
+        }
+    }
+}
+
+impl SpanProcessor for OversizedSpanGuard {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let size = estimated_size(&span);
+        if size <= self.max_bytes {
+            return self.inner.on_end(span);
+        }
+
+        if self.mode == OversizedSpanMode::Truncate {
+            let max = self.max_value_length;
+            map_attributes(&mut span, |kv| {
+                Some(KeyValue::new(kv.key, truncate_value(kv.value, max)))
+            });
+            map_event_attributes(&mut span, |kv| {
+                Some(KeyValue::new(kv.key, truncate_value(kv.value, max)))
+            });
+
+            let truncated_size = estimated_size(&span);
+            if truncated_size <= self.max_bytes {
+                tracing::warn!(
+                    span = %span.name,
+                    size,
+                    truncated_size,
+                    "Truncated oversized span attributes"
+                );
+                return self.inner.on_end(span);
+            }
+        }
+
+        OVERSIZED_SPANS.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(span = %span.name, size, "Dropped oversized span");
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Rough encoded size of a span: its name plus the keys and values of its
+/// attributes, events and links. Ids, timestamps and the resource are left out.
+fn estimated_size(span: &SpanData) -> usize {
+    fn kv_size<'a>(kvs: impl Iterator<Item = (&'a Key, &'a Value)>) -> usize {
+        kvs.map(|(key, value)| key.as_str().len() + value_size(value))
+            .sum()
+    }
+
+    span.name.len()
+        + kv_size(span.attributes.iter())
+        + span
+            .events
+            .iter()
+            .map(|event| {
+                event.name.len() + kv_size(event.attributes.iter().map(|kv| (&kv.key, &kv.value)))
+            })
+            .sum::<usize>()
+        + span
+            .links
+            .iter()
+            .map(|link| kv_size(link.attributes.iter().map(|kv| (&kv.key, &kv.value))))
+            .sum::<usize>()
+}
+
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.as_str().len(),
+        Value::Array(Array::String(values)) => values.iter().map(|s| s.as_str().len()).sum(),
+        value => value.to_string().len(),
+    }
+}
+
+/// Caps the number of spans exported per second regardless of sampling,
+/// dropping and counting the excess. Bursts of up to one second's worth of
+/// spans are let through, and at least one span at a time below one per
+/// second.
+#[derive(Debug)]
+pub struct RateLimitProcessor {
+    inner: Box<dyn SpanProcessor>,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimitProcessor {
+    pub fn new(inner: Box<dyn SpanProcessor>, spans_per_second: f64) -> Self {
+        Self {
+            inner,
+            bucket: Mutex::new(TokenBucket::new(spans_per_second, clock::now())),
+        }
+    }
+}
+
+impl SpanProcessor for RateLimitProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.bucket.lock().unwrap().try_take(clock::now()) {
+            self.inner.on_end(span);
+        } else {
+            RATE_LIMITED_SPANS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Rebuilds the span's attribute map, keeping only the attributes `f` returns.
+/// Attributes `f` removes are added to the map's dropped count.
+pub(crate) fn map_attributes<F>(span: &mut SpanData, mut f: F)
+where
+    F: FnMut(KeyValue) -> Option<KeyValue>,
+{
+    let kept: HashMap<Key, Value> = span
+        .attributes
+        .iter()
+        .filter_map(|(key, value)| f(KeyValue::new(key.clone(), value.clone())))
+        .map(|kv| (kv.key, kv.value))
+        .collect();
+    let removed = (span.attributes.len() - kept.len()) as u32;
+    let dropped = span.attributes.dropped_count() + removed;
+
+    // `EvictedHashMap` only counts what it evicts itself, so placeholders
+    // inserted first are evicted by the kept attributes to carry the count.
+    let mut attributes = EvictedHashMap::new(kept.len() as u32, kept.len());
+    for i in 0..dropped {
+        attributes.insert(KeyValue::new(format!("\0dropped.{i}"), true));
+    }
+    for (key, value) in kept {
+        attributes.insert(KeyValue::new(key, value));
+    }
+    span.attributes = attributes;
+}
+
+/// Like [`map_attributes`], for the attributes of the span's events.
+pub(crate) fn map_event_attributes<F>(span: &mut SpanData, mut f: F)
+where
+    F: FnMut(KeyValue) -> Option<KeyValue>,
+{
+    let mut events: Vec<_> = span.events.iter().cloned().collect();
+    for event in &mut events {
+        let len = event.attributes.len();
+        event.attributes = event.attributes.drain(..).filter_map(&mut f).collect();
+        event.dropped_attributes_count += (len - event.attributes.len()) as u32;
+    }
+    span.events = evicted_queue(events, span.events.dropped_count(), Event::with_name(""));
+}
+
+/// Like [`map_attributes`], for the attributes of the span's links.
+pub(crate) fn map_link_attributes<F>(span: &mut SpanData, mut f: F)
+where
+    F: FnMut(KeyValue) -> Option<KeyValue>,
+{
+    let mut links: Vec<_> = span.links.iter().cloned().collect();
+    for link in &mut links {
+        let len = link.attributes.len();
+        link.attributes = link.attributes.drain(..).filter_map(&mut f).collect();
+        link.dropped_attributes_count += (len - link.attributes.len()) as u32;
+    }
+    let placeholder = Link::new(SpanContext::empty_context(), Vec::new());
+    span.links = evicted_queue(links, span.links.dropped_count(), placeholder);
+}
+
+/// An `EvictedQueue` of `items` reporting `dropped` items dropped, carried
+/// the way [`map_attributes`] does, with copies of `placeholder` evicted by
+/// the items.
+fn evicted_queue<T: Clone>(mut items: Vec<T>, dropped: u32, placeholder: T) -> EvictedQueue<T> {
+    let mut queue = EvictedQueue::new(items.len() as u32);
+    queue.append_vec(&mut vec![placeholder; dropped as usize]);
+    queue.append_vec(&mut items);
+    queue
+}
+
+fn truncate_value(value: Value, max: usize) -> Value {
+    match value {
+        Value::String(s) if s.as_str().len() > max => Value::String(truncate_str(&s, max)),
+        Value::Array(Array::String(values)) => Value::Array(Array::String(
+            values
+                .into_iter()
+                .map(|s| {
+                    if s.as_str().len() > max {
+                        truncate_str(&s, max)
+                    } else {
+                        s
+                    }
+                })
+                .collect(),
+        )),
+        value => value,
+    }
+}
+
+fn truncate_str(s: &StringValue, max: usize) -> StringValue {
+    let s = s.as_str();
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_owned().into()
+}
+
+/// Wraps an exporting processor in the filtering and rewriting processors the
+/// settings enable. Every export destination gets the same chain, so they all
+/// receive the same spans.
+pub(crate) fn wrap_processor(
+    mut processor: Box<dyn SpanProcessor>,
+    settings: &Settings,
+) -> Box<dyn SpanProcessor> {
+    if settings.span_limits == SpanLimitsPreset::Tempo {
+        processor = Box::new(TruncateAttributes::new(
+            processor,
+            TEMPO_MAX_ATTRIBUTE_VALUE_LENGTH,
+        ));
+    }
+
+    // Processors wrap each other, so the last one added sees spans first.
+    if let Some(max_value_length) = settings.attribute_value_length_limit {
+        processor = Box::new(TruncateAttributes::new(processor, max_value_length));
+    }
+
+    if !settings.context_attributes.is_empty() {
+        processor = Box::new(ContextAttributesProcessor::new(processor));
+    }
+
+    if let Some(max_bytes) = settings.max_span_bytes {
+        processor = Box::new(OversizedSpanGuard::new(
+            processor,
+            max_bytes,
+            TEMPO_MAX_ATTRIBUTE_VALUE_LENGTH,
+            settings.oversized_spans,
+        ));
+    }
+
+    if let Some(rate) = settings.max_spans_per_second {
+        processor = Box::new(RateLimitProcessor::new(processor, rate));
+    }
+
+    if let Some(latency) = settings.tail_sampling_latency {
+        processor = Box::new(TailSampler::new(
+            processor,
+            latency,
+            settings.tail_sampling_window,
+            settings.tail_sampling_max_spans,
+        ));
+    }
+
+    if let Some(min_duration) = settings.min_span_duration {
+        processor = Box::new(MinDurationFilter::new(processor, min_duration));
+    }
+
+    if settings.attribute_key_policy != KeyPolicyMode::Off {
+        processor = Box::new(AttributeKeyPolicy::new(
+            processor,
+            settings.attribute_key_policy,
+        ));
+    }
+
+    if !settings.attribute_denylist.is_empty() {
+        processor = Box::new(AttributeDenylist::new(
+            processor,
+            settings.attribute_denylist.clone(),
+        ));
+    }
+
+    if let Some(rules) = &settings.redaction {
+        processor = Box::new(Redactor::new(processor, rules));
+    }
+
+    processor
+}
@@ -0,0 +1,427 @@
+//! Prometheus scrape endpoint for the metrics in [`crate::metrics`], for
+//! environments without an OTLP metrics backend. Enable it with
+//! `OtelTempoPrometheus` and mount [`router`] on the application.
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use opentelemetry::{
+    metrics::Result as MetricsResult,
+    sdk::{
+        metrics::{
+            data::{self, ResourceMetrics, Temporality},
+            reader::{AggregationSelector, MetricProducer, MetricReader, TemporalitySelector},
+            Aggregation, InstrumentKind, ManualReader, Pipeline,
+        },
+        Resource,
+    },
+    trace::{SpanId, TraceId},
+    Context, Key, Value,
+};
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex, Weak},
+    time::UNIX_EPOCH,
+};
+
+use crate::metrics;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Content type of the OpenMetrics format.
+pub const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The reader registered with the meter provider, once telemetry is installed
+/// with `OtelTempoPrometheus` set.
+static READER: Mutex<Option<PrometheusReader>> = Mutex::new(None);
+
+/// Reads metrics on demand for [`router`]. Cloning shares the underlying
+/// reader, so the scrape handler reads what the meter provider records.
+#[derive(Clone, Debug)]
+pub struct PrometheusReader(Arc<ManualReader>);
+
+impl PrometheusReader {
+    /// Creates the reader and makes it the one [`router`] scrapes. Hand it to
+    /// the meter provider.
+    pub fn install() -> Self {
+        let reader = Self(Arc::new(
+            ManualReader::builder()
+                .with_aggregation_selector(Box::new(metrics::aggregation))
+                .build(),
+        ));
+        *READER.lock().unwrap() = Some(reader.clone());
+        reader
+    }
+
+    /// The current metrics in `format`.
+    pub fn render(&self, format: Format) -> MetricsResult<String> {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        self.0.collect(&mut metrics)?;
+        metrics::attach_exemplars(&mut metrics);
+        Ok(render(&metrics, format))
+    }
+}
+
+impl TemporalitySelector for PrometheusReader {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+impl AggregationSelector for PrometheusReader {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.0.aggregation(kind)
+    }
+}
+
+impl MetricReader for PrometheusReader {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.0.register_pipeline(pipeline);
+    }
+
+    fn register_producer(&self, producer: Box<dyn MetricProducer>) {
+        self.0.register_producer(producer);
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricsResult<()> {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self, cx: &Context) -> MetricsResult<()> {
+        self.0.force_flush(cx)
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.0.shutdown()
+    }
+}
+
+/// A router serving `GET /metrics`, to merge into the application. Responds
+/// with 404 while the exporter is not enabled.
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new().route("/metrics", get(scrape))
+}
+
+async fn scrape(headers: HeaderMap) -> Response {
+    let reader = READER.lock().unwrap().clone();
+    let Some(reader) = reader else {
+        return (StatusCode::NOT_FOUND, "Prometheus exporter is not enabled").into_response();
+    };
+    let format = Format::accepted(&headers);
+    match reader.render(format) {
+        Ok(body) => ([(header::CONTENT_TYPE, format.content_type())], body).into_response(),
+        Err(e) => {
+            tracing::warn!("Failed to collect metrics for scrape: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The exposition formats served. Only OpenMetrics carries exemplars, and
+/// Prometheus asks for it when exemplar storage is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+}
+
+impl Format {
+    /// OpenMetrics when the scraper's `Accept` header lists it.
+    fn accepted(headers: &HeaderMap) -> Self {
+        let accepts_open_metrics = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("application/openmetrics-text"));
+        if accepts_open_metrics {
+            Format::OpenMetrics
+        } else {
+            Format::Text
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => CONTENT_TYPE,
+            Format::OpenMetrics => OPEN_METRICS_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Renders `metrics`. Resource attributes go on a `target_info` metric, as
+/// the OpenTelemetry to Prometheus mapping does.
+fn render(metrics: &ResourceMetrics, format: Format) -> String {
+    let mut out = String::new();
+
+    if !metrics.resource.is_empty() {
+        let labels = labels_text(metrics.resource.iter().collect(), None);
+        match format {
+            Format::Text => {
+                out.push_str("# HELP target_info Target metadata\n# TYPE target_info gauge\n")
+            }
+            Format::OpenMetrics => {
+                out.push_str("# HELP target Target metadata\n# TYPE target info\n")
+            }
+        }
+        let _ = writeln!(out, "target_info{labels} 1");
+    }
+
+    for metric in metrics
+        .scope_metrics
+        .iter()
+        .flat_map(|scope| &scope.metrics)
+    {
+        let name = metric_name(&metric.name, metric.unit.as_str());
+        let description = &metric.description;
+        let data = metric.data.as_any();
+        if let Some(sum) = data.downcast_ref::<data::Sum<u64>>() {
+            write_sum(&mut out, format, &name, description, sum);
+        } else if let Some(sum) = data.downcast_ref::<data::Sum<i64>>() {
+            write_sum(&mut out, format, &name, description, sum);
+        } else if let Some(sum) = data.downcast_ref::<data::Sum<f64>>() {
+            write_sum(&mut out, format, &name, description, sum);
+        } else if let Some(gauge) = data.downcast_ref::<data::Gauge<u64>>() {
+            write_points(
+                &mut out,
+                &name,
+                &name,
+                description,
+                "gauge",
+                &gauge.data_points,
+            );
+        } else if let Some(gauge) = data.downcast_ref::<data::Gauge<i64>>() {
+            write_points(
+                &mut out,
+                &name,
+                &name,
+                description,
+                "gauge",
+                &gauge.data_points,
+            );
+        } else if let Some(gauge) = data.downcast_ref::<data::Gauge<f64>>() {
+            write_points(
+                &mut out,
+                &name,
+                &name,
+                description,
+                "gauge",
+                &gauge.data_points,
+            );
+        } else if let Some(histogram) = data.downcast_ref::<data::Histogram<f64>>() {
+            write_histogram(&mut out, format, &name, description, histogram);
+        }
+    }
+
+    if format == Format::OpenMetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
+
+/// Monotonic sums are counters, whose samples end in `_total`. OpenMetrics
+/// names the family without the suffix.
+fn write_sum<T: Sample>(
+    out: &mut String,
+    format: Format,
+    name: &str,
+    description: &str,
+    sum: &data::Sum<T>,
+) {
+    if sum.is_monotonic {
+        let name = name.strip_suffix("_total").unwrap_or(name);
+        let sample_name = format!("{name}_total");
+        let family = match format {
+            Format::Text => &sample_name,
+            Format::OpenMetrics => name,
+        };
+        write_points(
+            out,
+            family,
+            &sample_name,
+            description,
+            "counter",
+            &sum.data_points,
+        );
+    } else {
+        write_points(out, name, name, description, "gauge", &sum.data_points);
+    }
+}
+
+fn write_points<T: Sample>(
+    out: &mut String,
+    family: &str,
+    name: &str,
+    description: &str,
+    kind: &str,
+    points: &[data::DataPoint<T>],
+) {
+    write_header(out, family, description, kind);
+    for point in points {
+        let labels = labels_text(point.attributes.iter().collect(), None);
+        let _ = writeln!(out, "{name}{labels} {}", point.value.sample());
+    }
+}
+
+/// Buckets are cumulative in Prometheus, unlike OpenTelemetry. In
+/// OpenMetrics each bucket is followed by its exemplar, if it has one.
+fn write_histogram(
+    out: &mut String,
+    format: Format,
+    name: &str,
+    description: &str,
+    histogram: &data::Histogram<f64>,
+) {
+    write_header(out, name, description, "histogram");
+    for point in &histogram.data_points {
+        let attributes: Vec<(&Key, &Value)> = point.attributes.iter().collect();
+        let mut cumulative = 0;
+        for (i, count) in point.bucket_counts.iter().enumerate() {
+            cumulative += count;
+            let le = point.bounds.get(i).map_or(f64::INFINITY, |bound| *bound);
+            let labels = labels_text(attributes.clone(), Some(&le.sample()));
+            let _ = write!(out, "{name}_bucket{labels} {cumulative}");
+            let exemplar = point
+                .exemplars
+                .iter()
+                .find(|exemplar| point.bounds.partition_point(|&b| b < exemplar.value) == i);
+            if let (Format::OpenMetrics, Some(exemplar)) = (format, exemplar) {
+                write_exemplar(out, exemplar);
+            }
+            out.push('\n');
+        }
+        let labels = labels_text(attributes, None);
+        let _ = writeln!(out, "{name}_sum{labels} {}", point.sum.sample());
+        let _ = writeln!(out, "{name}_count{labels} {}", point.count);
+    }
+}
+
+/// ` # {trace_id="..",span_id=".."} value timestamp`, in the label names
+/// Grafana looks for to link to the trace.
+fn write_exemplar(out: &mut String, exemplar: &data::Exemplar<f64>) {
+    let timestamp = exemplar
+        .time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let _ = write!(
+        out,
+        " # {{trace_id=\"{}\",span_id=\"{}\"}} {} {timestamp:.3}",
+        TraceId::from_bytes(exemplar.trace_id),
+        SpanId::from_bytes(exemplar.span_id),
+        exemplar.value.sample()
+    );
+}
+
+fn write_header(out: &mut String, name: &str, description: &str, kind: &str) {
+    if !description.is_empty() {
+        let description = description.replace('\\', r"\\").replace('\n', r"\n");
+        let _ = writeln!(out, "# HELP {name} {description}");
+    }
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// `{key="value",...}` for `attributes`, plus `le` for histogram buckets.
+fn labels_text(mut attributes: Vec<(&Key, &Value)>, le: Option<&str>) -> String {
+    if attributes.is_empty() && le.is_none() {
+        return String::new();
+    }
+    attributes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let mut labels: Vec<String> = attributes
+        .into_iter()
+        .map(|(key, value)| {
+            format!(
+                "{}=\"{}\"",
+                sanitize(key.as_str(), false),
+                escape(&value.as_str())
+            )
+        })
+        .collect();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{le}\""));
+    }
+    format!("{{{}}}", labels.join(","))
+}
+
+/// The Prometheus name for an OpenTelemetry metric, with its unit as a suffix:
+/// `http.server.request.duration` in `s` becomes
+/// `http_server_request_duration_seconds`. Annotations such as `{request}`
+/// are not units and are left off.
+fn metric_name(name: &str, unit: &str) -> String {
+    let mut name = sanitize(name, true);
+    let unit = match unit {
+        "s" => "seconds",
+        "ms" => "milliseconds",
+        "By" => "bytes",
+        "1" => "ratio",
+        unit if unit.starts_with('{') => "",
+        unit => unit,
+    };
+    let unit = sanitize(unit, true);
+    if !unit.is_empty() && !name.ends_with(&unit) {
+        name = format!("{name}_{unit}");
+    }
+    name
+}
+
+/// Replaces the characters Prometheus does not allow in names with `_`.
+/// Colons are only allowed in metric names.
+fn sanitize(name: &str, metric: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            ':' if metric => c,
+            _ => '_',
+        })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// A metric value as written in the exposition format.
+trait Sample {
+    fn sample(&self) -> String;
+}
+
+impl Sample for u64 {
+    fn sample(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Sample for i64 {
+    fn sample(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Sample for f64 {
+    fn sample(&self) -> String {
+        if self.is_nan() {
+            String::from("NaN")
+        } else if self.is_infinite() {
+            String::from(if *self > 0.0 { "+Inf" } else { "-Inf" })
+        } else {
+            self.to_string()
+        }
+    }
+}
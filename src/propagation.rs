@@ -0,0 +1,300 @@
+//! Trace context propagation formats, picked with `OtelTempoPropagators` or
+//! `OTEL_PROPAGATORS`. Every configured format is read from incoming requests
+//! and written to outgoing ones.
+use opentelemetry::{
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+    sdk::propagation::{BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator},
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use std::str::FromStr;
+
+const B3_SINGLE_HEADER: &str = "b3";
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+const JAEGER_HEADER: &str = "uber-trace-id";
+
+/// A propagation format, named as in `OTEL_PROPAGATORS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Propagator {
+    /// W3C `traceparent` and `tracestate`.
+    TraceContext,
+    /// W3C `baggage`.
+    Baggage,
+    /// Zipkin's single `b3` header.
+    B3,
+    /// Zipkin's `X-B3-*` headers.
+    B3Multi,
+    /// Jaeger's `uber-trace-id` header.
+    Jaeger,
+}
+
+impl FromStr for Propagator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracecontext" => Ok(Propagator::TraceContext),
+            "baggage" => Ok(Propagator::Baggage),
+            "b3" => Ok(Propagator::B3),
+            "b3multi" => Ok(Propagator::B3Multi),
+            "jaeger" => Ok(Propagator::Jaeger),
+            other => Err(format!("unsupported propagator {other}")),
+        }
+    }
+}
+
+/// The propagation formats in use. W3C trace context and baggage unless
+/// configured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Propagators(pub Vec<Propagator>);
+
+impl Default for Propagators {
+    fn default() -> Self {
+        Self(vec![Propagator::TraceContext, Propagator::Baggage])
+    }
+}
+
+impl FromStr for Propagators {
+    type Err = String;
+
+    /// Parses a comma separated list such as `tracecontext,b3multi`, or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut propagators = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name == "none" {
+                continue;
+            }
+            let propagator = name.parse()?;
+            if !propagators.contains(&propagator) {
+                propagators.push(propagator);
+            }
+        }
+        Ok(Self(propagators))
+    }
+}
+
+impl Propagators {
+    /// A propagator reading and writing every configured format. Extraction
+    /// runs in order, so the last format present in a request wins.
+    pub fn build(&self) -> TextMapCompositePropagator {
+        let propagators = self
+            .0
+            .iter()
+            .map(|propagator| -> Box<dyn TextMapPropagator + Send + Sync> {
+                match propagator {
+                    Propagator::TraceContext => Box::new(TraceContextPropagator::new()),
+                    Propagator::Baggage => Box::new(BaggagePropagator::new()),
+                    Propagator::B3 => Box::new(B3Propagator::single_header()),
+                    Propagator::B3Multi => Box::new(B3Propagator::multiple_headers()),
+                    Propagator::Jaeger => Box::new(JaegerPropagator::new()),
+                }
+            })
+            .collect();
+        TextMapCompositePropagator::new(propagators)
+    }
+}
+
+/// Zipkin B3 propagation. Reads both the single and multiple header
+/// encodings, and writes the one it was created for. A missing sampling
+/// decision is read as sampled, since the caller is tracing the request.
+#[derive(Debug)]
+pub struct B3Propagator {
+    single_header: bool,
+    fields: Vec<String>,
+}
+
+impl B3Propagator {
+    pub fn single_header() -> Self {
+        Self {
+            single_header: true,
+            fields: vec![B3_SINGLE_HEADER.to_owned()],
+        }
+    }
+
+    pub fn multiple_headers() -> Self {
+        Self {
+            single_header: false,
+            fields: [
+                B3_TRACE_ID_HEADER,
+                B3_SPAN_ID_HEADER,
+                B3_SAMPLED_HEADER,
+                B3_FLAGS_HEADER,
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+
+    fn extract_single(extractor: &dyn Extractor) -> Option<SpanContext> {
+        let value = extractor.get(B3_SINGLE_HEADER)?.trim();
+        let mut parts = value.split('-');
+        let trace_id = parse_b3_trace_id(parts.next()?)?;
+        let span_id = parse_b3_span_id(parts.next()?)?;
+        let sampled = match parts.next() {
+            None | Some("1") | Some("d") => true,
+            Some("0") => false,
+            Some(_) => return None,
+        };
+        Some(remote_span_context(trace_id, span_id, sampled))
+    }
+
+    fn extract_multi(extractor: &dyn Extractor) -> Option<SpanContext> {
+        let trace_id = parse_b3_trace_id(extractor.get(B3_TRACE_ID_HEADER)?.trim())?;
+        let span_id = parse_b3_span_id(extractor.get(B3_SPAN_ID_HEADER)?.trim())?;
+        let debug = extractor.get(B3_FLAGS_HEADER).map(str::trim) == Some("1");
+        let sampled = match extractor.get(B3_SAMPLED_HEADER).map(str::trim) {
+            None | Some("1") | Some("true") => true,
+            Some("0") | Some("false") => debug,
+            Some(_) => return None,
+        };
+        Some(remote_span_context(trace_id, span_id, sampled))
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        let sampled = if span_context.is_sampled() { "1" } else { "0" };
+        if self.single_header {
+            injector.set(
+                B3_SINGLE_HEADER,
+                format!(
+                    "{}-{}-{sampled}",
+                    span_context.trace_id(),
+                    span_context.span_id()
+                ),
+            );
+        } else {
+            injector.set(B3_TRACE_ID_HEADER, span_context.trace_id().to_string());
+            injector.set(B3_SPAN_ID_HEADER, span_context.span_id().to_string());
+            injector.set(B3_SAMPLED_HEADER, sampled.to_owned());
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match Self::extract_single(extractor).or_else(|| Self::extract_multi(extractor)) {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+/// Jaeger propagation through the
+/// `uber-trace-id: {trace-id}:{span-id}:{parent-span-id}:{flags}` header.
+/// Jaeger's `uberctx-*` baggage headers are not carried.
+#[derive(Debug)]
+pub struct JaegerPropagator {
+    fields: Vec<String>,
+}
+
+impl JaegerPropagator {
+    pub fn new() -> Self {
+        Self {
+            fields: vec![JAEGER_HEADER.to_owned()],
+        }
+    }
+
+    fn extract(extractor: &dyn Extractor) -> Option<SpanContext> {
+        // Some clients send the header URL encoded.
+        let value = extractor.get(JAEGER_HEADER)?.trim().replace("%3A", ":");
+        let parts: Vec<&str> = value.split(':').collect();
+        let [trace_id, span_id, _parent_span_id, flags] = parts[..] else {
+            return None;
+        };
+        let trace_id = parse_trace_id(trace_id)?;
+        let span_id = parse_span_id(span_id)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        // Bit 0 is sampled and bit 1 debug, which implies sampled.
+        Some(remote_span_context(trace_id, span_id, flags & 0b11 != 0))
+    }
+}
+
+impl Default for JaegerPropagator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextMapPropagator for JaegerPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        injector.set(
+            JAEGER_HEADER,
+            format!(
+                "{}:{}:0:{}",
+                span_context.trace_id(),
+                span_context.span_id(),
+                u8::from(span_context.is_sampled())
+            ),
+        );
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match Self::extract(extractor) {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Parses a hex trace id of up to 32 digits, with leading zeros optional.
+fn parse_trace_id(s: &str) -> Option<TraceId> {
+    if s.is_empty() || s.len() > 32 || !is_hex(s) {
+        return None;
+    }
+    TraceId::from_hex(s)
+        .ok()
+        .filter(|id| *id != TraceId::INVALID)
+}
+
+/// Parses a hex span id of up to 16 digits, with leading zeros optional.
+fn parse_span_id(s: &str) -> Option<SpanId> {
+    if s.is_empty() || s.len() > 16 || !is_hex(s) {
+        return None;
+    }
+    SpanId::from_hex(s).ok().filter(|id| *id != SpanId::INVALID)
+}
+
+/// B3 trace ids are 16 or 32 hex digits.
+fn parse_b3_trace_id(s: &str) -> Option<TraceId> {
+    matches!(s.len(), 16 | 32)
+        .then(|| parse_trace_id(s))
+        .flatten()
+}
+
+/// B3 span ids are 16 hex digits.
+fn parse_b3_span_id(s: &str) -> Option<SpanId> {
+    (s.len() == 16).then(|| parse_span_id(s)).flatten()
+}
+
+fn remote_span_context(trace_id: TraceId, span_id: SpanId, sampled: bool) -> SpanContext {
+    let flags = if sampled {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    SpanContext::new(trace_id, span_id, flags, true, TraceState::default())
+}
@@ -0,0 +1,638 @@
+use opentelemetry::{sdk::Resource, KeyValue};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs, process,
+    str::FromStr,
+    sync::{mpsc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+/// Placeholder `service.name` used when none is configured.
+pub const DEFAULT_SERVICE_NAME: &str = "axum-otel-test";
+
+/// Placeholder `environment` used when none is configured.
+pub const DEFAULT_ENVIRONMENT: &str = "dev";
+
+/// `service.version` baked in at build time: the `SERVICE_VERSION` variable of
+/// the build environment, or else the crate version.
+pub const BUILD_SERVICE_VERSION: &str = match option_env!("SERVICE_VERSION") {
+    Some(version) => version,
+    None => env!("CARGO_PKG_VERSION"),
+};
+
+/// `service.git.sha` baked in at build time: the `SERVICE_GIT_SHA` variable
+/// of the build environment, or else the commit checked out.
+pub const BUILD_GIT_SHA: Option<&str> = option_env!("SERVICE_GIT_SHA");
+
+/// Describes the host, OS, process, container or Kubernetes workload the
+/// service runs in as resource attributes, next to the detected cloud
+/// attributes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Detector {
+    /// `host.name` and `host.arch`.
+    Host,
+    /// `os.type`.
+    Os,
+    /// `process.pid`, `process.executable.name` and `process.runtime.*`.
+    Process,
+    /// `k8s.pod.name`, `k8s.namespace.name`, `k8s.node.name` and
+    /// `k8s.deployment.name` when running in a Kubernetes cluster.
+    K8s,
+    /// `container.id` from the cgroup, or the mounts, of the process.
+    Container,
+    /// The ECS task and container, or else the EC2 instance, from the AWS
+    /// metadata endpoints. Off by default, as finding out the service is not
+    /// on EC2 takes up to [`AWS_METADATA_TIMEOUT`] at startup.
+    Aws,
+}
+
+impl Detector {
+    /// The detectors enabled unless configured otherwise.
+    pub const DEFAULT: [Detector; 5] = [
+        Detector::Host,
+        Detector::Os,
+        Detector::Process,
+        Detector::K8s,
+        Detector::Container,
+    ];
+
+    fn key_values(self) -> Vec<KeyValue> {
+        match self {
+            Detector::Host => {
+                let arch = match env::consts::ARCH {
+                    "x86_64" => "amd64",
+                    "aarch64" => "arm64",
+                    "powerpc64" => "ppc64",
+                    arch => arch,
+                };
+                let mut attributes = vec![KeyValue::new("host.arch", arch)];
+                attributes.extend(host_name().map(|name| KeyValue::new("host.name", name)));
+                attributes
+            }
+            Detector::Os => vec![KeyValue::new("os.type", env::consts::OS)],
+            Detector::Process => {
+                let mut attributes = vec![
+                    KeyValue::new("process.pid", i64::from(process::id())),
+                    KeyValue::new("process.runtime.name", "rustc"),
+                ];
+                attributes.extend(
+                    env::current_exe()
+                        .ok()
+                        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+                        .map(|name| KeyValue::new("process.executable.name", name)),
+                );
+                if let Some(description) = option_env!("RUSTC_VERSION") {
+                    // `rustc 1.72.0 (5680fa18f 2023-08-23)`
+                    if let Some(version) = description.split(' ').nth(1) {
+                        attributes.push(KeyValue::new("process.runtime.version", version));
+                    }
+                    attributes.push(KeyValue::new("process.runtime.description", description));
+                }
+                attributes
+            }
+            Detector::K8s => k8s_key_values(),
+            Detector::Container => fs::read_to_string("/proc/self/cgroup")
+                .ok()
+                .and_then(|cgroup| container_id_from_cgroup(&cgroup))
+                .or_else(|| {
+                    fs::read_to_string("/proc/self/mountinfo")
+                        .ok()
+                        .and_then(|mounts| container_id_from_mountinfo(&mounts))
+                })
+                .map(|id| KeyValue::new("container.id", id))
+                .into_iter()
+                .collect(),
+            Detector::Aws => aws_key_values(),
+        }
+    }
+}
+
+/// Where the service account token and namespace are mounted in every pod.
+const K8S_SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// The pod's workload from the variables the downward API is usually mapped
+/// to, falling back to what every pod has: its host name, which is the pod
+/// name, and the mounted service account namespace. Nothing outside a cluster.
+fn k8s_key_values() -> Vec<KeyValue> {
+    let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+    if var("KUBERNETES_SERVICE_HOST").is_none() {
+        return Vec::new();
+    }
+
+    let pod = var("K8S_POD_NAME")
+        .or_else(|| var("POD_NAME"))
+        .or_else(host_name);
+    let namespace = var("K8S_NAMESPACE_NAME")
+        .or_else(|| var("POD_NAMESPACE"))
+        .or_else(|| {
+            fs::read_to_string(format!("{K8S_SERVICE_ACCOUNT_DIR}/namespace"))
+                .ok()
+                .map(|namespace| namespace.trim().to_owned())
+        });
+    let node = var("K8S_NODE_NAME").or_else(|| var("NODE_NAME"));
+    let deployment = var("K8S_DEPLOYMENT_NAME").or_else(|| {
+        pod.as_deref()
+            .and_then(deployment_of_pod)
+            .map(str::to_owned)
+    });
+
+    [
+        ("k8s.pod.name", pod),
+        ("k8s.namespace.name", namespace),
+        ("k8s.node.name", node),
+        ("k8s.deployment.name", deployment),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|value| KeyValue::new(key, value)))
+    .collect()
+}
+
+/// The deployment of a pod named `<deployment>-<replica set hash>-<pod
+/// suffix>`. Other pod names, such as those of stateful sets, have none.
+fn deployment_of_pod(pod: &str) -> Option<&str> {
+    // The alphabet Kubernetes generates name suffixes from.
+    let generated = |s: &str, len: std::ops::RangeInclusive<usize>| {
+        len.contains(&s.len())
+            && s.bytes()
+                .all(|b| b"bcdfghjklmnpqrstvwxz2456789".contains(&b))
+    };
+    let (rest, suffix) = pod.rsplit_once('-')?;
+    let (deployment, hash) = rest.rsplit_once('-')?;
+    (generated(suffix, 5..=5) && generated(hash, 6..=10) && !deployment.is_empty())
+        .then_some(deployment)
+}
+
+/// The container id in `/proc/self/cgroup` (cgroup v1, or v2 on hosts not
+/// giving containers a cgroup namespace): the 64 hex digit id ending a
+/// cgroup path such as `/docker/<id>`, `/kubepods/.../<id>` or
+/// `/system.slice/docker-<id>.scope`.
+pub fn container_id_from_cgroup(cgroup: &str) -> Option<String> {
+    static ID: OnceLock<Regex> = OnceLock::new();
+    let id = ID.get_or_init(|| Regex::new(r"(?:^|[/\-:])([0-9a-f]{64})(?:\.scope)?$").unwrap());
+    cgroup.lines().find_map(|line| {
+        // hierarchy-ID:controller-list:cgroup-path
+        let path = line.splitn(3, ':').nth(2)?;
+        Some(id.captures(path.trim())?[1].to_owned())
+    })
+}
+
+/// The container id in `/proc/self/mountinfo`, for cgroup v2 where the
+/// container only sees `0::/`: container runtimes bind mount the container's
+/// `hostname` and `resolv.conf` from `.../containers/<id>/`.
+pub fn container_id_from_mountinfo(mounts: &str) -> Option<String> {
+    static ID: OnceLock<Regex> = OnceLock::new();
+    let id = ID.get_or_init(|| Regex::new(r"/containers/([0-9a-f]{64})/").unwrap());
+    mounts
+        .lines()
+        .find_map(|line| Some(id.captures(line)?[1].to_owned()))
+}
+
+/// How long the AWS detector waits for the metadata endpoints.
+pub const AWS_METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The EC2 instance metadata service.
+const EC2_METADATA_ENDPOINT: &str = "http://169.254.169.254";
+
+/// Detected once, as the settings reload asks again.
+static AWS_ATTRIBUTES: OnceLock<Vec<KeyValue>> = OnceLock::new();
+
+/// The ECS attributes when `ECS_CONTAINER_METADATA_URI_V4` points at the task
+/// metadata endpoint, else the EC2 instance's. Nothing when the endpoints do
+/// not answer within [`AWS_METADATA_TIMEOUT`].
+fn aws_key_values() -> Vec<KeyValue> {
+    AWS_ATTRIBUTES
+        .get_or_init(|| {
+            // The caller may be on a Tokio runtime, which cannot block on
+            // another one, so the requests run on a thread of their own.
+            let (done, detected) = mpsc::channel();
+            thread::spawn(move || {
+                let attributes = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .ok()
+                    .and_then(|runtime| runtime.block_on(detect_aws()));
+                let _ = done.send(attributes);
+            });
+            detected
+                .recv_timeout(AWS_METADATA_TIMEOUT)
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        })
+        .clone()
+}
+
+async fn detect_aws() -> Option<Vec<KeyValue>> {
+    let client = reqwest::Client::builder()
+        .timeout(AWS_METADATA_TIMEOUT)
+        .build()
+        .ok()?;
+    match env::var("ECS_CONTAINER_METADATA_URI_V4") {
+        Ok(uri) if !uri.is_empty() => detect_ecs(&client, &uri).await,
+        _ => detect_ec2(&client).await,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EcsTask {
+    #[serde(rename = "TaskARN")]
+    task_arn: String,
+    cluster: Option<String>,
+    launch_type: Option<String>,
+    availability_zone: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EcsContainer {
+    docker_id: Option<String>,
+    name: Option<String>,
+    #[serde(rename = "ContainerARN")]
+    container_arn: Option<String>,
+}
+
+async fn detect_ecs(client: &reqwest::Client, uri: &str) -> Option<Vec<KeyValue>> {
+    let get = |url: String| async move {
+        client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    };
+    let task = get(format!("{uri}/task")).await.ok()?;
+    let container = get(uri.to_owned()).await.ok();
+    ecs_attributes(&task, container.as_deref())
+}
+
+/// The attributes described by the JSON the ECS task metadata endpoint (v4)
+/// returns for the task and, when it answered, for the container.
+pub fn ecs_attributes(task: &[u8], container: Option<&[u8]>) -> Option<Vec<KeyValue>> {
+    let task: EcsTask = serde_json::from_slice(task).ok()?;
+    let container: Option<EcsContainer> =
+        container.and_then(|container| serde_json::from_slice(container).ok());
+
+    let mut attributes = vec![
+        KeyValue::new("cloud.provider", "aws"),
+        KeyValue::new("cloud.platform", "aws_ecs"),
+    ];
+    // arn:aws:ecs:<region>:<account>:task/<cluster>/<id>
+    if let [_, _, _, region, account, ..] = task.task_arn.split(':').collect::<Vec<_>>()[..] {
+        attributes.push(KeyValue::new("cloud.region", region.to_owned()));
+        attributes.push(KeyValue::new("cloud.account.id", account.to_owned()));
+    }
+    attributes.push(KeyValue::new("aws.ecs.task.arn", task.task_arn));
+    let container = container.unwrap_or_default();
+    attributes.extend(
+        [
+            ("aws.ecs.cluster.arn", task.cluster),
+            (
+                "aws.ecs.launchtype",
+                task.launch_type.map(|t| t.to_lowercase()),
+            ),
+            ("cloud.availability_zone", task.availability_zone),
+            ("container.id", container.docker_id),
+            ("container.name", container.name),
+            ("aws.ecs.container.arn", container.container_arn),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| KeyValue::new(key, value))),
+    );
+    Some(attributes)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Ec2Identity {
+    instance_id: String,
+    instance_type: Option<String>,
+    image_id: Option<String>,
+    account_id: Option<String>,
+    region: Option<String>,
+    availability_zone: Option<String>,
+}
+
+/// Asks the instance metadata service with an IMDSv2 session token.
+async fn detect_ec2(client: &reqwest::Client) -> Option<Vec<KeyValue>> {
+    let token = client
+        .put(format!("{EC2_METADATA_ENDPOINT}/latest/api/token"))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    let get = |path: &'static str| {
+        let request = client
+            .get(format!("{EC2_METADATA_ENDPOINT}{path}"))
+            .header("X-aws-ec2-metadata-token", &token);
+        async move { request.send().await?.error_for_status()?.bytes().await }
+    };
+    let identity = get("/latest/dynamic/instance-identity/document")
+        .await
+        .ok()?;
+    let host_name = get("/latest/meta-data/hostname").await.ok();
+    ec2_attributes(
+        &identity,
+        host_name
+            .as_deref()
+            .and_then(|name| std::str::from_utf8(name).ok()),
+    )
+}
+
+/// The attributes described by the EC2 instance identity document and the
+/// instance's host name from the metadata service.
+pub fn ec2_attributes(identity: &[u8], host_name: Option<&str>) -> Option<Vec<KeyValue>> {
+    let identity: Ec2Identity = serde_json::from_slice(identity).ok()?;
+    let mut attributes = vec![
+        KeyValue::new("cloud.provider", "aws"),
+        KeyValue::new("cloud.platform", "aws_ec2"),
+        KeyValue::new("host.id", identity.instance_id),
+    ];
+    attributes.extend(
+        [
+            ("host.type", identity.instance_type),
+            ("host.image.id", identity.image_id),
+            ("host.name", host_name.map(str::to_owned)),
+            ("cloud.account.id", identity.account_id),
+            ("cloud.region", identity.region),
+            ("cloud.availability_zone", identity.availability_zone),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| KeyValue::new(key, value))),
+    );
+    Some(attributes)
+}
+
+impl FromStr for Detector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Detector::Host),
+            "os" => Ok(Detector::Os),
+            "process" => Ok(Detector::Process),
+            "k8s" => Ok(Detector::K8s),
+            "container" => Ok(Detector::Container),
+            "aws" => Ok(Detector::Aws),
+            other => Err(format!(
+                "expected host, os, process, k8s, container or aws, got {other}"
+            )),
+        }
+    }
+}
+
+/// Parses a comma separated list of detectors, `none` for no detectors.
+pub fn parse_detectors(s: &str) -> Result<Vec<Detector>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "none")
+        .map(str::parse)
+        .collect()
+}
+
+/// The kernel's host name, else `HOSTNAME` as set by most shells and
+/// container runtimes.
+fn host_name() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// Which resource attributes win when the same key is set in several places.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResourcePrecedence {
+    /// Configured attributes, then `OTEL_RESOURCE_ATTRIBUTES`, then detected ones.
+    #[default]
+    Explicit,
+    /// Detected attributes, then `OTEL_RESOURCE_ATTRIBUTES`, then configured ones.
+    Detected,
+}
+
+impl FromStr for ResourcePrecedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "explicit" => Ok(ResourcePrecedence::Explicit),
+            "detected" => Ok(ResourcePrecedence::Detected),
+            other => Err(format!("expected explicit or detected, got {other}")),
+        }
+    }
+}
+
+/// The configured `service.*` and `environment` resource attributes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServiceAttributes<'a> {
+    pub name: Option<&'a str>,
+    pub namespace: Option<&'a str>,
+    /// Defaults to [`BUILD_SERVICE_VERSION`].
+    pub version: Option<&'a str>,
+    /// Defaults to [`process_instance_id`].
+    pub instance_id: Option<&'a str>,
+    pub environment: Option<&'a str>,
+}
+
+/// A random version 4 UUID identifying this process as the default
+/// `service.instance.id`, the same for every resource built by the process.
+pub fn process_instance_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    })
+}
+
+/// The resource every signal starts from, merged from three layers ordered by
+/// `precedence`: the configured attributes, `resource_attributes` from
+/// `OTEL_RESOURCE_ATTRIBUTES`, and the detected cloud attributes plus those of
+/// `detectors`. The placeholder service name and environment, the
+/// [`process_instance_id`] and the build's [`BUILD_SERVICE_VERSION`] and
+/// [`BUILD_GIT_SHA`] only fill in when no layer sets them.
+pub fn base_resource(
+    service: &ServiceAttributes,
+    cloud: &CloudAttributes,
+    resource_attributes: &[KeyValue],
+    detectors: &[Detector],
+    precedence: ResourcePrecedence,
+) -> Resource {
+    let mut placeholders = vec![
+        KeyValue::new("service.name", DEFAULT_SERVICE_NAME),
+        KeyValue::new("environment", DEFAULT_ENVIRONMENT),
+        KeyValue::new("service.instance.id", process_instance_id()),
+        KeyValue::new("service.version", BUILD_SERVICE_VERSION),
+    ];
+    placeholders.extend(BUILD_GIT_SHA.map(|sha| KeyValue::new("service.git.sha", sha)));
+    let placeholders = Resource::new(placeholders);
+
+    let mut explicit: Vec<_> = [
+        ("service.name", service.name),
+        ("service.namespace", service.namespace),
+        ("service.version", service.version),
+        ("service.instance.id", service.instance_id),
+        ("environment", service.environment),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|value| KeyValue::new(key, value.to_owned())))
+    .collect();
+    explicit.extend(cloud.key_values());
+    let explicit = Resource::new(explicit);
+
+    let from_env = Resource::new(resource_attributes.iter().cloned());
+    let mut detected = CloudAttributes::detected().key_values();
+    detected.extend(detectors.iter().flat_map(|detector| detector.key_values()));
+    let detected = Resource::new(detected);
+
+    // `merge` lets its argument win, so the highest precedence layer goes last.
+    let (lowest, highest) = match precedence {
+        ResourcePrecedence::Explicit => (detected, explicit),
+        ResourcePrecedence::Detected => (explicit, detected),
+    };
+    placeholders.merge(&lowest).merge(&from_env).merge(&highest)
+}
+
+/// The `cloud.*` resource attributes, configured or detected from environment
+/// variables the cloud platforms set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CloudAttributes {
+    pub provider: Option<String>,
+    pub region: Option<String>,
+    pub availability_zone: Option<String>,
+}
+
+impl CloudAttributes {
+    /// Detects the attributes from AWS, GCP and Azure environment variables.
+    pub fn detected() -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+
+        let (provider, region) =
+            if let Some(region) = var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")) {
+                (Some("aws"), Some(region))
+            } else if var("K_SERVICE").is_some() || var("GOOGLE_CLOUD_PROJECT").is_some() {
+                (
+                    Some("gcp"),
+                    var("GOOGLE_CLOUD_REGION").or_else(|| var("FUNCTION_REGION")),
+                )
+            } else if var("WEBSITE_SITE_NAME").is_some() {
+                (Some("azure"), var("REGION_NAME"))
+            } else {
+                (None, None)
+            };
+
+        Self {
+            provider: provider.map(str::to_owned),
+            region,
+            availability_zone: None,
+        }
+    }
+
+    fn key_values(&self) -> Vec<KeyValue> {
+        [
+            ("cloud.provider", &self.provider),
+            ("cloud.region", &self.region),
+            ("cloud.availability_zone", &self.availability_zone),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.clone().map(|value| KeyValue::new(key, value)))
+        .collect()
+    }
+}
+
+/// The telemetry signals a resource can be tailored for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+    Traces,
+    Metrics,
+    Logs,
+}
+
+/// Extra attributes layered on top of the base resource for individual
+/// signals. A signal without overrides uses the base resource unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct SignalResources {
+    overrides: HashMap<Signal, Vec<KeyValue>>,
+}
+
+impl SignalResources {
+    pub fn with_override(mut self, signal: Signal, attributes: Vec<KeyValue>) -> Self {
+        self.overrides.entry(signal).or_default().extend(attributes);
+        self
+    }
+
+    /// The resource for `signal`, with its overrides taking precedence over `base`.
+    pub fn resource(&self, base: &Resource, signal: Signal) -> Resource {
+        match self.overrides.get(&signal) {
+            Some(attributes) => base.merge(&Resource::new(attributes.clone())),
+            None => base.clone(),
+        }
+    }
+}
+
+/// Parses `OTEL_RESOURCE_ATTRIBUTES`: `key=value` pairs separated by commas,
+/// with the values percent-encoded.
+pub fn parse_resource_attributes(s: &str) -> Result<Vec<KeyValue>, String> {
+    parse_key_values(s)?
+        .into_iter()
+        .map(|kv| {
+            Ok(KeyValue::new(
+                kv.key,
+                percent_decode(kv.value.as_str().as_ref())?,
+            ))
+        })
+        .collect()
+}
+
+pub(crate) fn percent_decode(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("invalid percent encoding in {s}"))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// Parses `key=value,key2=value2` lists as used by the OTel environment variables.
+pub fn parse_key_values(s: &str) -> Result<Vec<KeyValue>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(KeyValue::new(
+                key.trim().to_owned(),
+                value.trim().to_owned(),
+            )),
+            _ => Err(format!("expected key=value, got {pair}")),
+        })
+        .collect()
+}
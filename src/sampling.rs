@@ -0,0 +1,446 @@
+use opentelemetry::{
+    sdk::{
+        trace::{Sampler, ShouldSample},
+        Resource,
+    },
+    trace::{
+        Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId, TraceState,
+    },
+    Context, Key, OrderMap, Value,
+};
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::clock;
+use crate::reload::Reloadable;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Share of new traces sampled by default in production environments.
+pub const PRODUCTION_SAMPLING_RATIO: f64 = 0.1;
+
+/// The sampler used when none is configured: everything in development and
+/// staging, [`PRODUCTION_SAMPLING_RATIO`] of new traces in `prod` and
+/// `production`, judged by the resource's `deployment.environment` or
+/// `environment` attribute. Sampled upstream traces are always continued.
+pub fn environment_default(resource: &Resource) -> Sampler {
+    let environment = ["deployment.environment", "environment"]
+        .into_iter()
+        .find_map(|key| resource.get(Key::from_static_str(key)));
+
+    match environment.as_ref().map(|env| env.as_str()) {
+        Some(env) if env == "prod" || env == "production" => Sampler::ParentBased(Box::new(
+            Sampler::TraceIdRatioBased(PRODUCTION_SAMPLING_RATIO),
+        )),
+        _ => Sampler::AlwaysOn,
+    }
+}
+
+/// Builds the sampler named by `OTEL_TRACES_SAMPLER`, with the ratio of the
+/// ratio based ones taken from `OTEL_TRACES_SAMPLER_ARG`, defaulting to 1.0.
+/// Also accepts `parentbased_ratelimiting`, whose argument is the number of
+/// new traces sampled per second.
+pub fn from_otel_env(sampler: &str, arg: Option<&str>) -> Result<Sampler, String> {
+    let ratio = || match arg {
+        Some(arg) => arg
+            .parse::<f64>()
+            .map_err(|e| format!("invalid sampler ratio {arg}: {e}")),
+        None => Ok(1.0),
+    };
+    let parent_based = |root| Sampler::ParentBased(Box::new(root));
+
+    Ok(match sampler {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()?),
+        "parentbased_always_on" => parent_based(Sampler::AlwaysOn),
+        "parentbased_always_off" => parent_based(Sampler::AlwaysOff),
+        "parentbased_traceidratio" => parent_based(Sampler::TraceIdRatioBased(ratio()?)),
+        "parentbased_ratelimiting" => {
+            let rate = arg
+                .ok_or_else(|| {
+                    String::from("parentbased_ratelimiting needs a traces per second argument")
+                })?
+                .parse::<f64>()
+                .map_err(|e| format!("invalid sampler rate: {e}"))
+                .and_then(parse_rate)?;
+            Sampler::ParentBased(Box::new(RateLimitingSampler::new(rate)))
+        }
+        other => return Err(format!("unsupported sampler {other}")),
+    })
+}
+
+/// Checks that `rate` is a positive, finite number of traces per second.
+pub fn parse_rate(rate: f64) -> Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!(
+            "sampler rate must be a positive number, got {rate}"
+        ))
+    }
+}
+
+/// Parses a sampler with its argument in one value, such as `always_off` or
+/// `parentbased_traceidratio:0.25`, using the `OTEL_TRACES_SAMPLER` names.
+pub fn parse_sampler(s: &str) -> Result<Sampler, String> {
+    let (sampler, arg) = match s.split_once(':') {
+        Some((sampler, arg)) => (sampler, Some(arg)),
+        None => (s, None),
+    };
+    from_otel_env(sampler.trim(), arg.map(str::trim))
+}
+
+/// Refills at `rate` tokens per second up to a capacity of `rate`, so bursts
+/// of up to one second's worth are let through. Rates below one still hold a
+/// whole token, letting one through every `1 / rate` seconds. Takes the time
+/// from the caller, which passes [`clock::now`].
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: SystemTime,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(rate: f64, now: SystemTime) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token if one is available at `now`.
+    pub fn try_take(&mut self, now: SystemTime) -> bool {
+        let elapsed = now
+            .duration_since(self.last_refill)
+            .unwrap_or_default()
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = self.last_refill.max(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Samples at most `rate` new traces per second. Use it as the root of
+/// `Sampler::ParentBased` so the spans of a sampled trace are never dropped
+/// part way through, which `parentbased_ratelimiting:<rate>` does.
+#[derive(Clone, Debug)]
+pub struct RateLimitingSampler {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitingSampler {
+    pub fn new(traces_per_second: f64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(
+                traces_per_second,
+                clock::now(),
+            ))),
+        }
+    }
+}
+
+impl ShouldSample for RateLimitingSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &OrderMap<Key, Value>,
+        _links: &[Link],
+    ) -> SamplingResult {
+        let decision = if self.bucket.lock().unwrap().try_take(clock::now()) {
+            SamplingDecision::RecordAndSample
+        } else {
+            SamplingDecision::Drop
+        };
+        SamplingResult {
+            decision,
+            attributes: Vec::new(),
+            trace_state: match parent_context {
+                Some(cx) => cx.span().span_context().trace_state().clone(),
+                None => TraceState::default(),
+            },
+        }
+    }
+}
+
+/// Sample ratios for request routes, matched against the `http.route`
+/// template. A pattern ending in `*` matches every route starting with the
+/// rest of it. An exact pattern wins over `*` patterns, and among those the
+/// longest wins.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteSampling {
+    rules: Vec<(String, f64)>,
+}
+
+impl RouteSampling {
+    /// The ratio for `route`, if a pattern matches it.
+    pub fn ratio_for(&self, route: &str) -> Option<f64> {
+        let exact = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern == route)
+            .map(|&(_, ratio)| ratio);
+        exact.or_else(|| {
+            self.rules
+                .iter()
+                .filter_map(|(pattern, ratio)| {
+                    let prefix = pattern.strip_suffix('*')?;
+                    route.starts_with(prefix).then_some((prefix.len(), *ratio))
+                })
+                .max_by_key(|&(len, _)| len)
+                .map(|(_, ratio)| ratio)
+        })
+    }
+}
+
+impl FromStr for RouteSampling {
+    type Err = String;
+
+    /// Parses `route=ratio` entries separated by commas, e.g.
+    /// `/healthz=0,/api/*=1.0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (pattern, ratio) = entry
+                .rsplit_once('=')
+                .ok_or_else(|| format!("expected route=ratio, got {entry}"))?;
+            let ratio: f64 = ratio
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid sampling ratio in {entry}"))?;
+            rules.push((pattern.trim().to_owned(), ratio));
+        }
+        Ok(Self { rules })
+    }
+}
+
+/// Samples requests whose `http.route` matches a [`RouteSampling`] pattern
+/// by that pattern's ratio, including requests continuing an upstream trace,
+/// so noisy endpoints can be turned down. Spans with a local parent follow
+/// the parent, so a dropped request is dropped whole. Everything else is left
+/// to `fallback`.
+#[derive(Clone, Debug)]
+pub struct RouteSampler {
+    routes: RouteSampling,
+    fallback: Sampler,
+}
+
+impl RouteSampler {
+    pub fn new(routes: RouteSampling, fallback: Sampler) -> Self {
+        Self { routes, fallback }
+    }
+}
+
+impl ShouldSample for RouteSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+    ) -> SamplingResult {
+        let local_parent = parent_context
+            .filter(|cx| cx.has_active_span())
+            .map(|cx| cx.span().span_context().clone())
+            .filter(|parent| parent.is_valid() && !parent.is_remote());
+        if let Some(parent) = local_parent {
+            return SamplingResult {
+                decision: if parent.is_sampled() {
+                    SamplingDecision::RecordAndSample
+                } else {
+                    SamplingDecision::Drop
+                },
+                attributes: Vec::new(),
+                trace_state: parent.trace_state().clone(),
+            };
+        }
+
+        let ratio = attributes
+            .get(&Key::from_static_str("http.route"))
+            .and_then(|route| self.routes.ratio_for(&route.as_str()));
+        match ratio {
+            Some(ratio) => Sampler::TraceIdRatioBased(ratio).should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            ),
+            None => self.fallback.should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            ),
+        }
+    }
+}
+
+/// Delegates to a sampler that can be replaced while spans are being started,
+/// so a configuration reload can change sampling without a new tracer.
+#[derive(Clone, Debug)]
+pub struct ReloadableSampler(Reloadable<Box<dyn ShouldSample>>);
+
+impl ReloadableSampler {
+    pub fn new(sampler: Box<dyn ShouldSample>) -> Self {
+        Self(Reloadable::new(sampler))
+    }
+
+    pub fn set(&self, sampler: Box<dyn ShouldSample>) {
+        self.0.set(sampler);
+    }
+}
+
+impl ShouldSample for ReloadableSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+    ) -> SamplingResult {
+        self.0
+            .get()
+            .should_sample(parent_context, trace_id, name, span_kind, attributes, links)
+    }
+}
+
+/// A time of day window, in seconds since midnight UTC. Windows whose end is
+/// before their start wrap around midnight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingWindow {
+    start: u32,
+    end: u32,
+    ratio: f64,
+}
+
+impl SamplingWindow {
+    fn contains(&self, second_of_day: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&second_of_day)
+        } else {
+            second_of_day >= self.start || second_of_day < self.end
+        }
+    }
+}
+
+/// Samples root spans by trace id ratio, picking the ratio from the first
+/// window containing the current UTC time of day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduledSampler {
+    windows: Vec<SamplingWindow>,
+    /// Used outside every window.
+    default_ratio: f64,
+}
+
+impl ScheduledSampler {
+    fn ratio_at(&self, second_of_day: u32) -> f64 {
+        self.windows
+            .iter()
+            .find(|window| window.contains(second_of_day))
+            .map_or(self.default_ratio, |window| window.ratio)
+    }
+}
+
+impl ShouldSample for ScheduledSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+    ) -> SamplingResult {
+        let now = clock::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let ratio = self.ratio_at((now % u64::from(SECONDS_PER_DAY)) as u32);
+
+        Sampler::TraceIdRatioBased(ratio).should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+        )
+    }
+}
+
+impl FromStr for ScheduledSampler {
+    type Err = String;
+
+    /// Parses `HH:MM-HH:MM=ratio` windows separated by commas, with an optional
+    /// `*=ratio` entry for the rest of the day (1.0 when omitted), e.g.
+    /// `08:00-18:00=1.0,*=0.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sampler = ScheduledSampler {
+            windows: Vec::new(),
+            default_ratio: 1.0,
+        };
+
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (range, ratio) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected window=ratio, got {entry}"))?;
+            let ratio: f64 = ratio
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid sampling ratio in {entry}"))?;
+
+            if range.trim() == "*" {
+                sampler.default_ratio = ratio;
+                continue;
+            }
+
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| format!("expected HH:MM-HH:MM, got {range}"))?;
+            sampler.windows.push(SamplingWindow {
+                start: parse_time_of_day(start)?,
+                end: parse_time_of_day(end)?,
+                ratio,
+            });
+        }
+
+        Ok(sampler)
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let (hours, minutes) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected HH:MM, got {s}"))?;
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(h), Ok(m)) if h < 24 && m < 60 => Ok(h * 3600 + m * 60),
+        _ => Err(format!("invalid time of day {s}")),
+    }
+}
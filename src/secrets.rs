@@ -0,0 +1,110 @@
+use base64::{engine::general_purpose, Engine};
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use crate::export::HeaderProvider;
+
+/// Reads a mounted secret file, dropping the trailing newline most tools
+/// write.
+pub fn read_secret_file(path: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(contents.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Export credentials read from a secret file.
+#[derive(Clone, Debug)]
+pub enum CredentialFile {
+    Basic {
+        username: String,
+        password_file: PathBuf,
+    },
+    Bearer(PathBuf),
+}
+
+impl CredentialFile {
+    /// The variable that named the secret file.
+    pub fn var(&self) -> &'static str {
+        match self {
+            CredentialFile::Basic { .. } => "OtelTempoPassword_FILE",
+            CredentialFile::Bearer(_) => "OtelTempoBearerToken_FILE",
+        }
+    }
+
+    fn authorization(&self) -> Result<HeaderValue, String> {
+        let value = match self {
+            CredentialFile::Basic {
+                username,
+                password_file,
+            } => {
+                let password = read_secret_file(password_file)?;
+                format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(format!("{username}:{password}"))
+                )
+            }
+            CredentialFile::Bearer(token_file) => {
+                format!("Bearer {}", read_secret_file(token_file)?)
+            }
+        };
+        let mut value = HeaderValue::from_str(&value).map_err(|e| e.to_string())?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// The `Authorization` header built from a [`CredentialFile`], re-read when
+/// the process receives SIGHUP so rotated secrets apply without a restart.
+#[derive(Debug)]
+pub struct FileCredentials {
+    file: CredentialFile,
+    authorization: RwLock<HeaderValue>,
+}
+
+impl FileCredentials {
+    pub fn load(file: CredentialFile) -> Result<Self, String> {
+        Ok(Self {
+            authorization: RwLock::new(file.authorization()?),
+            file,
+        })
+    }
+
+    /// Re-reads the secret file. Keeps the current credentials when that fails.
+    pub fn reload(&self) -> Result<(), String> {
+        *self.authorization.write().unwrap() = self.file.authorization()?;
+        Ok(())
+    }
+
+    /// Reloads on every SIGHUP, on a background task that lives as long as
+    /// the runtime.
+    pub fn reload_on_sighup(self: &Arc<Self>) {
+        let credentials = self.clone();
+        tokio::spawn(async move {
+            let mut hangup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to listen for SIGHUP, secret files will not be re-read: {e}"
+                        );
+                        return;
+                    }
+                };
+            while hangup.recv().await.is_some() {
+                match credentials.reload() {
+                    Ok(()) => tracing::info!("Re-read export credentials"),
+                    Err(e) => tracing::warn!("Failed to re-read export credentials: {e}"),
+                }
+            }
+        });
+    }
+}
+
+impl HeaderProvider for FileCredentials {
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        vec![(AUTHORIZATION, self.authorization.read().unwrap().clone())]
+    }
+}
@@ -0,0 +1,75 @@
+use axum::http::{HeaderMap, Method};
+use opentelemetry::{global, trace::TraceContextExt, Context, Key, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use tracing::{field, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::processors::ContextAttributes;
+
+/// Records an integer measurement on the current span, keeping its numeric type
+/// so Tempo can filter on it with comparison operators.
+pub fn record_i64(key: impl Into<Key>, value: i64) {
+    tracing::Span::current().set_attribute(key, value);
+}
+
+/// Records a floating point measurement on the current span.
+pub fn record_f64(key: impl Into<Key>, value: f64) {
+    tracing::Span::current().set_attribute(key, value);
+}
+
+/// Whether the current span was sampled and will be exported. Check this before
+/// building attributes that are expensive to compute, since they are thrown
+/// away for unsampled spans.
+pub fn is_recording() -> bool {
+    let span = tracing::Span::current();
+    !span.is_disabled() && span.context().span().span_context().is_sampled()
+}
+
+/// Creates a `SpanKind::Client` span for an outgoing HTTP request, so Tempo's
+/// service graph draws an edge to the called service. Record
+/// `http.response.status_code` on it once the response arrives.
+pub fn client_span(method: &Method, url: &str) -> Span {
+    tracing::info_span!(
+        "HTTP request",
+        otel.kind = "client",
+        otel.name = %method,
+        http.request.method = %method,
+        url.full = url,
+        http.response.status_code = field::Empty,
+    )
+}
+
+/// Reads the caller's trace context from incoming request headers using the
+/// global propagator.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Writes the trace context of `span` into outgoing request headers using the
+/// global propagator.
+pub fn inject_context(span: &Span, headers: &mut HeaderMap) {
+    let cx = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut HeaderInjector(headers))
+    });
+}
+
+/// Adds `attributes` to the current request span and every span started
+/// beneath it, for request scoped values such as the tenant. Call it at
+/// request entry, inside `TraceLayer` and before any child span starts, with
+/// the request's headers.
+///
+/// The attributes ride along in the request span's parent context, which is
+/// extracted from `headers` again the same way [`crate::http_trace::layer`]
+/// does.
+pub fn set_context_attributes(headers: &HeaderMap, attributes: Vec<KeyValue>) {
+    let span = Span::current();
+    let mut all = span
+        .context()
+        .get::<ContextAttributes>()
+        .map(|ContextAttributes(existing)| existing.clone())
+        .unwrap_or_default();
+    all.extend(attributes);
+
+    span.set_parent(extract_context(headers).with_value(ContextAttributes(all)));
+}
@@ -0,0 +1,175 @@
+//! Appends finished spans to a local file as JSON lines, for environments
+//! where Tempo can't be reached. Each line is one span:
+//!
+//! ```json
+//! {"trace_id":"…","span_id":"…","parent_span_id":null,"name":"request","kind":"server",
+//!  "start_unix_nano":…,"end_unix_nano":…,"duration_ms":1.5,"status":"error",
+//!  "status_message":"…","attributes":{…},"events":[…],"links":[…],"resource":{…}}
+//! ```
+//!
+//! Attribute values are plain JSON values, ids are lowercase hex and times are
+//! nanoseconds since the Unix epoch.
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    sdk::export::trace::{ExportResult, SpanData, SpanExporter},
+    trace::{SpanId, SpanKind, Status, TraceError},
+    Array, Key, Value,
+};
+use serde_json::{json, Map};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Writes spans to `path`, moving it to `path.1` (and older files to `.2`,
+/// `.3`, …) once it grows past `max_bytes`. Keeps at most `max_files` rotated
+/// files.
+#[derive(Debug)]
+pub struct SpanFileExporter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl SpanFileExporter {
+    pub fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            written,
+        })
+    }
+
+    fn write_batch(&mut self, batch: &[SpanData]) -> io::Result<()> {
+        for span in batch {
+            let mut line = serde_json::to_vec(&span_line(span))?;
+            line.push(b'\n');
+            if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+                self.rotate()?;
+            }
+            self.file.write_all(&line)?;
+            self.written += line.len() as u64;
+        }
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+        self.file = BufWriter::new(open_append(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl SpanExporter for SpanFileExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let result = self
+            .write_batch(&batch)
+            .map_err(|e| TraceError::Other(Box::new(e)));
+        Box::pin(future::ready(result))
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn span_line(span: &SpanData) -> serde_json::Value {
+    let (status, status_message) = match &span.status {
+        Status::Unset => ("unset", None),
+        Status::Ok => ("ok", None),
+        Status::Error { description } => ("error", Some(description.as_ref())),
+    };
+    let kind = match span.span_kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    };
+    let duration = span
+        .end_time
+        .duration_since(span.start_time)
+        .unwrap_or_default();
+
+    json!({
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": (span.parent_span_id != SpanId::INVALID)
+            .then(|| span.parent_span_id.to_string()),
+        "name": span.name,
+        "kind": kind,
+        "start_unix_nano": unix_nanos(span.start_time),
+        "end_unix_nano": unix_nanos(span.end_time),
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+        "status": status,
+        "status_message": status_message,
+        "attributes": attributes(span.attributes.iter()),
+        "events": span.events.iter().map(|event| json!({
+            "name": event.name,
+            "time_unix_nano": unix_nanos(event.timestamp),
+            "attributes": attributes(event.attributes.iter().map(|kv| (&kv.key, &kv.value))),
+        })).collect::<Vec<_>>(),
+        "links": span.links.iter().map(|link| json!({
+            "trace_id": link.span_context.trace_id().to_string(),
+            "span_id": link.span_context.span_id().to_string(),
+        })).collect::<Vec<_>>(),
+        "resource": attributes(span.resource.iter()),
+    })
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn attributes<'a>(
+    attributes: impl Iterator<Item = (&'a Key, &'a Value)>,
+) -> Map<String, serde_json::Value> {
+    attributes
+        .map(|(key, value)| (key.as_str().to_owned(), value_json(value)))
+        .collect()
+}
+
+fn value_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Bool(v) => json!(v),
+        Value::I64(v) => json!(v),
+        Value::F64(v) => json!(v),
+        Value::String(v) => json!(v.as_str()),
+        Value::Array(Array::Bool(v)) => json!(v),
+        Value::Array(Array::I64(v)) => json!(v),
+        Value::Array(Array::F64(v)) => json!(v),
+        Value::Array(Array::String(v)) => json!(v.iter().map(|s| s.as_str()).collect::<Vec<_>>()),
+    }
+}
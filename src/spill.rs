@@ -0,0 +1,480 @@
+//! Spans of failed exports written to disk and replayed once the backend
+//! accepts exports again, for outages longer than the in-memory
+//! [`RecoveryBuffer`](crate::export::RecoveryBuffer) can bridge. Enable it
+//! with `OtelTempoSpillDir`.
+use futures_util::future::BoxFuture;
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::{EvictedHashMap, EvictedQueue},
+        InstrumentationLibrary, Resource,
+    },
+    trace::{Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState},
+    Array, KeyValue, StringValue, Value,
+};
+use opentelemetry_proto::tonic::{
+    collector::trace::v1::ExportTraceServiceRequest,
+    common::v1::{any_value, AnyValue},
+    trace::v1::{span, status, ResourceSpans, Span},
+};
+use prost::Message;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+const SPILL_EXTENSION: &str = "otlp";
+
+static SPILL_EVICTED_SPANS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of spans evicted from a full spill directory since startup.
+pub fn spill_evicted_spans() -> u64 {
+    SPILL_EVICTED_SPANS.load(Ordering::Relaxed)
+}
+
+/// Writes each failed batch to its own file in `dir`, as an OTLP
+/// `ExportTraceServiceRequest`, and reports the export as done. After each
+/// accepted export the oldest file is read ahead, and the next export carries
+/// its spans and deletes it once the backend accepts them, so the backlog
+/// drains as new spans are exported. Files left by an earlier run are
+/// replayed too. Past `max_bytes` the oldest files are deleted first, except
+/// the one being replayed.
+///
+/// The files are listed once, when created, and tracked in memory after
+/// that. File access runs on Tokio's blocking threads.
+#[derive(Debug)]
+pub struct DiskSpill<E> {
+    inner: E,
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Arc<Mutex<SpillIndex>>,
+}
+
+impl<E> DiskSpill<E> {
+    pub fn new(inner: E, dir: PathBuf, max_bytes: u64) -> Self {
+        let files: VecDeque<SpillFile> = spill_files(&dir).into();
+        let index = SpillIndex {
+            next_file: files
+                .back()
+                .and_then(|file| file.number)
+                .map_or(0, |n| n + 1),
+            bytes: files.iter().map(|file| file.bytes).sum(),
+            files,
+            loaded: None,
+            loading: false,
+        };
+        Self {
+            inner,
+            dir,
+            max_bytes,
+            index: Arc::new(Mutex::new(index)),
+        }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for DiskSpill<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let replayed = self.index.lock().unwrap().loaded.take();
+        let mut spans = batch.clone();
+        if let Some((_, replayed_spans)) = &replayed {
+            spans.extend(replayed_spans.iter().cloned());
+        }
+        let export = self.inner.export(spans);
+        let dir = self.dir.clone();
+        let max_bytes = self.max_bytes;
+        let index = self.index.clone();
+
+        Box::pin(async move {
+            let result = export.await;
+            match (replayed, &result) {
+                (Some((file, _)), Ok(())) => {
+                    let path = file.path.clone();
+                    if let Err(e) = blocking(move || fs::remove_file(path)).await {
+                        tracing::warn!(
+                            "Failed to delete replayed spill file {}: {e}",
+                            file.path.display()
+                        );
+                    }
+                    index.lock().unwrap().bytes -= file.bytes;
+                }
+                (Some(replayed), Err(_)) => index.lock().unwrap().put_back(replayed),
+                (None, _) => {}
+            }
+            let Err(e) = result else {
+                read_ahead(&index).await;
+                return Ok(());
+            };
+            match spill(&dir, &index, batch, max_bytes).await {
+                Ok(()) => Ok(()),
+                Err(spill_error) => {
+                    tracing::warn!("Failed to spill spans to {}: {spill_error}", dir.display());
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
+
+/// The spill files on disk. `files` holds those waiting to be replayed,
+/// oldest first, and is all eviction may delete. `bytes` also counts the file
+/// being read ahead or replayed, which are held apart from `files`.
+#[derive(Debug)]
+struct SpillIndex {
+    files: VecDeque<SpillFile>,
+    bytes: u64,
+    next_file: u64,
+    loaded: Option<(SpillFile, Vec<SpanData>)>,
+    loading: bool,
+}
+
+impl SpillIndex {
+    /// Returns a file whose replay failed, to be carried again later.
+    fn put_back(&mut self, (file, spans): (SpillFile, Vec<SpanData>)) {
+        if self.loaded.is_none() {
+            self.loaded = Some((file, spans));
+        } else {
+            self.files.push_front(file);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    number: Option<u64>,
+    spans: u64,
+    bytes: u64,
+}
+
+/// The spill files in `dir`, oldest first. Named `{number}-{spans}.otlp`.
+fn spill_files(dir: &Path) -> Vec<SpillFile> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<SpillFile> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == SPILL_EXTENSION))
+        .map(|path| {
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            let (number, spans) = stem.split_once('-').unwrap_or((stem, "0"));
+            SpillFile {
+                number: number.parse().ok(),
+                spans: spans.parse().unwrap_or(0),
+                bytes: fs::metadata(&path).map_or(0, |m| m.len()),
+                path,
+            }
+        })
+        .collect();
+    files.sort_by_key(|file| file.number);
+    files
+}
+
+/// Runs file access on a blocking thread, off the runtime's workers.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Reads the oldest file for the next export to carry, unless one is already
+/// read or being read. Unreadable files are discarded.
+async fn read_ahead(index: &Mutex<SpillIndex>) {
+    let file = {
+        let mut index = index.lock().unwrap();
+        if index.loaded.is_some() || index.loading {
+            return;
+        }
+        let Some(file) = index.files.pop_front() else {
+            return;
+        };
+        index.loading = true;
+        file
+    };
+    let path = file.path.clone();
+    let read = blocking(move || {
+        read_spans(&path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    })
+    .await;
+    match read {
+        Ok(spans) => {
+            let mut index = index.lock().unwrap();
+            index.loading = false;
+            index.put_back((file, spans));
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Discarding unreadable spill file {}: {e}",
+                file.path.display()
+            );
+            let path = file.path.clone();
+            let _ = blocking(move || fs::remove_file(path)).await;
+            let mut index = index.lock().unwrap();
+            index.loading = false;
+            index.bytes -= file.bytes;
+        }
+    }
+}
+
+/// Writes `spans` as the next file, then deletes the oldest files waiting to
+/// be replayed until the directory holds at most `max_bytes`.
+async fn spill(
+    dir: &Path,
+    index: &Mutex<SpillIndex>,
+    spans: Vec<SpanData>,
+    max_bytes: u64,
+) -> io::Result<()> {
+    let number = {
+        let mut index = index.lock().unwrap();
+        let number = index.next_file;
+        index.next_file += 1;
+        number
+    };
+    let spans_len = spans.len() as u64;
+    let path = dir.join(format!("{number:020}-{spans_len}.{SPILL_EXTENSION}"));
+    let written = {
+        let dir = dir.to_path_buf();
+        let path = path.clone();
+        blocking(move || {
+            fs::create_dir_all(&dir)?;
+            let request = ExportTraceServiceRequest {
+                resource_spans: spans.into_iter().map(ResourceSpans::from).collect(),
+            };
+            let bytes = request.encode_to_vec();
+            // Written under a temporary name, so a crash cannot leave half a file to replay.
+            let mut partial = path.clone().into_os_string();
+            partial.push(".partial");
+            fs::write(&partial, &bytes)?;
+            fs::rename(&partial, &path)?;
+            Ok(bytes.len() as u64)
+        })
+        .await?
+    };
+
+    let evicted: Vec<SpillFile> = {
+        let mut index = index.lock().unwrap();
+        index.files.push_back(SpillFile {
+            path,
+            number: Some(number),
+            spans: spans_len,
+            bytes: written,
+        });
+        index.bytes += written;
+        let mut evicted = Vec::new();
+        while index.bytes > max_bytes {
+            let Some(file) = index.files.pop_front() else {
+                break;
+            };
+            index.bytes -= file.bytes;
+            evicted.push(file);
+        }
+        evicted
+    };
+    if evicted.is_empty() {
+        return Ok(());
+    }
+    blocking(move || {
+        for file in evicted {
+            match fs::remove_file(&file.path) {
+                Ok(()) => {
+                    SPILL_EVICTED_SPANS.fetch_add(file.spans, Ordering::Relaxed);
+                }
+                Err(e) => tracing::warn!("Failed to evict spill file {}: {e}", file.path.display()),
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn read_spans(path: &Path) -> Result<Vec<SpanData>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let request = ExportTraceServiceRequest::decode(bytes.as_slice()).map_err(|e| e.to_string())?;
+    let mut spans = Vec::new();
+    for resource_spans in request.resource_spans {
+        let attributes = resource_spans
+            .resource
+            .map(|resource| key_values(resource.attributes))
+            .unwrap_or_default();
+        let resource = if resource_spans.schema_url.is_empty() {
+            Resource::new(attributes)
+        } else {
+            Resource::from_schema_url(attributes, resource_spans.schema_url)
+        };
+        for scope_spans in resource_spans.scope_spans {
+            let scope = scope_spans.scope.unwrap_or_default();
+            let library = InstrumentationLibrary::new(
+                scope.name,
+                Some(scope.version).filter(|v| !v.is_empty()),
+                Some(scope_spans.schema_url).filter(|url| !url.is_empty()),
+                None,
+            );
+            for span in scope_spans.spans {
+                spans.push(span_data(span, &resource, &library)?);
+            }
+        }
+    }
+    Ok(spans)
+}
+
+fn span_data(
+    span: Span,
+    resource: &Resource,
+    library: &InstrumentationLibrary,
+) -> Result<SpanData, String> {
+    let trace_state = TraceState::from_str(&span.trace_state).unwrap_or_default();
+    // Only sampled spans reach the exporter, and OTLP here carries no flags.
+    let span_context = SpanContext::new(
+        trace_id(&span.trace_id)?,
+        span_id(&span.span_id)?,
+        TraceFlags::SAMPLED,
+        false,
+        trace_state,
+    );
+    let parent_span_id = if span.parent_span_id.is_empty() {
+        SpanId::INVALID
+    } else {
+        span_id(&span.parent_span_id)?
+    };
+
+    let mut attributes = EvictedHashMap::new(span.attributes.len() as u32, span.attributes.len());
+    for kv in key_values(span.attributes) {
+        attributes.insert(kv);
+    }
+    let mut events = EvictedQueue::new(span.events.len() as u32);
+    events.extend(span.events.into_iter().map(|event| {
+        Event::new(
+            event.name,
+            UNIX_EPOCH + Duration::from_nanos(event.time_unix_nano),
+            key_values(event.attributes),
+            event.dropped_attributes_count,
+        )
+    }));
+    let mut links = EvictedQueue::new(span.links.len() as u32);
+    for link in span.links {
+        let span_context = SpanContext::new(
+            trace_id(&link.trace_id)?,
+            span_id(&link.span_id)?,
+            TraceFlags::default(),
+            true,
+            TraceState::from_str(&link.trace_state).unwrap_or_default(),
+        );
+        links.extend([Link::new(span_context, key_values(link.attributes))]);
+    }
+
+    let span_status = span.status.unwrap_or_default();
+    let status = match status::StatusCode::from_i32(span_status.code) {
+        Some(status::StatusCode::Ok) => Status::Ok,
+        Some(status::StatusCode::Error) => Status::error(span_status.message),
+        _ => Status::Unset,
+    };
+
+    Ok(SpanData {
+        span_context,
+        parent_span_id,
+        span_kind: match span::SpanKind::from_i32(span.kind) {
+            Some(span::SpanKind::Server) => SpanKind::Server,
+            Some(span::SpanKind::Client) => SpanKind::Client,
+            Some(span::SpanKind::Producer) => SpanKind::Producer,
+            Some(span::SpanKind::Consumer) => SpanKind::Consumer,
+            _ => SpanKind::Internal,
+        },
+        name: Cow::Owned(span.name),
+        start_time: UNIX_EPOCH + Duration::from_nanos(span.start_time_unix_nano),
+        end_time: UNIX_EPOCH + Duration::from_nanos(span.end_time_unix_nano),
+        attributes,
+        events,
+        links,
+        status,
+        resource: Cow::Owned(resource.clone()),
+        instrumentation_lib: library.clone(),
+    })
+}
+
+fn trace_id(bytes: &[u8]) -> Result<TraceId, String> {
+    let bytes = bytes.try_into().map_err(|_| "invalid trace id")?;
+    Ok(TraceId::from_bytes(bytes))
+}
+
+fn span_id(bytes: &[u8]) -> Result<SpanId, String> {
+    let bytes = bytes.try_into().map_err(|_| "invalid span id")?;
+    Ok(SpanId::from_bytes(bytes))
+}
+
+fn key_values(attributes: Vec<opentelemetry_proto::tonic::common::v1::KeyValue>) -> Vec<KeyValue> {
+    attributes
+        .into_iter()
+        .filter_map(|kv| Some(KeyValue::new(kv.key, value(kv.value?)?)))
+        .collect()
+}
+
+/// The SDK only records scalars and arrays of them, so other OTLP values
+/// cannot come from a spilled span.
+fn value(value: AnyValue) -> Option<Value> {
+    Some(match value.value? {
+        any_value::Value::StringValue(s) => Value::from(s),
+        any_value::Value::BoolValue(b) => Value::Bool(b),
+        any_value::Value::IntValue(i) => Value::I64(i),
+        any_value::Value::DoubleValue(f) => Value::F64(f),
+        any_value::Value::ArrayValue(array) => {
+            let values = array.values.into_iter().filter_map(|v| v.value);
+            let array = match values.clone().next()? {
+                any_value::Value::BoolValue(_) => Array::Bool(
+                    values
+                        .filter_map(|v| match v {
+                            any_value::Value::BoolValue(b) => Some(b),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                any_value::Value::IntValue(_) => Array::I64(
+                    values
+                        .filter_map(|v| match v {
+                            any_value::Value::IntValue(i) => Some(i),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                any_value::Value::DoubleValue(_) => Array::F64(
+                    values
+                        .filter_map(|v| match v {
+                            any_value::Value::DoubleValue(f) => Some(f),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                _ => Array::String(
+                    values
+                        .filter_map(|v| match v {
+                            any_value::Value::StringValue(s) => Some(StringValue::from(s)),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+            };
+            Value::Array(array)
+        }
+        any_value::Value::KvlistValue(_) | any_value::Value::BytesValue(_) => return None,
+    })
+}
@@ -1,85 +1,604 @@
-use base64::{engine::general_purpose, Engine};
+use futures_util::future::BoxFuture;
 use opentelemetry::{
+    global,
+    logs::LoggerProvider as _,
     sdk::{
-        trace::{self, RandomIdGenerator, Sampler},
+        logs::{self, Logger, LoggerProvider},
+        metrics::MeterProvider,
+        trace::{self, RandomIdGenerator, Sampler, ShouldSample, Tracer, TracerProvider},
         Resource,
     },
-    KeyValue,
+    trace::TracerProvider as _,
 };
-use opentelemetry_otlp::WithExportConfig;
-use std::{collections::HashMap, env, time::Duration};
-use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Layer, Registry};
+
+use crate::config::{
+    load_settings, span_limits_for_tempo, Settings, SpanLimitsPreset, TelemetryMode,
+};
+use crate::error::TelemetryError;
+use crate::export::{
+    self, batch_processor, build_export_client, export_auth, export_preflight, otlp_log_exporter,
+    otlp_metric_reader, otlp_processor,
+};
+use crate::health;
+use crate::http_trace::CapturedHeaders;
+use crate::logging::{self, JsonFormat, LogFormat, TraceFlagsFormat};
+use crate::logs::OtelLogLayer;
+use crate::metrics;
+use crate::middleware::BodyCapture;
+use crate::processors::{self, wrap_processor, BoxedProcessor, FanOut};
+use crate::prometheus::PrometheusReader;
+use crate::reload::Reloadable;
+use crate::resource::{self, ServiceAttributes, Signal};
+use crate::sampling::{self, ReloadableSampler, RouteSampler};
+use crate::span_file::SpanFileExporter;
+use crate::status;
+
+/// The provider installed by [`init_telemetry`], kept so it can be flushed on demand and
+/// released on [`shutdown`].
+static TRACER_PROVIDER: Mutex<Option<TracerProvider>> = Mutex::new(None);
+
+/// The meter provider when metrics are exported, shut down with the tracer
+/// provider so the last readings are sent.
+static METER_PROVIDER: Mutex<Option<MeterProvider>> = Mutex::new(None);
 
-struct Settings {
-    otel_username: String,
-    otel_password: String,
-    otel_endpoint: String,
+/// The logger provider when logs are exported. Loggers only hold a weak
+/// reference to it, so it is kept here until shutdown.
+static LOGGER_PROVIDER: Mutex<Option<LoggerProvider>> = Mutex::new(None);
+
+/// The tracer provider's sampler, replaced by [`ReloadHandle::reload`].
+static SAMPLER: Mutex<Option<ReloadableSampler>> = Mutex::new(None);
+
+/// Keeps telemetry running. Dropping it flushes the spans still queued and
+/// shuts the tracer provider down, so hold it until the server has stopped.
+/// Prefer awaiting [`TelemetryGuard::shutdown`], which gives up on a backend
+/// that does not answer instead of hanging.
+pub struct TelemetryGuard {
+    pub settings: Settings,
+    /// Why span export could not be set up, when `fail_open` let the service
+    /// start with logging only.
+    pub degraded: Option<TelemetryError>,
+    shut_down: bool,
 }
 
-pub fn init() {
-    let settings = load_settings();
+impl TelemetryGuard {
+    /// Whether spans are being exported.
+    pub fn is_exporting(&self) -> bool {
+        self.degraded.is_none() && self.settings.mode != TelemetryMode::Disabled
+    }
+
+    /// See [`force_flush`]. Blocks until the export finishes.
+    pub fn force_flush(&self) {
+        force_flush();
+    }
 
-    init_otel_telemetry(settings);
+    /// Shuts telemetry down like dropping the guard, waiting at most
+    /// `settings.shutdown_timeout` for the final flush.
+    pub async fn shutdown(mut self) {
+        self.shut_down = true;
+        shutdown_with_timeout(self.settings.shutdown_timeout).await;
+    }
+
+    /// For reloading the configuration from outside the task holding the guard.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            capture_request_headers: self.settings.capture_request_headers.clone(),
+            capture_response_headers: self.settings.capture_response_headers.clone(),
+            body_capture: self.settings.body_capture.clone(),
+        }
+    }
+}
+
+/// Applies a fresh read of the configuration to the running service. Only
+/// the console log filter, the sampler and the header and body capture
+/// allowlists change; everything else needs a restart. Body capture cannot
+/// be turned on by a reload when it was off at startup.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    capture_request_headers: Reloadable<CapturedHeaders>,
+    capture_response_headers: Reloadable<CapturedHeaders>,
+    body_capture: Option<Reloadable<BodyCapture>>,
 }
 
-fn load_settings() -> Settings {
-    match dotenvy::dotenv() {
-        Ok(path) => println!(".env read successfully from {}", path.display()),
-        Err(e) => println!("Could not load .env file: {e}"),
+impl ReloadHandle {
+    /// Reads the settings again with [`load_settings`] and applies them. In
+    /// flight requests finish with the values they started with. On error
+    /// nothing is changed.
+    pub fn reload(&self) -> Result<(), TelemetryError> {
+        let settings = load_settings()?;
+
+        if let Err(e) = logging::replace_log_filter(layer_filter(settings.log_filter.as_deref())) {
+            tracing::warn!("Failed to reload the log filter: {e}");
+        }
+        if let Some(sampler) = SAMPLER.lock().unwrap().as_ref() {
+            sampler.set(build_sampler(&settings, &base_resource(&settings)));
+        }
+        self.capture_request_headers
+            .set(settings.capture_request_headers.get());
+        self.capture_response_headers
+            .set(settings.capture_response_headers.get());
+        if let Some(body_capture) = &self.body_capture {
+            // Capturing no routes stands in for capture being turned off.
+            let routes = settings.body_capture.map(|capture| capture.get());
+            body_capture.set(routes.unwrap_or_else(|| {
+                Arc::new(BodyCapture {
+                    routes: Vec::new(),
+                    ..(*body_capture.get()).clone()
+                })
+            }));
+        }
+
+        tracing::info!("Telemetry configuration reloaded");
+        Ok(())
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if !self.shut_down {
+            shutdown();
+        }
+    }
+}
+
+/// Sets up span export, registers the configured propagators globally and
+/// installs the global tracing subscriber. Must be called from within a
+/// Tokio runtime.
+///
+/// Fails when span export cannot be set up, unless `settings.fail_open` is set,
+/// in which case logging still works and the guard reports why export is off.
+pub fn init_telemetry(settings: Settings) -> Result<TelemetryGuard, TelemetryError> {
+    health::mark_started();
+    global::set_text_map_propagator(settings.propagators.build());
+    // Only fails on a poisoned lock, leaving the default handler in place.
+    let _ = global::set_error_handler(status::handle_error);
+
+    let pipelines = match settings.mode {
+        TelemetryMode::Disabled => Ok(None),
+        _ => init_otel_telemetry(&settings).map(Some),
+    };
+    let degraded = match pipelines {
+        Ok(pipelines) => {
+            let (tracer, logger, preflight) = match pipelines {
+                Some((tracer, logger, preflight)) => (Some(tracer), logger, preflight),
+                None => (None, None, None),
+            };
+            install_subscriber(tracer, logger, &settings)?;
+            if let Some(preflight) = preflight {
+                tokio::spawn(preflight);
+            }
+            None
+        }
+        Err(e) if settings.fail_open => {
+            install_subscriber(None, None, &settings)?;
+            tracing::warn!("Span export is disabled, continuing with logging only: {e}");
+            Some(e)
+        }
+        Err(e) => return Err(e),
     };
 
-    Settings {
-        otel_username: env::var("OtelTempoUserName").expect("OtelTempoUserName not set"),
-        otel_password: env::var("OtelTempoPassword").expect("OtelTempoPassword not set"),
-        otel_endpoint: env::var("OtelTempoEndpoint").expect("OtelTempoEndpoint not set"),
+    for setting in &settings.resolution {
+        tracing::debug!(
+            setting = setting.name,
+            source = %setting.source,
+            value = %setting.value,
+            "resolved telemetry setting"
+        );
+    }
+
+    if settings.deploy_event {
+        tracing::info_span!(
+            "deploy",
+            service.version = settings
+                .service_version
+                .as_deref()
+                .unwrap_or(resource::BUILD_SERVICE_VERSION),
+            deploy.event = true
+        )
+        .in_scope(|| tracing::info!("service started"));
+    }
+
+    if let Some(interval) = settings.flush_interval {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let _ = tokio::task::spawn_blocking(force_flush).await;
+            }
+        });
+    }
+
+    if let Some(interval) = settings.heartbeat_interval {
+        tokio::spawn(heartbeat(interval));
+    }
+
+    if let Some(interval) = settings.status_log_interval {
+        tokio::spawn(log_status(interval));
+    }
+
+    Ok(TelemetryGuard {
+        settings,
+        degraded,
+        shut_down: false,
+    })
+}
+
+/// Emits a tiny span on every tick so idle services keep a warm exporter
+/// connection. Filter them out in Tempo with `{ span.telemetry.heartbeat = true }`.
+async fn heartbeat(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        tracing::info_span!("telemetry.heartbeat", telemetry.heartbeat = true).in_scope(|| {});
+    }
+}
+
+/// Logs the export counters on every tick, as a warning when spans were
+/// dropped or exports failed since the previous one.
+async fn log_status(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    let mut last = status::telemetry_status();
+    loop {
+        ticker.tick().await;
+        let status = status::telemetry_status();
+        let dropped = status.spans_dropped - last.spans_dropped;
+        let failures = status.export_failures - last.export_failures;
+        if dropped > 0 || failures > 0 {
+            tracing::warn!(
+                spans_exported = status.spans_exported,
+                batches_exported = status.batches_exported,
+                spans_dropped = status.spans_dropped,
+                spans_queue_full = status.spans_queue_full,
+                export_failures = status.export_failures,
+                last_export_error = status.last_export_error,
+                "Telemetry delivery degraded, {dropped} spans dropped and {failures} exports failed in the last {interval:?}"
+            );
+        } else {
+            tracing::info!(
+                spans_exported = status.spans_exported,
+                batches_exported = status.batches_exported,
+                spans_dropped = status.spans_dropped,
+                export_failures = status.export_failures,
+                "Telemetry status"
+            );
+        }
+        last = status;
+    }
+}
+
+/// Exports all spans and log records the batch processors are holding
+/// without waiting for their schedule. `OtelTempoFlushIntervalMs` runs this
+/// periodically, for services with too little traffic to fill a batch.
+///
+/// Blocks until the export finishes, so call it from a blocking task.
+pub fn force_flush() {
+    let provider = TRACER_PROVIDER.lock().unwrap().clone();
+    if let Some(provider) = provider {
+        for result in provider.force_flush() {
+            if let Err(e) = result {
+                tracing::warn!("Failed to flush spans: {e}");
+            }
+        }
+    }
+
+    let logger_provider = LOGGER_PROVIDER.lock().unwrap().clone();
+    if let Some(provider) = logger_provider {
+        for result in provider.force_flush() {
+            if let Err(e) = result {
+                tracing::warn!("Failed to flush logs: {e}");
+            }
+        }
     }
 }
 
-fn init_otel_telemetry(settings: Settings) {
-    let mut header_map = HashMap::new();
+/// Flushes the final batch and releases the tracer provider so its processors
+/// shut down. Warns when the flush fails, since those spans are lost.
+///
+/// [`TelemetryGuard`] calls this on drop.
+pub fn shutdown() {
+    let rate_limited = processors::rate_limited_spans();
+    if rate_limited > 0 {
+        tracing::warn!(rate_limited, "Spans were dropped by the export rate limit");
+    }
+
+    let provider = TRACER_PROVIDER.lock().unwrap().take();
+    if let Some(provider) = provider {
+        for result in provider.force_flush() {
+            if let Err(e) = result {
+                tracing::warn!("Spans may have been lost at shutdown, final flush failed: {e}");
+            }
+        }
+    }
+    global::shutdown_tracer_provider();
+
+    let logger_provider = LOGGER_PROVIDER.lock().unwrap().take();
+    if let Some(mut provider) = logger_provider {
+        for result in provider
+            .force_flush()
+            .into_iter()
+            .chain(provider.shutdown())
+        {
+            if let Err(e) = result {
+                tracing::warn!("Logs may have been lost at shutdown, final export failed: {e}");
+            }
+        }
+    }
+
+    let meter_provider = METER_PROVIDER.lock().unwrap().take();
+    if let Some(provider) = meter_provider {
+        if let Err(e) = provider.shutdown() {
+            tracing::warn!("Final metrics export failed: {e}");
+        }
+    }
 
-    header_map.insert(
-        String::from("Authorization"),
-        format!(
-            "Basic {}",
-            general_purpose::STANDARD
-                .encode(settings.otel_username + ":" + &settings.otel_password)
-        ),
+    let status = status::telemetry_status();
+    tracing::info!(
+        spans_exported = status.spans_exported,
+        spans_dropped = status.spans_dropped,
+        export_failures = status.export_failures,
+        export_retries = status.export_retries,
+        last_export_error = status.last_export_error,
+        "Span export finished"
     );
-    let client = reqwest::Client::new();
-
-    let tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(
-            opentelemetry_otlp::new_exporter()
-                .http()
-                .with_http_client(client)
-                .with_headers(header_map)
-                .with_endpoint(settings.otel_endpoint)
-                .with_timeout(Duration::from_secs(3)),
-        )
-        .with_trace_config(
-            trace::config()
-                .with_sampler(Sampler::AlwaysOn)
-                .with_id_generator(RandomIdGenerator::default())
+}
+
+/// Runs [`shutdown`] on its own thread and stops waiting after `timeout`, so
+/// a backend that does not answer cannot hang the process. The thread is
+/// left behind, and the spans it had not exported by then are lost.
+pub async fn shutdown_with_timeout(timeout: Duration) {
+    let exported = status::telemetry_status().spans_exported;
+    let (done, finished) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        shutdown();
+        let _ = done.send(());
+    });
+    let timed_out = tokio::time::timeout(timeout, finished).await.is_err();
+
+    let status = status::telemetry_status();
+    let flushed = status.spans_exported - exported;
+    let dropped = status.spans_queued.saturating_sub(status.spans_exported);
+    if timed_out {
+        tracing::warn!(
+            flushed,
+            dropped,
+            "Telemetry shutdown timed out after {timeout:?}, spans still queued are lost"
+        );
+    } else {
+        tracing::info!(flushed, dropped, "Telemetry shut down");
+    }
+}
+
+/// Builds the tracer, the logger when logs are exported over OTLP, and the
+/// export preflight when it is enabled. The preflight is only spawned once
+/// the subscriber is installed, so its outcome is logged.
+#[allow(clippy::type_complexity)]
+fn init_otel_telemetry(
+    settings: &Settings,
+) -> Result<(Tracer, Option<Logger>, Option<BoxFuture<'static, ()>>), TelemetryError> {
+    if settings.require_service_identity {
+        if settings.service_name.is_none() {
+            return Err(TelemetryError::MissingSetting("OTEL_SERVICE_NAME"));
+        }
+        if settings.environment.is_none() {
+            return Err(TelemetryError::MissingSetting("OtelTempoEnvironment"));
+        }
+    }
+    let base_resource = base_resource(settings);
+
+    let sampler = ReloadableSampler::new(build_sampler(settings, &base_resource));
+    *SAMPLER.lock().unwrap() = Some(sampler.clone());
+    let mut config = trace::config()
+        .with_sampler(sampler)
+        .with_id_generator(RandomIdGenerator::default())
+        .with_resource(
+            settings
+                .signal_resources
+                .resource(&base_resource, Signal::Traces),
+        );
+
+    let mut metric_reader = None;
+    let mut log_exporter = None;
+    let mut preflight = None;
+    let processor = match settings.mode {
+        TelemetryMode::Otlp => {
+            let client = build_export_client(settings)?;
+            let auth = export_auth(settings, &client)?;
+            let processor = otlp_processor(settings, client.clone(), auth.clone())?;
+            if settings.export_preflight {
+                preflight = Some(export_preflight(settings, client.clone(), auth.clone())?);
+            }
+            if settings.metrics {
+                metric_reader = Some(otlp_metric_reader(settings, client.clone(), auth.clone())?);
+            }
+            if settings.logs {
+                log_exporter = Some(otlp_log_exporter(settings, client, auth)?);
+            }
+            processor
+        }
+        TelemetryMode::Stdout => {
+            batch_processor(opentelemetry_stdout::SpanExporter::default(), settings)
+        }
+        TelemetryMode::File => {
+            let exporter = SpanFileExporter::open(
+                settings.span_file.clone(),
+                settings.span_file_max_bytes,
+                settings.span_file_max_files,
+            )
+            .map_err(|e| TelemetryError::InvalidSetting {
+                var: "OtelTempoSpanFile",
+                reason: e.to_string(),
+            })?;
+            batch_processor(exporter, settings)
+        }
+        TelemetryMode::Disabled => unreachable!("no tracer is built when telemetry is disabled"),
+    };
+
+    match settings.span_limits {
+        SpanLimitsPreset::Default => {
+            config = config
                 .with_max_events_per_span(64)
-                .with_max_attributes_per_span(16)
-                .with_resource(Resource::new(vec![
-                    KeyValue::new("service.name", "axum-otel-test"),
-                    KeyValue::new("environment", "dev"),
-                ])),
-        )
-        .install_batch(opentelemetry::runtime::Tokio)
-        .unwrap();
+                .with_max_attributes_per_span(16);
+        }
+        SpanLimitsPreset::Tempo => config = config.with_span_limits(span_limits_for_tempo()),
+    }
 
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+    if let Some(limit) = settings.attribute_count_limit {
+        config = config
+            .with_max_attributes_per_span(limit)
+            .with_max_attributes_per_event(limit)
+            .with_max_attributes_per_link(limit);
+    }
+
+    if settings.metrics && settings.mode != TelemetryMode::Otlp {
+        tracing::warn!("Metrics are only exported over OTLP in otlp mode");
+    }
+    if settings.logs && settings.mode != TelemetryMode::Otlp {
+        tracing::warn!("Logs are only exported over OTLP in otlp mode");
+    }
+    let logger = log_exporter.map(|exporter| {
+        let provider = LoggerProvider::builder()
+            .with_config(
+                logs::Config::default().with_resource(
+                    settings
+                        .signal_resources
+                        .resource(&base_resource, Signal::Logs),
+                ),
+            )
+            .with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+            .build();
+        let logger = provider.versioned_logger(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION").into()),
+            None,
+            None,
+        );
+        *LOGGER_PROVIDER.lock().unwrap() = Some(provider);
+        logger
+    });
+    if metric_reader.is_some() || settings.prometheus {
+        let mut builder = MeterProvider::builder().with_resource(
+            settings
+                .signal_resources
+                .resource(&base_resource, Signal::Metrics),
+        );
+        if let Some(reader) = metric_reader {
+            builder = builder.with_reader(reader);
+        }
+        if settings.prometheus {
+            builder = builder.with_reader(PrometheusReader::install());
+        }
+        let provider = builder.build();
+        *METER_PROVIDER.lock().unwrap() = Some(provider.clone());
+        global::set_meter_provider(provider);
+        status::register_metrics(&global::meter(metrics::METER_NAME));
+    }
+
+    // Sampling and the processors from `wrap_processor` run once, ahead of
+    // the fan-out to the primary and secondary endpoints.
+    let mut destinations = vec![processor];
+    for (index, endpoint) in settings.secondary_endpoints.iter().enumerate() {
+        destinations.push(export::secondary_processor(settings, index, endpoint)?);
+    }
+    let builder = TracerProvider::builder()
+        .with_span_processor(BoxedProcessor(wrap_processor(
+            Box::new(FanOut(destinations)),
+            settings,
+        )))
+        .with_config(config);
+    let provider = builder.build();
+    let tracer = provider.versioned_tracer(
+        "opentelemetry-otlp",
+        Some(env!("CARGO_PKG_VERSION")),
+        None::<&'static str>,
+        None,
+    );
+    *TRACER_PROVIDER.lock().unwrap() = Some(provider.clone());
+    global::set_tracer_provider(provider);
+
+    Ok((tracer, logger, preflight))
+}
+
+fn base_resource(settings: &Settings) -> Resource {
+    resource::base_resource(
+        &ServiceAttributes {
+            name: settings.service_name.as_deref(),
+            namespace: settings.service_namespace.as_deref(),
+            version: settings.service_version.as_deref(),
+            instance_id: settings.service_instance_id.as_deref(),
+            environment: settings.environment.as_deref(),
+        },
+        &settings.cloud,
+        &settings.resource_attributes,
+        &settings.resource_detectors,
+        settings.resource_precedence,
+    )
+}
+
+/// The configured sampler, else the schedule, else the environment's default,
+/// behind the route ratios when there are any.
+fn build_sampler(settings: &Settings, base_resource: &Resource) -> Box<dyn ShouldSample> {
+    let sampler = match (&settings.sampler, &settings.sampling_schedule) {
+        (Some(sampler), _) => sampler.clone(),
+        (None, Some(schedule)) => Sampler::ParentBased(Box::new(schedule.clone())),
+        (None, None) => sampling::environment_default(base_resource),
+    };
+    match &settings.route_sampling {
+        Some(routes) => Box::new(RouteSampler::new(routes.clone(), sampler)),
+        None => Box::new(sampler),
+    }
+}
+
+/// Used when neither `RUST_LOG` nor a layer's own directives are set.
+const DEFAULT_FILTER: &str = "axum_otel_tempo=info,tower_http=debug,axum::rejection=trace";
+
+/// A layer's own directives if configured, else `RUST_LOG`, else [`DEFAULT_FILTER`].
+fn layer_filter(directives: Option<&str>) -> EnvFilter {
+    match directives {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_FILTER.into()),
+    }
+}
+
+/// Installs the global subscriber. Without a tracer only the fmt layer is
+/// installed, so logging keeps working when export is unavailable. Each
+/// layer has its own filter, so console verbosity does not change what is
+/// exported.
+fn install_subscriber(
+    tracer: Option<Tracer>,
+    logger: Option<Logger>,
+    settings: &Settings,
+) -> Result<(), TelemetryError> {
+    let export_filter = || layer_filter(settings.export_filter.as_deref());
+    let telemetry = tracer.map(|tracer| {
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(export_filter())
+    });
+    let logs = logger.map(|logger| OtelLogLayer::new(logger).with_filter(export_filter()));
+    let fmt: Box<dyn Layer<Registry> + Send + Sync> = match settings.log_format {
+        LogFormat::Full if settings.log_trace_flags => tracing_subscriber::fmt::layer()
+            .event_format(TraceFlagsFormat::default())
+            .boxed(),
+        LogFormat::Full => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer().compact().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .event_format(JsonFormat)
+            .boxed(),
+    };
 
     let subscriber = Registry::default()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-            "axum_otel_tempo=info,tower_http=debug,axum::rejection=trace".into()
-        }))
-        .with(telemetry);
+        .with(fmt.with_filter(logging::reloadable(layer_filter(
+            settings.log_filter.as_deref(),
+        ))))
+        .with(telemetry)
+        .with(logs);
 
     tracing::subscriber::set_global_default(subscriber)
-        .expect("Failed to set global default tracing");
+        .map_err(|_| TelemetryError::SubscriberAlreadySet)
 }
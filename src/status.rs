@@ -0,0 +1,373 @@
+use futures_util::future::BoxFuture;
+use opentelemetry::{
+    global,
+    metrics::{Meter, Unit},
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        runtime::TrySendError,
+        trace::{Span, SpanProcessor},
+    },
+    trace::{TraceError, TraceResult},
+    Context, KeyValue,
+};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::export::{self, CircuitOpen};
+use crate::{processors, spill, tail_sampling};
+
+static SPANS_QUEUED: AtomicU64 = AtomicU64::new(0);
+static SPANS_QUEUE_FULL: AtomicU64 = AtomicU64::new(0);
+static SPANS_EXPORTED: AtomicU64 = AtomicU64::new(0);
+static BATCHES_EXPORTED: AtomicU64 = AtomicU64::new(0);
+static EXPORT_FAILURES: AtomicU64 = AtomicU64::new(0);
+static CONSECUTIVE_EXPORT_FAILURES: AtomicU64 = AtomicU64::new(0);
+static LAST_EXPORT_ERROR: Mutex<Option<String>> = Mutex::new(None);
+static SECONDARY_DESTINATIONS: Mutex<Vec<Arc<DestinationCounters>>> = Mutex::new(Vec::new());
+
+/// Metric attribute naming the destination of the export counters.
+pub const DESTINATION_KEY: &str = "telemetry.destination";
+
+/// Value of [`DESTINATION_KEY`] for the primary endpoint.
+pub const PRIMARY_DESTINATION: &str = "primary";
+
+/// A snapshot of how span export is doing, for exposing on an admin endpoint
+/// to catch silent trace loss.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TelemetryStatus {
+    /// Sampled spans handed to the batch processor. Those not exported yet
+    /// are queued, waiting for a retry, or lost.
+    pub spans_queued: u64,
+    /// Spans the collector accepted.
+    pub spans_exported: u64,
+    /// Batches the collector accepted.
+    pub batches_exported: u64,
+    /// Spans dropped before export by the rate limit, for being oversized,
+    /// for finding the batch queue full, or evicted from the recovery buffer
+    /// or spill directory.
+    pub spans_dropped: u64,
+    /// The part of `spans_dropped` that found the batch queue full.
+    pub spans_queue_full: u64,
+    /// Traces the tail sampler decided not to export.
+    pub traces_tail_dropped: u64,
+    /// Traces the tail sampler dropped undecided, when their window ran out
+    /// or its buffer was full.
+    pub traces_tail_evicted: u64,
+    /// Export calls that failed. Their spans are lost.
+    pub export_failures: u64,
+    /// Export calls that failed since the last one that succeeded.
+    pub consecutive_export_failures: u64,
+    /// Export attempts repeated after a failure.
+    pub export_retries: u64,
+    /// Whether exports are paused after repeated failures.
+    pub export_circuit_open: bool,
+    /// The error of the most recent failed export.
+    pub last_export_error: Option<String>,
+    /// Counters of the secondary endpoints, which the fields above leave out.
+    pub secondary_destinations: Vec<DestinationStatus>,
+}
+
+/// How export to one secondary endpoint is doing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DestinationStatus {
+    /// The endpoint, also the [`DESTINATION_KEY`] of its metrics.
+    pub name: String,
+    pub spans_queued: u64,
+    pub spans_exported: u64,
+    pub batches_exported: u64,
+    pub export_failures: u64,
+}
+
+/// Counters since startup.
+pub fn telemetry_status() -> TelemetryStatus {
+    TelemetryStatus {
+        spans_queued: SPANS_QUEUED.load(Ordering::Relaxed),
+        spans_exported: SPANS_EXPORTED.load(Ordering::Relaxed),
+        batches_exported: BATCHES_EXPORTED.load(Ordering::Relaxed),
+        spans_dropped: processors::rate_limited_spans()
+            + processors::oversized_spans()
+            + SPANS_QUEUE_FULL.load(Ordering::Relaxed)
+            + export::recovery_evicted_spans()
+            + spill::spill_evicted_spans(),
+        spans_queue_full: SPANS_QUEUE_FULL.load(Ordering::Relaxed),
+        traces_tail_dropped: tail_sampling::tail_dropped_traces(),
+        traces_tail_evicted: tail_sampling::tail_evicted_traces(),
+        export_failures: EXPORT_FAILURES.load(Ordering::Relaxed),
+        consecutive_export_failures: CONSECUTIVE_EXPORT_FAILURES.load(Ordering::Relaxed),
+        export_retries: export::export_retries(),
+        export_circuit_open: export::export_circuit_open(),
+        last_export_error: LAST_EXPORT_ERROR.lock().unwrap().clone(),
+        secondary_destinations: SECONDARY_DESTINATIONS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|counters| counters.status())
+            .collect(),
+    }
+}
+
+/// Where a [`QueueCounter`] and [`CountingExporter`] count: the primary
+/// endpoint, whose counters make up [`telemetry_status`], or a secondary one
+/// counted separately so a span sent to both is not counted twice.
+#[derive(Clone, Debug, Default)]
+pub enum Destination {
+    #[default]
+    Primary,
+    Secondary(Arc<DestinationCounters>),
+}
+
+impl Destination {
+    /// Registers the counters of the secondary endpoint `name`.
+    pub fn secondary(name: impl Into<String>) -> Self {
+        let counters = Arc::new(DestinationCounters {
+            name: name.into(),
+            ..DestinationCounters::default()
+        });
+        SECONDARY_DESTINATIONS
+            .lock()
+            .unwrap()
+            .push(counters.clone());
+        Destination::Secondary(counters)
+    }
+}
+
+/// The export counters of a secondary endpoint.
+#[derive(Debug, Default)]
+pub struct DestinationCounters {
+    name: String,
+    spans_queued: AtomicU64,
+    spans_exported: AtomicU64,
+    batches_exported: AtomicU64,
+    export_failures: AtomicU64,
+}
+
+impl DestinationCounters {
+    fn status(&self) -> DestinationStatus {
+        DestinationStatus {
+            name: self.name.clone(),
+            spans_queued: self.spans_queued.load(Ordering::Relaxed),
+            spans_exported: self.spans_exported.load(Ordering::Relaxed),
+            batches_exported: self.batches_exported.load(Ordering::Relaxed),
+            export_failures: self.export_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Global OpenTelemetry error handler. Counts the spans the batch processor
+/// drops for a full queue into [`telemetry_status`] instead of printing one
+/// line per span, and skips exports turned away by an open
+/// [`export::CircuitBreaker`], which warned once when it opened. Prints
+/// everything else to stderr like the default handler.
+pub fn handle_error(err: global::Error) {
+    match err {
+        global::Error::Trace(TraceError::Other(e)) if e.is::<CircuitOpen>() => {}
+        global::Error::Trace(TraceError::Other(e))
+            if matches!(e.downcast_ref(), Some(TrySendError::ChannelFull)) =>
+        {
+            SPANS_QUEUE_FULL.fetch_add(1, Ordering::Relaxed);
+        }
+        global::Error::Trace(e) => eprintln!("OpenTelemetry trace error occurred. {e}"),
+        global::Error::Log(e) => eprintln!("OpenTelemetry log error occurred. {e}"),
+        e => eprintln!("OpenTelemetry error occurred. {e}"),
+    }
+}
+
+/// Reports [`telemetry_status`] as counters on `meter`, read on every
+/// collection, so delivery problems can be alerted on like any other metric.
+/// The per endpoint counters carry a [`DESTINATION_KEY`] attribute.
+pub fn register_metrics(meter: &Meter) {
+    observe(
+        meter,
+        "telemetry.spans.queued",
+        "Sampled spans handed to the batch processor.",
+        "{span}",
+        Counter::PerDestination(|s| s.spans_queued, |d| d.spans_queued),
+    );
+    observe(
+        meter,
+        "telemetry.spans.exported",
+        "Spans the collector accepted.",
+        "{span}",
+        Counter::PerDestination(|s| s.spans_exported, |d| d.spans_exported),
+    );
+    observe(
+        meter,
+        "telemetry.spans.dropped",
+        "Spans dropped before export.",
+        "{span}",
+        Counter::Total(|s| s.spans_dropped),
+    );
+    observe(
+        meter,
+        "telemetry.traces.tail_dropped",
+        "Traces the tail sampler decided not to export.",
+        "{trace}",
+        Counter::Total(|s| s.traces_tail_dropped),
+    );
+    observe(
+        meter,
+        "telemetry.traces.tail_evicted",
+        "Traces the tail sampler dropped undecided.",
+        "{trace}",
+        Counter::Total(|s| s.traces_tail_evicted),
+    );
+    observe(
+        meter,
+        "telemetry.export.batches",
+        "Batches the collector accepted.",
+        "{batch}",
+        Counter::PerDestination(|s| s.batches_exported, |d| d.batches_exported),
+    );
+    observe(
+        meter,
+        "telemetry.export.failures",
+        "Span exports that failed.",
+        "{failure}",
+        Counter::PerDestination(|s| s.export_failures, |d| d.export_failures),
+    );
+    observe(
+        meter,
+        "telemetry.export.retries",
+        "Span export attempts repeated after a failure.",
+        "{retry}",
+        Counter::Total(|s| s.export_retries),
+    );
+}
+
+/// Where an observed counter's values come from.
+#[derive(Clone, Copy)]
+enum Counter {
+    /// One value for all destinations.
+    Total(fn(&TelemetryStatus) -> u64),
+    /// The primary endpoint's value and each secondary endpoint's.
+    PerDestination(fn(&TelemetryStatus) -> u64, fn(&DestinationStatus) -> u64),
+}
+
+fn observe(
+    meter: &Meter,
+    name: &'static str,
+    description: &'static str,
+    unit: &'static str,
+    value: Counter,
+) {
+    meter
+        .u64_observable_counter(name)
+        .with_description(description)
+        .with_unit(Unit::new(unit))
+        .with_callback(move |counter| {
+            let status = telemetry_status();
+            match value {
+                Counter::Total(total) => counter.observe(total(&status), &[]),
+                Counter::PerDestination(primary, secondary) => {
+                    counter.observe(
+                        primary(&status),
+                        &[KeyValue::new(DESTINATION_KEY, PRIMARY_DESTINATION)],
+                    );
+                    for destination in &status.secondary_destinations {
+                        counter.observe(
+                            secondary(destination),
+                            &[KeyValue::new(DESTINATION_KEY, destination.name.clone())],
+                        );
+                    }
+                }
+            }
+        })
+        .init();
+}
+
+/// Counts the spans entering the wrapped batch processor into
+/// [`telemetry_status`], so spans still queued at shutdown can be told apart
+/// from those exported.
+#[derive(Debug)]
+pub struct QueueCounter<P> {
+    inner: P,
+    destination: Destination,
+}
+
+impl<P> QueueCounter<P> {
+    pub fn new(inner: P, destination: Destination) -> Self {
+        Self { inner, destination }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for QueueCounter<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        // The batch processor skips unsampled spans too.
+        if span.span_context.is_sampled() {
+            match &self.destination {
+                Destination::Primary => SPANS_QUEUED.fetch_add(1, Ordering::Relaxed),
+                Destination::Secondary(counters) => {
+                    counters.spans_queued.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Counts the outcome of every export of the wrapped exporter into
+/// [`telemetry_status`].
+#[derive(Debug)]
+pub struct CountingExporter<E> {
+    inner: E,
+    destination: Destination,
+}
+
+impl<E> CountingExporter<E> {
+    pub fn new(inner: E, destination: Destination) -> Self {
+        Self { inner, destination }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for CountingExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let len = batch.len() as u64;
+        let export = self.inner.export(batch);
+        let destination = self.destination.clone();
+        Box::pin(async move {
+            let result = export.await;
+            match (&result, destination) {
+                (Ok(()), Destination::Primary) => {
+                    SPANS_EXPORTED.fetch_add(len, Ordering::Relaxed);
+                    BATCHES_EXPORTED.fetch_add(1, Ordering::Relaxed);
+                    CONSECUTIVE_EXPORT_FAILURES.store(0, Ordering::Relaxed);
+                }
+                (Err(e), Destination::Primary) => {
+                    EXPORT_FAILURES.fetch_add(1, Ordering::Relaxed);
+                    CONSECUTIVE_EXPORT_FAILURES.fetch_add(1, Ordering::Relaxed);
+                    *LAST_EXPORT_ERROR.lock().unwrap() = Some(e.to_string());
+                }
+                (Ok(()), Destination::Secondary(counters)) => {
+                    counters.spans_exported.fetch_add(len, Ordering::Relaxed);
+                    counters.batches_exported.fetch_add(1, Ordering::Relaxed);
+                }
+                (Err(_), Destination::Secondary(counters)) => {
+                    counters.export_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
@@ -0,0 +1,248 @@
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{Span, SpanProcessor},
+    },
+    trace::{Span as _, SpanId, Status, TraceContextExt, TraceId, TraceResult},
+    Context,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::clock;
+
+static TAIL_DROPPED_TRACES: AtomicU64 = AtomicU64::new(0);
+static TAIL_EVICTED_TRACES: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of traces [`TailSampler`] decided not to export since startup.
+pub fn tail_dropped_traces() -> u64 {
+    TAIL_DROPPED_TRACES.load(Ordering::Relaxed)
+}
+
+/// Total number of traces [`TailSampler`] dropped undecided, because their
+/// window ran out or the buffer was full.
+pub fn tail_evicted_traces() -> u64 {
+    TAIL_EVICTED_TRACES.load(Ordering::Relaxed)
+}
+
+/// Buffers spans per trace and exports only the traces that contain an error
+/// or whose local root span took at least `latency_threshold`.
+///
+/// A trace is decided as soon as a span in it ends with an error, or else when
+/// its local root ends. Spans ending after the decision follow it. Traces
+/// still undecided after `window`, or the oldest ones once more than
+/// `max_spans` are buffered, are dropped.
+///
+/// A flush exports the undecided traces already running for longer than
+/// `latency_threshold`; shutdown also drops the rest, as decided.
+#[derive(Debug)]
+pub struct TailSampler {
+    inner: Box<dyn SpanProcessor>,
+    latency_threshold: Duration,
+    window: Duration,
+    max_spans: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: HashMap<TraceId, PendingTrace>,
+    /// Pending traces, oldest first. May name traces already decided.
+    order: VecDeque<TraceId>,
+    buffered_spans: usize,
+    /// Traces decided recently, and whether they are kept.
+    decided: HashMap<TraceId, (bool, SystemTime)>,
+}
+
+#[derive(Debug)]
+struct PendingTrace {
+    started: SystemTime,
+    local_roots: HashSet<SpanId>,
+    spans: Vec<SpanData>,
+}
+
+impl PendingTrace {
+    fn new(started: SystemTime) -> Self {
+        Self {
+            started,
+            local_roots: HashSet::new(),
+            spans: Vec::new(),
+        }
+    }
+}
+
+impl TailSampler {
+    pub fn new(
+        inner: Box<dyn SpanProcessor>,
+        latency_threshold: Duration,
+        window: Duration,
+        max_spans: usize,
+    ) -> Self {
+        Self {
+            inner,
+            latency_threshold,
+            window,
+            max_spans,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Drops undecided traces older than the window, and forgets decisions
+    /// older than it.
+    fn expire(&self, state: &mut State, now: SystemTime) {
+        let expired =
+            |since: SystemTime| now.duration_since(since).unwrap_or_default() > self.window;
+
+        while let Some(trace_id) = state.order.front().copied() {
+            match state.pending.get(&trace_id) {
+                Some(trace) if !expired(trace.started) => break,
+                Some(_) => {
+                    let trace = state.pending.remove(&trace_id).expect("checked above");
+                    state.buffered_spans -= trace.spans.len();
+                    TAIL_EVICTED_TRACES.fetch_add(1, Ordering::Relaxed);
+                }
+                None => {}
+            }
+            state.order.pop_front();
+        }
+        state.decided.retain(|_, (_, at)| !expired(*at));
+    }
+
+    fn evict_oldest(&self, state: &mut State) {
+        while let Some(trace_id) = state.order.pop_front() {
+            if let Some(trace) = state.pending.remove(&trace_id) {
+                state.buffered_spans -= trace.spans.len();
+                TAIL_EVICTED_TRACES.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Keeps the pending traces that started at least `latency_threshold` ago,
+    /// as their local root cannot end any sooner, and with `all` drops the
+    /// rest.
+    fn settle(&self, all: bool) {
+        let now = clock::now();
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state, now);
+
+        let pending: Vec<_> = state
+            .pending
+            .iter()
+            .map(|(&trace_id, trace)| {
+                let running = now.duration_since(trace.started).unwrap_or_default();
+                (trace_id, running >= self.latency_threshold)
+            })
+            .collect();
+        for (trace_id, slow) in pending {
+            if slow || all {
+                self.decide(&mut state, trace_id, slow, now);
+            }
+        }
+    }
+
+    fn decide(&self, state: &mut State, trace_id: TraceId, keep: bool, now: SystemTime) {
+        state.decided.insert(trace_id, (keep, now));
+        if let Some(trace) = state.pending.remove(&trace_id) {
+            state.buffered_spans -= trace.spans.len();
+            if keep {
+                for span in trace.spans {
+                    self.inner.on_end(span);
+                }
+            } else {
+                TAIL_DROPPED_TRACES.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl SpanProcessor for TailSampler {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+
+        let is_local_root = !cx.has_active_span() || cx.span().span_context().is_remote();
+        if !is_local_root {
+            return;
+        }
+
+        let now = clock::now();
+        let span_context = span.span_context();
+        let trace_id = span_context.trace_id();
+        let mut state = self.state.lock().unwrap();
+        if state.decided.contains_key(&trace_id) {
+            return;
+        }
+        if !state.pending.contains_key(&trace_id) {
+            state.order.push_back(trace_id);
+        }
+        state
+            .pending
+            .entry(trace_id)
+            .or_insert_with(|| PendingTrace::new(now))
+            .local_roots
+            .insert(span_context.span_id());
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let now = clock::now();
+        let trace_id = span.span_context.trace_id();
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state, now);
+
+        if let Some(&(keep, _)) = state.decided.get(&trace_id) {
+            drop(state);
+            if keep {
+                self.inner.on_end(span);
+            }
+            return;
+        }
+
+        let is_error = matches!(span.status, Status::Error { .. });
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+        let trace = state
+            .pending
+            .entry(trace_id)
+            .or_insert_with(|| PendingTrace::new(now));
+        let is_new = trace.spans.is_empty() && trace.local_roots.is_empty();
+        let is_local_root = trace.local_roots.contains(&span.span_context.span_id());
+        trace.spans.push(span);
+        state.buffered_spans += 1;
+        if is_new {
+            state.order.push_back(trace_id);
+        }
+
+        if is_error {
+            self.decide(&mut state, trace_id, true, now);
+        } else if is_local_root {
+            self.decide(
+                &mut state,
+                trace_id,
+                duration >= self.latency_threshold,
+                now,
+            );
+        }
+
+        while state.buffered_spans > self.max_spans {
+            self.evict_oldest(&mut state);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.settle(false);
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.settle(true);
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,226 @@
+use opentelemetry::sdk::trace::Sampler;
+use std::time::Duration;
+
+use crate::config::{self, local_collector_endpoint, Settings, SpanLimitsPreset, TelemetryMode};
+use crate::error::TelemetryError;
+use crate::export::{ExportCompression, ExportProtocol, HttpEncoding};
+use crate::logging::LogFormat;
+use crate::oauth::OAuth2Settings;
+use crate::propagation::Propagators;
+use crate::startup::{self, TelemetryGuard};
+use crate::tls::ExportTls;
+
+/// Configures and installs the pipeline in code, as an alternative to the
+/// environment variables read by [`config::load_settings`]:
+/// `TelemetryBuilder::new().endpoint(..).basic_auth(..).service_name(..).install()?`.
+#[derive(Default)]
+pub struct TelemetryBuilder {
+    settings: Settings,
+}
+
+impl TelemetryBuilder {
+    /// Starts from [`Settings::default`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from the settings in the environment, for overriding some of
+    /// them in code.
+    pub fn from_env() -> Result<Self, TelemetryError> {
+        config::load_settings().map(Self::from_settings)
+    }
+
+    pub fn from_settings(settings: Settings) -> Self {
+        Self { settings }
+    }
+
+    /// Where spans go; [`TelemetryMode::Stdout`] needs no endpoint or credentials.
+    pub fn mode(mut self, mode: TelemetryMode) -> Self {
+        self.settings.mode = mode;
+        self
+    }
+
+    /// The OTLP/HTTP traces endpoint.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.settings.otel_endpoint = endpoint.into();
+        self
+    }
+
+    /// Credentials for the Basic auth header sent with every export.
+    pub fn basic_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.settings.otel_username = username.into();
+        self.settings.otel_password = password.into();
+        self.settings.bearer_token = None;
+        self.settings.oauth2 = None;
+        self.settings.local_collector = false;
+        self
+    }
+
+    /// Adds a header sent with every export, such as `X-Scope-OrgID`.
+    pub fn export_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.settings
+            .export_headers
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// Fetches export access tokens with the OAuth2 client credentials flow
+    /// and refreshes them before they expire.
+    pub fn oauth2(mut self, oauth2: OAuth2Settings) -> Self {
+        self.settings.oauth2 = Some(oauth2);
+        self.settings.local_collector = false;
+        self
+    }
+
+    /// CA bundle and client certificate for gateways that require mutual TLS.
+    pub fn export_tls(mut self, tls: ExportTls) -> Self {
+        self.settings.export_tls = tls;
+        self
+    }
+
+    /// A token sent as `Authorization: Bearer <token>` with every export.
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.settings.bearer_token = Some(token.into());
+        self.settings.local_collector = false;
+        self
+    }
+
+    /// Exports without credentials to a collector sidecar at
+    /// [`local_collector_endpoint`], which handles auth to Tempo itself.
+    pub fn local_collector(mut self) -> Self {
+        self.settings.otel_endpoint =
+            String::from(local_collector_endpoint(self.settings.export_protocol));
+        self.settings.local_collector = true;
+        self
+    }
+
+    /// Sends spans over OTLP/HTTP (the default) or OTLP/gRPC. The gRPC
+    /// endpoint has no `/v1/traces` path, e.g. `http://tempo:4317`.
+    pub fn export_protocol(mut self, protocol: ExportProtocol) -> Self {
+        self.settings.export_protocol = protocol;
+        if self.settings.local_collector {
+            self.settings.otel_endpoint = String::from(local_collector_endpoint(protocol));
+        }
+        self
+    }
+
+    /// Sends spans over OTLP/HTTP as protobuf (the default) or as JSON, for
+    /// proxies that mangle binary bodies or for reading the payloads.
+    /// Metrics and logs stay protobuf.
+    pub fn http_encoding(mut self, encoding: HttpEncoding) -> Self {
+        self.settings.http_encoding = encoding;
+        self
+    }
+
+    /// Gzips OTLP/HTTP request bodies.
+    pub fn export_compression(mut self, compression: ExportCompression) -> Self {
+        self.settings.export_compression = compression;
+        self
+    }
+
+    /// Sends an empty span export at startup and warns with the endpoint,
+    /// auth mode and response when it fails.
+    pub fn export_preflight(mut self, export_preflight: bool) -> Self {
+        self.settings.export_preflight = export_preflight;
+        self
+    }
+
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.settings.service_name = Some(service_name.into());
+        self
+    }
+
+    pub fn service_namespace(mut self, service_namespace: impl Into<String>) -> Self {
+        self.settings.service_namespace = Some(service_namespace.into());
+        self
+    }
+
+    pub fn service_version(mut self, service_version: impl Into<String>) -> Self {
+        self.settings.service_version = Some(service_version.into());
+        self
+    }
+
+    pub fn environment(mut self, environment: impl Into<String>) -> Self {
+        self.settings.environment = Some(environment.into());
+        self
+    }
+
+    /// Replaces the environment's default sampler.
+    pub fn sampler(mut self, sampler: Sampler) -> Self {
+        self.settings.sampler = Some(sampler);
+        self
+    }
+
+    /// Replaces the W3C trace context and baggage default, e.g. with B3 as well.
+    pub fn propagators(mut self, propagators: Propagators) -> Self {
+        self.settings.propagators = propagators;
+        self
+    }
+
+    pub fn span_limits(mut self, span_limits: SpanLimitsPreset) -> Self {
+        self.settings.span_limits = span_limits;
+        self
+    }
+
+    /// Forces a flush on this interval.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.settings.flush_interval = Some(interval);
+        self
+    }
+
+    /// Also exports HTTP server metrics over OTLP. Tempo itself does not
+    /// accept metrics, so point them at a collector or gateway that does.
+    pub fn metrics(mut self, metrics: bool) -> Self {
+        self.settings.metrics = metrics;
+        self
+    }
+
+    /// Also exports log events over OTLP, correlated with their traces.
+    pub fn logs(mut self, logs: bool) -> Self {
+        self.settings.logs = logs;
+        self
+    }
+
+    /// Serves metrics for scraping; mount [`crate::prometheus::router`].
+    pub fn prometheus(mut self, prometheus: bool) -> Self {
+        self.settings.prometheus = prometheus;
+        self
+    }
+
+    /// Serves the log level endpoint; mount [`crate::admin::router`].
+    pub fn admin_endpoints(mut self, admin_endpoints: bool) -> Self {
+        self.settings.admin_endpoints = admin_endpoints;
+        self
+    }
+
+    /// Serves `/healthz`, `/livez` and `/readyz`, on by default; mount
+    /// [`crate::health::health_routes`] and [`crate::health::readiness_routes`].
+    pub fn health_endpoints(mut self, health_endpoints: bool) -> Self {
+        self.settings.health_endpoints = health_endpoints;
+        self
+    }
+
+    /// `Json` writes one object per line with `trace_id` and `span_id`, for
+    /// Loki to link log lines to traces in Tempo.
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.settings.log_format = log_format;
+        self
+    }
+
+    /// Starts with logging only when span export cannot be set up.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.settings.fail_open = fail_open;
+        self
+    }
+
+    /// For the settings without a dedicated method.
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Sets up span export and installs the global subscriber, like
+    /// [`startup::init_telemetry`].
+    pub fn install(self) -> Result<TelemetryGuard, TelemetryError> {
+        startup::init_telemetry(self.settings)
+    }
+}
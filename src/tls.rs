@@ -0,0 +1,197 @@
+use axum::http::Uri;
+use futures_util::future::BoxFuture;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_native_tls::TlsStream;
+use tonic::transport::server::Connected;
+
+use crate::error::TelemetryError;
+
+/// PEM files securing the export connection, for gateways that require
+/// mutual TLS. The client key must be PKCS#8.
+#[derive(Clone, Debug, Default)]
+pub struct ExportTls {
+    /// CA bundle trusted in addition to the system roots.
+    pub ca_file: Option<PathBuf>,
+    /// Client certificate presented to the backend.
+    pub client_cert_file: Option<PathBuf>,
+    /// Private key of the client certificate.
+    pub client_key_file: Option<PathBuf>,
+}
+
+impl ExportTls {
+    /// Configures the reqwest client used for OTLP/HTTP.
+    pub fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, TelemetryError> {
+        if let Some(ca) = &self.ca_file {
+            let certificate =
+                reqwest::Certificate::from_pem(&read(ca)?).map_err(TelemetryError::ExportClient)?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(PemIdentity { cert, key }) = self.client_identity()? {
+            let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+                .map_err(TelemetryError::ExportClient)?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder)
+    }
+
+    /// Builds the connector for OTLP/gRPC, which also handles plain `http`
+    /// endpoints.
+    pub fn grpc_connector(&self) -> Result<GrpcConnector, TelemetryError> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.request_alpns(&["h2"]);
+        if let Some(ca) = &self.ca_file {
+            let certificate =
+                native_tls::Certificate::from_pem(&read(ca)?).map_err(|e| invalid_file(ca, e))?;
+            builder.add_root_certificate(certificate);
+        }
+        if let Some(PemIdentity { cert, key }) = self.client_identity()? {
+            let identity = native_tls::Identity::from_pkcs8(&cert, &key).map_err(|e| {
+                TelemetryError::InvalidSetting {
+                    var: "OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE",
+                    reason: e.to_string(),
+                }
+            })?;
+            builder.identity(identity);
+        }
+        let tls = builder
+            .build()
+            .map_err(|e| TelemetryError::InvalidSetting {
+                var: "OTEL_EXPORTER_OTLP_CERTIFICATE",
+                reason: e.to_string(),
+            })?;
+        Ok(GrpcConnector { tls: tls.into() })
+    }
+
+    fn client_identity(&self) -> Result<Option<PemIdentity>, TelemetryError> {
+        match (&self.client_cert_file, &self.client_key_file) {
+            (Some(cert), Some(key)) => Ok(Some(PemIdentity {
+                cert: read(cert)?,
+                key: read(key)?,
+            })),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(TelemetryError::MissingSetting(
+                "OTEL_EXPORTER_OTLP_CLIENT_KEY",
+            )),
+            (None, Some(_)) => Err(TelemetryError::MissingSetting(
+                "OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE",
+            )),
+        }
+    }
+}
+
+struct PemIdentity {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TelemetryError> {
+    fs::read(path).map_err(|e| invalid_file(path, e))
+}
+
+fn invalid_file(path: &Path, e: impl ToString) -> TelemetryError {
+    TelemetryError::TlsFile {
+        path: path.to_owned(),
+        reason: e.to_string(),
+    }
+}
+
+/// Opens the connections of the OTLP/gRPC channel, with TLS for `https`
+/// endpoints.
+#[derive(Clone)]
+pub struct GrpcConnector {
+    tls: tokio_native_tls::TlsConnector,
+}
+
+impl tower::Service<Uri> for GrpcConnector {
+    type Response = GrpcStream;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<GrpcStream>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let tls = self.tls.clone();
+        Box::pin(async move {
+            let https = uri.scheme_str() == Some("https");
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "endpoint has no host"))?
+                .to_owned();
+            let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+            let tcp = TcpStream::connect((host.as_str(), port)).await?;
+            tcp.set_nodelay(true)?;
+
+            if !https {
+                return Ok(GrpcStream::Plain(tcp));
+            }
+            let stream = tls.connect(&host, tcp).await.map_err(io::Error::other)?;
+            Ok(GrpcStream::Tls(Box::new(stream)))
+        })
+    }
+}
+
+/// A connection opened by [`GrpcConnector`].
+pub enum GrpcStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Connected for GrpcStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl AsyncRead for GrpcStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            GrpcStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for GrpcStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            GrpcStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            GrpcStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            GrpcStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            GrpcStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            GrpcStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
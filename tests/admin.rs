@@ -0,0 +1,66 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use axum_otel_tempo::{admin, config::TelemetryMode, TelemetryBuilder};
+use tower::ServiceExt;
+use tracing::Level;
+
+/// Sends `request` to the admin router, returning the status and body.
+async fn send(request: Request<Body>) -> (StatusCode, String) {
+    let response = Router::<()>::new()
+        .merge(admin::router())
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn get_log_level() -> (StatusCode, String) {
+    send(
+        Request::get("/admin/log-level")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+}
+
+async fn put_log_level(directives: &'static str) -> (StatusCode, String) {
+    send(
+        Request::put("/admin/log-level")
+            .body(Body::from(directives))
+            .unwrap(),
+    )
+    .await
+}
+
+// One test, as the subscriber is process wide.
+#[tokio::test]
+async fn log_level_can_be_read_and_changed_at_runtime() {
+    // Nothing to change before the subscriber is installed.
+    assert_eq!(get_log_level().await.0, StatusCode::NOT_FOUND);
+    assert_eq!(put_log_level("debug").await.0, StatusCode::NOT_FOUND);
+
+    let mut builder = TelemetryBuilder::new().mode(TelemetryMode::Disabled);
+    builder.settings_mut().log_filter = Some(String::from("info"));
+    let _telemetry = builder.install().unwrap();
+    assert_eq!(
+        get_log_level().await,
+        (StatusCode::OK, String::from("info"))
+    );
+    assert!(!tracing::enabled!(target: "axum_otel_tempo", Level::DEBUG));
+
+    let (status, body) = put_log_level("axum_otel_tempo=debug,warn\n").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, get_log_level().await.1);
+    assert!(body.contains("axum_otel_tempo=debug"), "{body}");
+    assert!(tracing::enabled!(target: "axum_otel_tempo", Level::DEBUG));
+
+    let (status, body) = put_log_level("axum_otel_tempo=loud").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.starts_with("cannot parse filter directives"), "{body}");
+    assert!(get_log_level().await.1.contains("axum_otel_tempo=debug"));
+}
@@ -0,0 +1,115 @@
+use axum::{
+    body::Body,
+    http::Request,
+    middleware::{from_fn, from_fn_with_state, Next},
+    routing::get,
+    Router,
+};
+use axum_otel_tempo::{
+    clock::{self, ClockEndTime, ClockStartTime, ManualClock},
+    middleware::{self, RequestCompleteHook},
+    processors::MinDurationFilter,
+};
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{self, SpanProcessor, TracerProvider},
+    },
+    trace::{TraceResult, TracerProvider as _},
+    Context,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tower::ServiceExt;
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+/// The clock is process wide, so tests installing one take turns.
+static CLOCK: Mutex<()> = Mutex::new(());
+
+/// Installs a manual clock for the duration of the returned guard.
+fn manual_clock() -> (ManualClock, std::sync::MutexGuard<'static, ()>) {
+    let guard = CLOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    clock::set_clock(clock.clone());
+    (clock, guard)
+}
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn span_durations_follow_the_manual_clock() {
+    let (clock, _guard) = manual_clock();
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(ClockEndTime::new(Box::new(MinDurationFilter::new(
+            Box::new(collected.clone()),
+            Duration::from_millis(10),
+        ))))
+        .build();
+    let subscriber = Registry::default()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+        .with(ClockStartTime);
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("parent").in_scope(|| {
+            tracing::info_span!("fast").in_scope(|| clock.advance(Duration::from_millis(9)));
+            tracing::info_span!("slow").in_scope(|| clock.advance(Duration::from_millis(10)));
+        });
+    });
+
+    let spans = collected.0.lock().unwrap();
+    let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+    assert_eq!(names, ["slow", "parent"]);
+    let durations: Vec<_> = spans
+        .iter()
+        .map(|span| span.end_time.duration_since(span.start_time).unwrap())
+        .collect();
+    assert_eq!(
+        durations,
+        [Duration::from_millis(10), Duration::from_millis(19)]
+    );
+}
+
+#[test]
+fn request_duration_follows_the_manual_clock() {
+    let (clock, _guard) = manual_clock();
+    let durations = Arc::new(Mutex::new(Vec::new()));
+    let recorded = durations.clone();
+    let hook: RequestCompleteHook =
+        Arc::new(move |summary| recorded.lock().unwrap().push(summary.duration));
+    let app = Router::new()
+        .route("/", get(|| async { "ok" }))
+        .layer(from_fn(move |req: Request<Body>, next: Next<Body>| {
+            clock.advance(Duration::from_millis(250));
+            next.run(req)
+        }))
+        .layer(from_fn_with_state(hook, middleware::request_complete));
+
+    let request = Request::get("/").body(Body::empty()).unwrap();
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(app.oneshot(request))
+        .unwrap();
+
+    assert_eq!(*durations.lock().unwrap(), [Duration::from_millis(250)]);
+}
@@ -0,0 +1,77 @@
+use axum_otel_tempo::{
+    config::{FileConfig, Settings, SpanLimitsPreset},
+    load_settings, TelemetryError,
+};
+use std::{env, path::Path};
+
+#[test]
+fn sampler_and_schedule_have_their_own_keys() {
+    let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "sampler = \"parentbased_traceidratio:0.25\"\nsampling_schedule = \"*=0.1\"\n",
+    )
+    .unwrap();
+    let config = FileConfig::load(&path, true);
+    std::fs::remove_file(&path).unwrap();
+    let config = config.unwrap();
+
+    assert_eq!(
+        config.value_for("OtelTempoSampler").as_deref(),
+        Some("parentbased_traceidratio:0.25")
+    );
+    assert_eq!(
+        config.value_for("OtelTempoSamplingSchedule").as_deref(),
+        Some("*=0.1")
+    );
+}
+
+#[test]
+fn example_config_parses() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config.toml.example");
+    FileConfig::load(&path, true).unwrap();
+}
+
+/// Loads the settings with `vars` set, against a local collector so no
+/// credentials are needed.
+fn settings_with(vars: &[(&str, &str)]) -> Result<Settings, TelemetryError> {
+    env::set_var("OtelTempoLocalCollector", "true");
+    for (var, value) in vars {
+        env::set_var(var, value);
+    }
+    let settings = load_settings();
+    for (var, _) in vars {
+        env::remove_var(var);
+    }
+    settings
+}
+
+// One test, as the variables are process wide.
+#[test]
+fn otel_attribute_limits_are_parsed_and_validated() {
+    let settings = settings_with(&[]).unwrap();
+    assert_eq!(settings.attribute_value_length_limit, None);
+    assert_eq!(settings.attribute_count_limit, None);
+    assert_eq!(settings.span_limits, SpanLimitsPreset::Default);
+
+    let settings = settings_with(&[
+        ("OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT", "4096"),
+        ("OTEL_ATTRIBUTE_COUNT_LIMIT", "64"),
+    ])
+    .unwrap();
+    assert_eq!(settings.attribute_value_length_limit, Some(4096));
+    assert_eq!(settings.attribute_count_limit, Some(64));
+
+    for (var, value) in [
+        ("OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT", "0"),
+        ("OTEL_ATTRIBUTE_VALUE_LENGTH_LIMIT", "-1"),
+        ("OTEL_ATTRIBUTE_COUNT_LIMIT", "0"),
+        ("OTEL_ATTRIBUTE_COUNT_LIMIT", "many"),
+    ] {
+        match settings_with(&[(var, value)]) {
+            Err(TelemetryError::InvalidSetting { var: invalid, .. }) => assert_eq!(invalid, var),
+            Err(e) => panic!("{var}={value} gave {e:?}"),
+            Ok(_) => panic!("{var}={value} was accepted"),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+use axum_otel_tempo::{
+    clock::{self, ManualClock},
+    export::{
+        self, CircuitBreaker, ExportClient, ExportCompression, HttpEncoding, RecoveryBuffer,
+        RetryingExporter, TenantRoutingExporter,
+    },
+    resource::Signal,
+    status::{self, CountingExporter, Destination},
+};
+use flate2::read::GzDecoder;
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::{self, SpanProcessor, TracerProvider},
+    },
+    trace::{Span, TraceError, TraceResult, Tracer, TracerProvider as _},
+    Context, KeyValue,
+};
+use opentelemetry_http::{HttpClient, Request};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsPartialSuccess, ExportMetricsServiceResponse,
+};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+
+/// Serves an OTLP/HTTP endpoint at `path` answering every export with `body`.
+fn collector(path: &str, body: Vec<u8>) -> SocketAddr {
+    let app = Router::new().route(path, post(move || async move { body }));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+/// Log output collected in memory.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn metrics_partial_success_is_reported_as_data_points() {
+    let response = ExportMetricsServiceResponse {
+        partial_success: Some(ExportMetricsPartialSuccess {
+            rejected_data_points: 3,
+            error_message: String::from("too many series"),
+        }),
+    };
+    let addr = collector("/v1/metrics", response.encode_to_vec());
+    let client = ExportClient::new(reqwest::Client::new(), HttpEncoding::Protobuf)
+        .with_signal(Signal::Metrics);
+
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let request = Request::post(format!("http://{addr}/v1/metrics"))
+        .body(Vec::new())
+        .unwrap();
+    client.send(request).await.unwrap();
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        logs.contains("Collector partially rejected exported metric data points")
+            && logs.contains("rejected_data_points=3")
+            && logs.contains("too many series"),
+        "no partial success warning in {logs:?}"
+    );
+    assert!(!logs.contains("spans"), "reported as spans in {logs:?}");
+}
+
+/// The `content-encoding` and gunzipped body of the last export.
+type Received = Arc<Mutex<Option<(String, Vec<u8>)>>>;
+
+/// Serves an OTLP/HTTP traces endpoint keeping what it last received.
+fn gunzipping_collector(received: Received) -> SocketAddr {
+    let app = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                    let encoding = headers["content-encoding"].to_str().unwrap().to_owned();
+                    let mut decoded = Vec::new();
+                    GzDecoder::new(&body[..]).read_to_end(&mut decoded).unwrap();
+                    *received.lock().unwrap() = Some((encoding, decoded));
+                },
+            ),
+        )
+        .with_state(received);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+#[tokio::test]
+async fn gzip_bodies_round_trip_through_a_standard_decoder() {
+    let received = Received::default();
+    let addr = gunzipping_collector(Arc::clone(&received));
+    let client = ExportClient::new(reqwest::Client::new(), HttpEncoding::Protobuf)
+        .with_compression(ExportCompression::Gzip);
+    let body: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+    let request = Request::post(format!("http://{addr}/v1/traces"))
+        .body(body.clone())
+        .unwrap();
+    let response = client.send(request).await.unwrap();
+
+    assert!(response.status().is_success());
+    let (encoding, decoded) = received.lock().unwrap().take().unwrap();
+    assert_eq!(encoding, "gzip");
+    assert_eq!(decoded, body);
+}
+
+/// Span names exported per org id, `None` being the default tenant.
+type ByOrgId = Arc<Mutex<Vec<(Option<String>, String)>>>;
+
+/// Records the span names it exports under the org id it was built for.
+#[derive(Debug)]
+struct TenantExporter {
+    org_id: Option<String>,
+    exported: ByOrgId,
+}
+
+impl SpanExporter for TenantExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let mut exported = self.exported.lock().unwrap();
+        for span in batch {
+            exported.push((self.org_id.clone(), span.name.into_owned()));
+        }
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+#[test]
+fn unmapped_tenants_go_to_the_default_tenant() {
+    let exported = ByOrgId::default();
+    let factory_exported = exported.clone();
+    let router = export::org_id_router(HashMap::from([(
+        String::from("acme"),
+        String::from("org-acme"),
+    )]));
+    let exporter = TenantRoutingExporter::new(
+        "tenant.id",
+        router,
+        Box::new(move |org_id| {
+            Ok(Box::new(TenantExporter {
+                org_id: org_id.map(str::to_owned),
+                exported: factory_exported.clone(),
+            }) as Box<dyn SpanExporter>)
+        }),
+    );
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .build();
+    let tracer = provider.tracer("test");
+
+    for (name, tenant) in [
+        ("mapped", Some("acme")),
+        ("unmapped", Some("org-of-my-choosing")),
+        ("untagged", None),
+    ] {
+        let mut builder = tracer.span_builder(name);
+        if let Some(tenant) = tenant {
+            builder = builder.with_attributes(vec![KeyValue::new("tenant.id", tenant)]);
+        }
+        builder.start(&tracer).end();
+    }
+    provider.force_flush();
+
+    assert_eq!(
+        *exported.lock().unwrap(),
+        [
+            (Some(String::from("org-acme")), String::from("mapped")),
+            (None, String::from("unmapped")),
+            (None, String::from("untagged")),
+        ]
+    );
+}
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// A batch of spans with the given names.
+fn batch(names: &[&'static str]) -> Vec<SpanData> {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(collected.clone())
+        .build();
+    let tracer = provider.tracer("test");
+    for &name in names {
+        tracer.start(name).end();
+    }
+    let spans = collected.0.lock().unwrap().clone();
+    spans
+}
+
+/// Fails the next `failures` exports, then records the span names of the
+/// batches it accepts. Notes the time of every attempt.
+#[derive(Clone, Debug, Default)]
+struct Flaky {
+    failures: Arc<AtomicU32>,
+    attempts: Arc<Mutex<Vec<Instant>>>,
+    exported: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl Flaky {
+    fn failing(failures: u32) -> Self {
+        let flaky = Self::default();
+        flaky.failures.store(failures, Ordering::SeqCst);
+        flaky
+    }
+
+    fn exported(&self) -> Vec<Vec<String>> {
+        std::mem::take(&mut *self.exported.lock().unwrap())
+    }
+
+    /// The waits between consecutive attempts.
+    fn waits(&self) -> Vec<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        attempts.windows(2).map(|w| w[1] - w[0]).collect()
+    }
+}
+
+impl SpanExporter for Flaky {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.attempts.lock().unwrap().push(Instant::now());
+        let failing = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            return Box::pin(future::ready(Err(TraceError::from("backend is down"))));
+        }
+        let names = batch.iter().map(|span| span.name.to_string()).collect();
+        self.exported.lock().unwrap().push(names);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+#[tokio::test]
+async fn recovery_buffer_keeps_the_newest_spans_and_sends_them_once_the_backend_recovers() {
+    let backend = Flaky::failing(2);
+    let mut buffer = RecoveryBuffer::new(backend.clone(), 3);
+    let evicted = export::recovery_evicted_spans();
+
+    assert!(buffer.export(batch(&["a", "b"])).await.is_err());
+    assert!(buffer.export(batch(&["c", "d"])).await.is_err());
+    assert_eq!(export::recovery_evicted_spans(), evicted + 1);
+
+    buffer.export(batch(&["e"])).await.unwrap();
+    buffer.export(batch(&["f"])).await.unwrap();
+    assert_eq!(backend.exported(), [vec!["b", "c", "d", "e"], vec!["f"]]);
+}
+
+/// The retry counter is process wide, so tests reading it take turns.
+static RETRIES: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test(start_paused = true)]
+async fn retries_back_off_with_jitter_until_an_export_succeeds() {
+    let _guard = RETRIES.lock().await;
+    let retries = export::export_retries();
+    let backend = Flaky::failing(2);
+    let mut exporter = RetryingExporter::new(backend.clone(), 5, Duration::from_millis(100));
+
+    exporter.export(batch(&["work"])).await.unwrap();
+
+    assert_eq!(backend.exported(), [vec!["work"]]);
+    assert_eq!(export::export_retries(), retries + 2);
+    // Each wait is jittered to between half and all of the doubling backoff,
+    // rounded up to the timer's millisecond.
+    let waits = backend.waits();
+    assert_eq!(waits.len(), 2);
+    assert!(
+        (Duration::from_millis(50)..=Duration::from_millis(100)).contains(&waits[0]),
+        "first wait {:?}",
+        waits[0]
+    );
+    assert!(
+        (Duration::from_millis(100)..=Duration::from_millis(200)).contains(&waits[1]),
+        "second wait {:?}",
+        waits[1]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn exports_failing_every_attempt_are_counted_as_one_failure() {
+    let _guard = RETRIES.lock().await;
+    let retries = export::export_retries();
+    let backend = Flaky::failing(u32::MAX);
+    let mut exporter = CountingExporter::new(
+        RetryingExporter::new(backend.clone(), 3, Duration::from_millis(10)),
+        Destination::secondary("http://retrying"),
+    );
+
+    assert!(exporter.export(batch(&["work"])).await.is_err());
+
+    assert_eq!(backend.attempts.lock().unwrap().len(), 3);
+    assert_eq!(export::export_retries(), retries + 2);
+    let status = status::telemetry_status();
+    let destination = status
+        .secondary_destinations
+        .iter()
+        .find(|destination| destination.name == "http://retrying")
+        .unwrap();
+    assert_eq!(destination.export_failures, 1);
+    assert_eq!(destination.spans_exported, 0);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_after_failures_and_closes_after_a_trial_export() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    clock::set_clock(clock.clone());
+    let cooldown = Duration::from_secs(30);
+    let backend = Flaky::failing(3);
+    let mut breaker = CircuitBreaker::new(backend.clone(), 2, cooldown);
+    let attempts = || backend.attempts.lock().unwrap().len();
+
+    // Closed: failures reach the backend until there are two in a row.
+    assert!(breaker.export(batch(&["1"])).await.is_err());
+    assert!(!export::export_circuit_open());
+    assert!(breaker.export(batch(&["2"])).await.is_err());
+    assert!(export::export_circuit_open());
+    assert_eq!(attempts(), 2);
+
+    // Open: batches are failed without trying the backend.
+    let error = breaker.export(batch(&["3"])).await.unwrap_err();
+    assert!(error.to_string().contains("circuit is open"), "{error}");
+    assert_eq!(attempts(), 2);
+
+    // Half-open: a failed trial opens the circuit for another cooldown.
+    clock.advance(cooldown);
+    assert!(breaker.export(batch(&["4"])).await.is_err());
+    assert_eq!(attempts(), 3);
+    assert!(breaker.export(batch(&["5"])).await.is_err());
+    assert_eq!(attempts(), 3);
+
+    // Half-open: only the trial goes through, and its success closes the circuit.
+    clock.advance(cooldown);
+    let trial = breaker.export(batch(&["6"]));
+    assert!(breaker.export(batch(&["7"])).await.is_err());
+    trial.await.unwrap();
+    assert!(!export::export_circuit_open());
+    breaker.export(batch(&["8"])).await.unwrap();
+    assert_eq!(backend.exported(), [vec!["6"], vec!["8"]]);
+}
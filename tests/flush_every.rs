@@ -0,0 +1,100 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::Request,
+    middleware::from_fn_with_state,
+    routing::{get, post},
+    Router,
+};
+use axum_otel_tempo::{middleware, TelemetryBuilder};
+use futures_util::future;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use prost::Message;
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tower::ServiceExt;
+
+/// Serves an OTLP/HTTP traces endpoint counting the spans it receives.
+fn collector(received: Arc<AtomicUsize>) -> SocketAddr {
+    let app = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |State(received): State<Arc<AtomicUsize>>, body: Bytes| async move {
+                    let request = ExportTraceServiceRequest::decode(body).unwrap();
+                    let spans = request
+                        .resource_spans
+                        .iter()
+                        .flat_map(|resource| &resource.scope_spans)
+                        .map(|scope| scope.spans.len())
+                        .sum::<usize>();
+                    received.fetch_add(spans, Ordering::SeqCst);
+                },
+            ),
+        )
+        .with_state(received);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+/// Waits a moment for `received` to reach `expected`.
+async fn received_spans(received: &AtomicUsize, expected: usize) -> usize {
+    for _ in 0..50 {
+        if received.load(Ordering::SeqCst) >= expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    received.load(Ordering::SeqCst)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_flushed_once_every_n_requests() {
+    let received = Arc::new(AtomicUsize::new(0));
+    let addr = collector(received.clone());
+
+    let mut builder = TelemetryBuilder::new()
+        .local_collector()
+        .endpoint(format!("http://{addr}/v1/traces"));
+    let settings = builder.settings_mut();
+    settings.export_filter = Some(String::from("info"));
+    // Nothing leaves the queue on schedule, only through the flushes.
+    settings.batch_scheduled_delay = Some(Duration::from_secs(3600));
+    let _telemetry = builder.install().unwrap();
+
+    let app = Router::new()
+        .route(
+            "/",
+            get(|| async { tracing::info_span!("work").in_scope(|| {}) }),
+        )
+        .layer(from_fn_with_state(3, middleware::flush_every));
+    let request = || Request::get("/").body(Body::empty()).unwrap();
+
+    // Finishing together, nine requests still flush three times, the last
+    // one after every span has ended.
+    future::join_all((0..9).map(|_| app.clone().oneshot(request()))).await;
+    assert_eq!(received_spans(&received, 9).await, 9);
+    // Flushes run in the background, so the earlier ones may finish last.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    for _ in 0..2 {
+        app.clone().oneshot(request()).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(received.load(Ordering::SeqCst), 9);
+
+    app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(received_spans(&received, 12).await, 12);
+}
@@ -0,0 +1,57 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use axum_otel_tempo::{health, resource};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn healthz_and_livez_report_the_build() {
+    for path in ["/healthz", "/livez"] {
+        let response = Router::<()>::new()
+            .merge(health::health_routes())
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["version"], resource::BUILD_SERVICE_VERSION);
+        assert!(body["uptime_seconds"].is_u64());
+    }
+}
+
+async fn readyz(endpoint: Option<String>) -> (StatusCode, serde_json::Value) {
+    let response = Router::<()>::new()
+        .merge(health::readiness_routes(endpoint))
+        .oneshot(Request::get("/readyz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn readyz_without_export_is_ready() {
+    let (status, body) = readyz(None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["export"], "disabled");
+}
+
+#[tokio::test]
+async fn readyz_checks_the_endpoint_is_reachable() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (status, body) = readyz(Some(format!("http://{addr}/v1/traces"))).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["status"], "ready");
+
+    drop(listener);
+    let (status, body) = readyz(Some(format!("http://{addr}/v1/traces"))).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "unreachable");
+}
@@ -0,0 +1,486 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::{from_fn, from_fn_with_state},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use axum_otel_tempo::{
+    config::Settings,
+    http_trace::{self, CapturedHeaders, TrustedProxies},
+    middleware::{self, BodyCapture, ErrorMessage, RequestSpanFields},
+    span,
+};
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    global,
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        propagation::TraceContextPropagator,
+        trace::TracerProvider,
+    },
+    trace::{SpanId, SpanKind, Status, TraceId, TracerProvider as _},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tower::ServiceExt;
+use tracing::{subscriber::DefaultGuard, Instrument};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+/// Keeps every exported span for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Exported {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+/// Exports the spans of this thread's tracing to `exported`, until the guard
+/// is dropped.
+fn trace_to(exported: &Exported) -> (TracerProvider, DefaultGuard) {
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exported.clone())
+        .build();
+    let subscriber = Registry::default()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    (provider, tracing::subscriber::set_default(subscriber))
+}
+
+/// A `/users/:id` route behind [`http_trace::layer`].
+fn users(settings: &Settings) -> Router {
+    Router::new()
+        .route("/users/:id", get(|| async { "user" }))
+        .layer(http_trace::layer(settings))
+}
+
+/// Sends `request` through `app` and returns the exported server span.
+async fn server_span(app: Router, request: Request<Body>) -> SpanData {
+    let exported = Exported::default();
+    let (provider, _guard) = trace_to(&exported);
+
+    app.oneshot(request).await.unwrap();
+    provider.force_flush();
+
+    let spans = exported.0.lock().unwrap();
+    assert_eq!(spans.len(), 1, "expected only the server span");
+    spans[0].clone()
+}
+
+/// The string value of `key` on `span`.
+fn attribute(span: &SpanData, key: &str) -> Option<String> {
+    span.attributes
+        .iter()
+        .find(|(k, _)| k.as_str() == key)
+        .map(|(_, value)| value.as_str().into_owned())
+}
+
+#[tokio::test]
+async fn request_span_attribute_keys_are_unique_and_filtered() {
+    let settings = Settings {
+        request_span_fields: RequestSpanFields {
+            headers: true,
+            ..RequestSpanFields::default()
+        },
+        capture_request_headers: "x-request-id,authorization"
+            .parse::<CapturedHeaders>()
+            .unwrap()
+            .into(),
+        ..Settings::default()
+    };
+    let request = Request::get("/users/7?page=2")
+        .header("host", "api.example.com:8080")
+        .header("user-agent", "curl/8.0")
+        .header("x-request-id", "abc")
+        .header("authorization", "Bearer secret")
+        .header("cookie", "session=secret")
+        .body(Body::empty())
+        .unwrap();
+
+    let span = server_span(users(&settings), request).await;
+
+    let keys: Vec<_> = span
+        .attributes
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect();
+    let mut unique = keys.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    assert_eq!(keys.len(), unique.len(), "duplicate keys in {keys:?}");
+
+    for key in [
+        "http.request.method",
+        "url.path",
+        "http.route",
+        "server.address",
+    ] {
+        assert!(keys.contains(&key), "{key} missing from {keys:?}");
+    }
+    assert!(keys.contains(&"http.request.header.x-request-id"));
+    assert!(!keys.contains(&"http.request.headers"));
+    assert!(!keys
+        .iter()
+        .any(|key| key.contains("authorization") || key.contains("cookie")));
+}
+
+#[tokio::test]
+async fn request_span_continues_the_callers_trace() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let request = Request::post("/users/7")
+        .header(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )
+        .body(Body::empty())
+        .unwrap();
+
+    let span = server_span(users(&Settings::default()), request).await;
+
+    assert_eq!(
+        span.span_context.trace_id(),
+        TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+    );
+    assert_eq!(
+        span.parent_span_id,
+        SpanId::from_hex("00f067aa0ba902b7").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn path_params_are_recorded_only_when_listed() {
+    let names: Arc<[String]> = vec![String::from("region")].into();
+    let app = Router::new()
+        .route("/regions/:region/users/:id", get(|| async { "user" }))
+        .layer(from_fn_with_state(names, middleware::record_path_params))
+        .layer(http_trace::layer(&Settings::default()));
+    let request = Request::get("/regions/eu-west/users/7")
+        .body(Body::empty())
+        .unwrap();
+
+    let span = server_span(app, request).await;
+
+    assert_eq!(
+        attribute(&span, "http.route.param.region").as_deref(),
+        Some("eu-west")
+    );
+    assert_eq!(attribute(&span, "http.route.param.id"), None);
+}
+
+#[tokio::test]
+async fn correlation_id_is_recorded_on_the_server_span() {
+    let app = users(&Settings::default()).layer(from_fn(middleware::correlation_id));
+    let request = Request::get("/users/7")
+        .header("x-correlation-id", "order-42")
+        .body(Body::empty())
+        .unwrap();
+
+    let span = server_span(app, request).await;
+
+    assert_eq!(
+        attribute(&span, middleware::CORRELATION_ID_KEY).as_deref(),
+        Some("order-42")
+    );
+}
+
+#[test]
+fn trusted_proxies_parse_addresses_and_ranges() {
+    let proxies: TrustedProxies = "10.0.0.0/8, 192.168.1.1,fd00::/8".parse().unwrap();
+
+    for trusted in ["10.1.2.3", "192.168.1.1", "fd12::1", "::ffff:10.0.0.1"] {
+        assert!(proxies.contains(trusted.parse().unwrap()), "{trusted}");
+    }
+    for untrusted in ["11.0.0.1", "192.168.1.2", "fe80::1"] {
+        assert!(!proxies.contains(untrusted.parse().unwrap()), "{untrusted}");
+    }
+    assert!("10.0.0.0/33".parse::<TrustedProxies>().is_err());
+    assert!("proxy.local".parse::<TrustedProxies>().is_err());
+}
+
+/// A request from `peer` carrying `header`.
+fn forwarded_request(peer: &str, header: (&str, &str)) -> Request<Body> {
+    let mut request = Request::get("/users/7")
+        .header(header.0, header.1)
+        .body(Body::empty())
+        .unwrap();
+    let peer = SocketAddr::new(peer.parse::<IpAddr>().unwrap(), 41000);
+    request.extensions_mut().insert(ConnectInfo(peer));
+    request
+}
+
+#[tokio::test]
+async fn client_address_is_taken_from_forwarded_only_behind_a_trusted_proxy() {
+    let settings = Settings {
+        trusted_proxies: "10.0.0.0/8".parse().unwrap(),
+        ..Settings::default()
+    };
+    let cases = [
+        (
+            "10.0.0.1",
+            (
+                "forwarded",
+                r#"for="[2001:db8::7]:4711";proto=https, for=10.0.0.2"#,
+            ),
+            "2001:db8::7",
+        ),
+        (
+            "10.0.0.1",
+            ("forwarded", "for=198.51.100.4:8080"),
+            "198.51.100.4",
+        ),
+        (
+            "10.0.0.1",
+            ("x-forwarded-for", "203.0.113.9, 10.0.0.3"),
+            "203.0.113.9",
+        ),
+        (
+            "198.51.100.1",
+            ("forwarded", "for=203.0.113.9"),
+            "198.51.100.1",
+        ),
+    ];
+
+    for (peer, header, client) in cases {
+        let span = server_span(users(&settings), forwarded_request(peer, header)).await;
+        assert_eq!(
+            attribute(&span, "client.address").as_deref(),
+            Some(client),
+            "{peer} {header:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn server_address_drops_ipv6_brackets() {
+    let request = Request::get("/users/7")
+        .header("host", "[2001:db8::1]:8443")
+        .body(Body::empty())
+        .unwrap();
+
+    let span = server_span(users(&Settings::default()), request).await;
+
+    assert_eq!(
+        attribute(&span, "server.address").as_deref(),
+        Some("2001:db8::1")
+    );
+    assert_eq!(attribute(&span, "server.port").as_deref(), Some("8443"));
+}
+
+#[tokio::test]
+async fn downstream_calls_are_client_spans_between_server_spans() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let backend = users(&Settings::default());
+    let app = Router::new()
+        .route(
+            "/downstream",
+            get(move || async move {
+                let client_span = span::client_span(&Method::GET, "http://backend/users/7");
+                let mut headers = HeaderMap::new();
+                span::inject_context(&client_span, &mut headers);
+                let mut request = Request::get("/users/7").body(Body::empty()).unwrap();
+                *request.headers_mut() = headers;
+                backend
+                    .oneshot(request)
+                    .instrument(client_span)
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(http_trace::layer(&Settings::default()));
+    let exported = Exported::default();
+    let (provider, _guard) = trace_to(&exported);
+
+    app.oneshot(Request::get("/downstream").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    provider.force_flush();
+
+    let spans = exported.0.lock().unwrap();
+    let kinds: Vec<_> = spans
+        .iter()
+        .map(|span| (span.name.as_ref(), span.span_kind.clone()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("GET /users/:id", SpanKind::Server),
+            ("GET", SpanKind::Client),
+            ("GET /downstream", SpanKind::Server),
+        ]
+    );
+    assert_eq!(spans[0].parent_span_id, spans[1].span_context.span_id());
+    assert_eq!(spans[1].parent_span_id, spans[2].span_context.span_id());
+}
+
+/// A route failing with `response`, behind `record_error_status` looking at
+/// the `error.message` field of JSON bodies.
+fn failing(response: fn() -> axum::response::Response) -> Router {
+    let path: Arc<[String]> = vec![String::from("error"), String::from("message")].into();
+    Router::new()
+        .route("/users/:id", get(move || async move { response() }))
+        .layer(from_fn_with_state(path, middleware::record_error_status))
+        .layer(http_trace::layer(&Settings::default()))
+}
+
+#[tokio::test]
+async fn server_error_descriptions_become_the_span_status() {
+    let extension = failing(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Extension(ErrorMessage(String::from("database unavailable"))),
+        )
+            .into_response()
+    });
+    let json = failing(|| {
+        (
+            StatusCode::BAD_GATEWAY,
+            [(header::CONTENT_TYPE, "application/json")],
+            r#"{"error":{"message":"upstream timed out"}}"#,
+        )
+            .into_response()
+    });
+
+    for (app, description) in [
+        (extension, "database unavailable"),
+        (json, "upstream timed out"),
+    ] {
+        let request = Request::get("/users/7").body(Body::empty()).unwrap();
+        let span = server_span(app, request).await;
+
+        assert_eq!(span.status, Status::error(description));
+        assert_eq!(attribute(&span, "otel.status_message"), None);
+    }
+}
+
+#[tokio::test]
+async fn handler_panics_become_500s_with_an_exception_event() {
+    let app = Router::new()
+        .route(
+            "/users/:id",
+            get(|| async {
+                if true {
+                    panic!("user 7 vanished");
+                }
+                "user"
+            }),
+        )
+        .layer(middleware::catch_panic())
+        .layer(http_trace::layer(&Settings::default()));
+    let exported = Exported::default();
+    let (provider, _guard) = trace_to(&exported);
+
+    let request = Request::get("/users/7").body(Body::empty()).unwrap();
+    let status = app.oneshot(request).await.unwrap().status();
+    provider.force_flush();
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let spans = exported.0.lock().unwrap();
+    let event = spans[0]
+        .events
+        .iter()
+        .find(|event| event.name == "exception")
+        .expect("no exception event");
+    let value = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    };
+    assert_eq!(value("exception.type").as_deref(), Some("panic"));
+    assert_eq!(
+        value("exception.message").as_deref(),
+        Some("user 7 vanished")
+    );
+    assert!(value("exception.stacktrace").is_some());
+}
+
+/// Echo routes under `/api` and `/internal` with `capture_bodies` selecting
+/// the JSON and text bodies of `/api/*`, cut off after 8 bytes.
+fn echoing() -> Router {
+    let capture = BodyCapture {
+        routes: vec![String::from("/api/*")],
+        max_bytes: 8,
+        content_types: vec![String::from("application/json"), String::from("text/*")],
+    };
+    let echo = post(|headers: HeaderMap, body: Bytes| async move {
+        let content_type = headers[header::CONTENT_TYPE].clone();
+        ([(header::CONTENT_TYPE, content_type)], body)
+    });
+    Router::new()
+        .route("/api/echo", echo.clone())
+        .route("/internal/echo", echo)
+        .layer(from_fn_with_state(
+            capture.into(),
+            middleware::capture_bodies,
+        ))
+        .layer(http_trace::layer(&Settings::default()))
+}
+
+/// Posts `body` as `content_type` to `path` on [`echoing`], returning the
+/// server span and the response body.
+async fn echo(path: &str, content_type: &str, body: Vec<u8>) -> (SpanData, Bytes) {
+    let exported = Exported::default();
+    let (provider, _guard) = trace_to(&exported);
+
+    let request = Request::post(path)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap();
+    let response = echoing().oneshot(request).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    provider.force_flush();
+
+    let span = exported.0.lock().unwrap()[0].clone();
+    (span, body)
+}
+
+#[tokio::test]
+async fn bodies_are_captured_up_to_max_bytes() {
+    let json = br#"{"name":"ada lovelace"}"#.to_vec();
+    let (span, body) = echo("/api/echo", "application/json; charset=utf-8", json.clone()).await;
+
+    assert_eq!(body, json);
+    for prefix in ["http.request", "http.response"] {
+        assert_eq!(
+            attribute(&span, &format!("{prefix}.body")).as_deref(),
+            Some(r#"{"name":"#)
+        );
+        assert_eq!(
+            attribute(&span, &format!("{prefix}.body.size")),
+            Some(json.len().to_string())
+        );
+    }
+
+    let (span, _) = echo("/api/echo", "text/plain", b"hello".to_vec()).await;
+    assert_eq!(
+        attribute(&span, "http.request.body").as_deref(),
+        Some("hello")
+    );
+}
+
+#[tokio::test]
+async fn bodies_are_captured_only_for_selected_routes_and_content_types() {
+    let json = br#"{"name":"ada lovelace"}"#.to_vec();
+    let (span, body) = echo("/internal/echo", "application/json", json.clone()).await;
+    assert_eq!(body, json);
+    assert_eq!(attribute(&span, "http.request.body"), None);
+    assert_eq!(attribute(&span, "http.response.body"), None);
+
+    let (span, body) = echo("/api/echo", "application/octet-stream", vec![0xff; 16]).await;
+    assert_eq!(body, vec![0xff; 16]);
+    assert_eq!(attribute(&span, "http.request.body"), None);
+    assert_eq!(attribute(&span, "http.response.body.size"), None);
+
+    // Too large to buffer, so passed through as is.
+    let large = vec![b'a'; 1024 * 1024 + 1];
+    let (span, body) = echo("/api/echo", "text/plain", large.clone()).await;
+    assert_eq!(body, large);
+    assert_eq!(attribute(&span, "http.request.body"), None);
+}
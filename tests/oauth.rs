@@ -0,0 +1,219 @@
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Form, Json, Router,
+};
+use axum_otel_tempo::{
+    export::HeaderProvider,
+    oauth::{self, OAuth2Settings, OAuth2Token},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// An access token and its `expires_in`, or the status to fail with.
+type Answer = Result<(&'static str, u64), StatusCode>;
+
+/// The `Authorization` header and form of a token request.
+type TokenRequest = (Option<String>, HashMap<String, String>);
+
+/// What the token endpoint answers, one entry per request.
+#[derive(Clone, Default)]
+struct Script {
+    responses: Arc<Mutex<VecDeque<Answer>>>,
+    requests: Arc<Mutex<Vec<TokenRequest>>>,
+}
+
+impl Script {
+    fn new(responses: impl IntoIterator<Item = Answer>) -> Self {
+        Self {
+            responses: Arc::new(Mutex::new(responses.into_iter().collect())),
+            ..Self::default()
+        }
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+/// Serves the token endpoint at `/token`, returning its settings.
+fn token_endpoint(script: Script) -> OAuth2Settings {
+    let app = Router::new()
+        .route(
+            "/token",
+            post(
+                |State(script): State<Script>,
+                 headers: HeaderMap,
+                 Form(form): Form<HashMap<String, String>>| async move {
+                    let authorization = headers
+                        .get(AUTHORIZATION)
+                        .map(|value| value.to_str().unwrap().to_owned());
+                    script.requests.lock().unwrap().push((authorization, form));
+                    let response: Response = match script.responses.lock().unwrap().pop_front() {
+                        Some(Ok((token, expires_in))) => Json(serde_json::json!({
+                            "access_token": token,
+                            "token_type": "Bearer",
+                            "expires_in": expires_in,
+                        }))
+                        .into_response(),
+                        Some(Err(status)) => status.into_response(),
+                        None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                    };
+                    response
+                },
+            ),
+        )
+        .with_state(script);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    OAuth2Settings {
+        token_url: format!("http://{addr}/token"),
+        client_id: String::from("client"),
+        client_secret: String::from("secret"),
+        scope: Some(String::from("traces:write")),
+    }
+}
+
+/// The `Authorization` header `token` adds to exports, if any.
+fn authorization(token: &OAuth2Token) -> Option<String> {
+    token
+        .headers()
+        .into_iter()
+        .map(|(_, value)| value.to_str().unwrap().to_owned())
+        .next()
+}
+
+/// Lets the refresh task and the endpoint run until `done`, without letting
+/// the paused clock move on its own.
+async fn run_until(mut done: impl FnMut() -> bool) {
+    for _ in 0..100_000 {
+        if done() {
+            return;
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("timed out");
+}
+
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    /// How many token requests have failed, each logged before the refresh
+    /// task waits to retry.
+    fn failures(&self) -> usize {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .matches("Failed to fetch OAuth2 access token")
+            .count()
+    }
+}
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Lets the refresh task and the endpoint run for a while.
+async fn settle() {
+    for _ in 0..1_000 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn tokens_are_cached_and_refreshed_before_they_expire() {
+    let script = Script::new([Ok(("first", 120)), Ok(("second", 120))]);
+    let token = oauth::start(token_endpoint(script.clone()), reqwest::Client::new());
+
+    run_until(|| authorization(&token).is_some()).await;
+    assert_eq!(authorization(&token).as_deref(), Some("Bearer first"));
+    let requests = script.requests.lock().unwrap().clone();
+    assert_eq!(requests[0].0.as_deref(), Some("Basic Y2xpZW50OnNlY3JldA=="));
+    assert_eq!(requests[0].1["grant_type"], "client_credentials");
+    assert_eq!(requests[0].1["scope"], "traces:write");
+
+    // Served from the cache until a minute before it expires.
+    tokio::time::advance(Duration::from_secs(59)).await;
+    settle().await;
+    assert_eq!(authorization(&token).as_deref(), Some("Bearer first"));
+    assert_eq!(script.requests(), 1);
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    run_until(|| authorization(&token).as_deref() == Some("Bearer second")).await;
+    assert_eq!(script.requests(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_refreshes_keep_the_last_token_and_retry() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    // The refresh task runs on this thread, so it logs here too.
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let script = Script::new([
+        Ok(("first", 120)),
+        Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(StatusCode::UNAUTHORIZED),
+        Ok(("second", 120)),
+    ]);
+    let token = oauth::start(token_endpoint(script.clone()), reqwest::Client::new());
+    run_until(|| authorization(&token).is_some()).await;
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    run_until(|| logs.failures() == 1).await;
+    assert_eq!(authorization(&token).as_deref(), Some("Bearer first"));
+
+    // Failed requests are retried every five seconds.
+    tokio::time::advance(Duration::from_secs(4)).await;
+    settle().await;
+    assert_eq!(script.requests(), 2);
+    tokio::time::advance(Duration::from_secs(1)).await;
+    run_until(|| logs.failures() == 2).await;
+    assert_eq!(script.requests(), 3);
+    assert_eq!(authorization(&token).as_deref(), Some("Bearer first"));
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    run_until(|| authorization(&token).as_deref() == Some("Bearer second")).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn no_token_is_sent_until_one_is_fetched() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let script = Script::new([Err(StatusCode::INTERNAL_SERVER_ERROR), Ok(("first", 3600))]);
+    let token = oauth::start(token_endpoint(script.clone()), reqwest::Client::new());
+
+    run_until(|| logs.failures() == 1).await;
+    assert!(token.headers().is_empty());
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    run_until(|| authorization(&token).as_deref() == Some("Bearer first")).await;
+}
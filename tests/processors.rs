@@ -0,0 +1,461 @@
+use axum_otel_tempo::{
+    config,
+    processors::{
+        self, AttributeDenylist, AttributeKeyPolicy, FanOut, KeyPolicyMode, OversizedSpanGuard,
+        OversizedSpanMode, RateLimitProcessor, RedactPattern, RedactionRules, Redactor,
+        TruncateAttributes, REDACTED,
+    },
+    status::{self, CountingExporter, Destination},
+};
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::{self, SpanProcessor, TracerProvider},
+    },
+    trace::{
+        Link, Span, SpanContext, SpanId, TraceFlags, TraceId, TraceResult, TraceState, Tracer,
+        TracerProvider as _,
+    },
+    Array, Context, Key, KeyValue, Value,
+};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn rate_limit_below_one_per_second_still_exports_a_span() {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(RateLimitProcessor::new(Box::new(collected.clone()), 0.5))
+        .build();
+    let tracer = provider.tracer("test");
+
+    for _ in 0..5 {
+        tracer.start("work").end();
+    }
+
+    assert_eq!(collected.0.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn fan_out_behind_the_rate_limit_sends_each_span_to_every_destination_once() {
+    let (primary, secondary) = (Collected::default(), Collected::default());
+    let fan_out = FanOut(vec![Box::new(primary.clone()), Box::new(secondary.clone())]);
+    let provider = TracerProvider::builder()
+        .with_span_processor(RateLimitProcessor::new(Box::new(fan_out), 2.0))
+        .build();
+    let tracer = provider.tracer("test");
+
+    for _ in 0..5 {
+        tracer.start("work").end();
+    }
+
+    assert_eq!(primary.0.lock().unwrap().len(), 2);
+    assert_eq!(secondary.0.lock().unwrap().len(), 2);
+}
+
+/// Accepts every batch.
+#[derive(Debug)]
+struct Accepting;
+
+impl SpanExporter for Accepting {
+    fn export(&mut self, _batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+#[tokio::test]
+async fn secondary_destinations_are_counted_apart_from_the_primary() {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(collected.clone())
+        .build();
+    provider.tracer("test").start("work").end();
+    let batch = collected.0.lock().unwrap().clone();
+
+    let exported = status::telemetry_status().spans_exported;
+    let mut exporter = CountingExporter::new(Accepting, Destination::secondary("http://collector"));
+    exporter.export(batch.clone()).await.unwrap();
+    exporter.export(batch).await.unwrap();
+
+    let status = status::telemetry_status();
+    assert_eq!(status.spans_exported, exported);
+    let secondary = status
+        .secondary_destinations
+        .iter()
+        .find(|destination| destination.name == "http://collector")
+        .unwrap();
+    assert_eq!(secondary.spans_exported, 2);
+    assert_eq!(secondary.batches_exported, 2);
+}
+
+#[test]
+fn truncation_keeps_the_counts_of_what_the_limits_dropped() {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_config(trace::config().with_span_limits(config::span_limits_for_tempo()))
+        .with_span_processor(TruncateAttributes::new(Box::new(collected.clone()), 8))
+        .build();
+    let tracer = provider.tracer("test");
+
+    let mut span = tracer.start("work");
+    for i in 0..200 {
+        span.set_attribute(KeyValue::new(format!("key.{i}"), "a long attribute value"));
+    }
+    for i in 0..150 {
+        span.add_event(format!("event {i}"), vec![]);
+    }
+    span.end();
+
+    let spans = collected.0.lock().unwrap();
+    let span = &spans[0];
+    assert_eq!(span.attributes.len(), 128);
+    assert_eq!(span.attributes.dropped_count(), 72);
+    assert!(span
+        .attributes
+        .iter()
+        .all(|(key, value)| key.as_str().starts_with("key.") && value.as_str().len() <= 8));
+    assert_eq!(span.events.len(), 128);
+    assert_eq!(span.events.dropped_count(), 22);
+}
+
+#[test]
+fn truncation_covers_event_and_link_attributes() {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(TruncateAttributes::new(Box::new(collected.clone()), 4))
+        .build();
+    let tracer = provider.tracer("test");
+
+    let mut span = tracer
+        .span_builder("work")
+        .with_links(vec![Link::new(
+            SpanContext::new(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([1; 8]),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            vec![KeyValue::new("link.note", "truncated")],
+        )])
+        .start(&tracer);
+    span.add_event("note", vec![KeyValue::new("event.note", "truncated")]);
+    span.end();
+
+    let spans = collected.0.lock().unwrap();
+    let span = &spans[0];
+    let event = span.events.iter().next().unwrap();
+    assert_eq!(event.attributes, [KeyValue::new("event.note", "trun")]);
+    let link = span.links.iter().next().unwrap();
+    assert_eq!(link.attributes, [KeyValue::new("link.note", "trun")]);
+}
+
+/// Log output collected in memory.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Ends a span with `attributes` behind `processor`, returning what reached
+/// `collected`.
+fn end_span_through<P: SpanProcessor + 'static>(
+    processor: P,
+    collected: &Collected,
+    attributes: Vec<KeyValue>,
+) -> SpanData {
+    let provider = TracerProvider::builder()
+        .with_span_processor(processor)
+        .build();
+    let tracer = provider.tracer("test");
+    tracer
+        .span_builder("work")
+        .with_attributes(attributes)
+        .start(&tracer)
+        .end();
+    let span = collected.0.lock().unwrap().pop().unwrap();
+    span
+}
+
+/// The keys of `span`'s attributes, sorted.
+fn attribute_keys(span: &SpanData) -> Vec<String> {
+    let mut keys: Vec<String> = span
+        .attributes
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn key_policy_drops_nonconforming_keys_in_drop_mode() {
+    let collected = Collected::default();
+    let span = end_span_through(
+        AttributeKeyPolicy::new(Box::new(collected.clone()), KeyPolicyMode::Drop),
+        &collected,
+        vec![
+            KeyValue::new("User Name", "ada"),
+            KeyValue::new("http.response.status_code", 200),
+            KeyValue::new("app.retry_count_2", 1),
+        ],
+    );
+
+    assert_eq!(
+        attribute_keys(&span),
+        ["app.retry_count_2", "http.response.status_code"]
+    );
+}
+
+#[test]
+fn key_policy_keeps_but_logs_nonconforming_keys_in_log_mode() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let collected = Collected::default();
+    let span = end_span_through(
+        AttributeKeyPolicy::new(Box::new(collected.clone()), KeyPolicyMode::Log),
+        &collected,
+        vec![
+            KeyValue::new("User Name", "ada"),
+            KeyValue::new("user.name", "ada"),
+        ],
+    );
+
+    assert_eq!(attribute_keys(&span), ["User Name", "user.name"]);
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        logs.contains("Span attribute key does not follow the naming policy")
+            && logs.contains("key=User Name")
+            && logs.contains("dropped=false"),
+        "no warning in {logs:?}"
+    );
+    assert!(!logs.contains("key=user.name"), "warned in {logs:?}");
+}
+
+#[test]
+fn denylist_wildcards_strip_span_and_event_attributes() {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(AttributeDenylist::new(
+            Box::new(collected.clone()),
+            vec![String::from("*.email"), String::from("*.ssn")],
+        ))
+        .build();
+    let tracer = provider.tracer("test");
+
+    let mut span = tracer
+        .span_builder("signup")
+        .with_attributes(vec![
+            KeyValue::new("user.email", "ada@example.com"),
+            KeyValue::new("customer.contact.email", "ada@example.com"),
+            KeyValue::new("user.ssn", "078-05-1120"),
+            KeyValue::new("user.id", 42),
+            KeyValue::new("email", "ada@example.com"),
+        ])
+        .start(&tracer);
+    span.add_event(
+        "verified",
+        vec![
+            KeyValue::new("user.email", "ada@example.com"),
+            KeyValue::new("verification.method", "link"),
+        ],
+    );
+    span.end();
+
+    let spans = collected.0.lock().unwrap();
+    let span = &spans[0];
+    assert_eq!(attribute_keys(span), ["email", "user.id"]);
+    let event = span.events.iter().next().unwrap();
+    assert_eq!(
+        event.attributes,
+        [KeyValue::new("verification.method", "link")]
+    );
+    assert_eq!(event.dropped_attributes_count, 1);
+}
+
+#[test]
+fn redaction_scrubs_keys_and_patterns_in_span_and_event_attributes() {
+    let collected = Collected::default();
+    let rules = RedactionRules {
+        keys: vec![String::from("*.password")],
+        patterns: vec![
+            RedactPattern::Email,
+            RedactPattern::CardNumber,
+            r"token=\w+".parse().unwrap(),
+        ],
+    };
+    let provider = TracerProvider::builder()
+        .with_span_processor(Redactor::new(Box::new(collected.clone()), &rules))
+        .build();
+    let tracer = provider.tracer("test");
+
+    let mut span = tracer
+        .span_builder("checkout")
+        .with_attributes(vec![
+            KeyValue::new("user.password", "hunter2"),
+            KeyValue::new(
+                "user.note",
+                "mail ada@example.com, card 4111 1111 1111 1111",
+            ),
+            KeyValue::new("order.id", "1234567890123"),
+            KeyValue::new("http.target", "/pay?token=abc123&step=2"),
+            KeyValue::new("user.id", 42),
+        ])
+        .start(&tracer);
+    span.add_event(
+        "charged",
+        vec![
+            KeyValue::new("payment.card", "4111-1111-1111-1111"),
+            KeyValue::new(
+                "receipt.to",
+                Value::Array(Array::String(vec!["ada@example.com".into()])),
+            ),
+        ],
+    );
+    span.end();
+
+    let spans = collected.0.lock().unwrap();
+    let span = &spans[0];
+    let value = |key: &'static str| span.attributes.get(&Key::new(key)).unwrap().clone();
+    assert_eq!(value("user.password"), Value::from(REDACTED));
+    assert_eq!(
+        value("user.note"),
+        Value::from(format!("mail {REDACTED}, card {REDACTED}"))
+    );
+    // Not a card number, since it fails the Luhn check.
+    assert_eq!(value("order.id"), Value::from("1234567890123"));
+    assert_eq!(
+        value("http.target"),
+        Value::from(format!("/pay?{REDACTED}&step=2"))
+    );
+    assert_eq!(value("user.id"), Value::from(42));
+    let event = span.events.iter().next().unwrap();
+    assert_eq!(
+        event.attributes,
+        [
+            KeyValue::new("payment.card", REDACTED),
+            KeyValue::new(
+                "receipt.to",
+                Value::Array(Array::String(vec![REDACTED.into()]))
+            ),
+        ]
+    );
+}
+
+/// The oversized span counter is process wide, so tests reading it take turns.
+static OVERSIZED: Mutex<()> = Mutex::new(());
+
+/// An [`OversizedSpanGuard`] allowing 1000 bytes per span and truncating
+/// values to 100 bytes.
+fn oversized_span_guard(collected: &Collected, mode: OversizedSpanMode) -> OversizedSpanGuard {
+    OversizedSpanGuard::new(Box::new(collected.clone()), 1000, 100, mode)
+}
+
+#[test]
+fn oversized_spans_are_truncated_to_fit() {
+    let _guard = OVERSIZED.lock().unwrap_or_else(|e| e.into_inner());
+    let oversized = processors::oversized_spans();
+    let collected = Collected::default();
+    let span = end_span_through(
+        oversized_span_guard(&collected, OversizedSpanMode::Truncate),
+        &collected,
+        vec![
+            KeyValue::new("request.body", "x".repeat(5000)),
+            KeyValue::new("user.id", 42),
+        ],
+    );
+
+    let body = span.attributes.get(&Key::new("request.body")).unwrap();
+    assert_eq!(body.as_str().len(), 100);
+    assert_eq!(
+        span.attributes.get(&Key::new("user.id")),
+        Some(&Value::I64(42))
+    );
+    assert_eq!(processors::oversized_spans(), oversized);
+}
+
+#[test]
+fn oversized_spans_still_too_large_once_truncated_are_dropped() {
+    let _guard = OVERSIZED.lock().unwrap_or_else(|e| e.into_inner());
+    let oversized = processors::oversized_spans();
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(oversized_span_guard(
+            &collected,
+            OversizedSpanMode::Truncate,
+        ))
+        .build();
+    let tracer = provider.tracer("test");
+
+    let attributes: Vec<_> = (0..20)
+        .map(|i| KeyValue::new(format!("chunk.{i}"), "x".repeat(100)))
+        .collect();
+    tracer
+        .span_builder("work")
+        .with_attributes(attributes)
+        .start(&tracer)
+        .end();
+
+    assert!(collected.0.lock().unwrap().is_empty());
+    assert_eq!(processors::oversized_spans(), oversized + 1);
+}
+
+#[test]
+fn oversized_spans_are_dropped_in_drop_mode() {
+    let _guard = OVERSIZED.lock().unwrap_or_else(|e| e.into_inner());
+    let oversized = processors::oversized_spans();
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(oversized_span_guard(&collected, OversizedSpanMode::Drop))
+        .build();
+    let tracer = provider.tracer("test");
+
+    for (name, body) in [("small", 10), ("large", 5000)] {
+        tracer
+            .span_builder(name)
+            .with_attributes(vec![KeyValue::new("request.body", "x".repeat(body))])
+            .start(&tracer)
+            .end();
+    }
+
+    let spans = collected.0.lock().unwrap();
+    let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+    assert_eq!(names, ["small"]);
+    assert_eq!(processors::oversized_spans(), oversized + 1);
+}
@@ -0,0 +1,161 @@
+use axum_otel_tempo::propagation::{B3Propagator, JaegerPropagator, Propagator, Propagators};
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use std::collections::HashMap;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const SPAN_ID: &str = "00f067aa0ba902b7";
+
+/// A context whose current span is the remote span above.
+fn context(sampled: bool) -> Context {
+    let flags = if sampled {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    Context::new().with_remote_span_context(SpanContext::new(
+        TraceId::from_hex(TRACE_ID).unwrap(),
+        SpanId::from_hex(SPAN_ID).unwrap(),
+        flags,
+        true,
+        TraceState::default(),
+    ))
+}
+
+/// The headers `propagator` writes for `cx`.
+fn inject(propagator: &dyn TextMapPropagator, cx: &Context) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    propagator.inject_context(cx, &mut headers);
+    headers
+}
+
+/// The span context `propagator` reads from `headers`.
+fn extract(propagator: &dyn TextMapPropagator, headers: &[(&str, &str)]) -> SpanContext {
+    let headers: HashMap<String, String> = headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    propagator.extract(&headers).span().span_context().clone()
+}
+
+/// Writes `cx` with `propagator` and reads it back.
+fn round_trip(propagator: &dyn TextMapPropagator, cx: &Context) -> SpanContext {
+    let headers = inject(propagator, cx);
+    let headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    extract(propagator, &headers)
+}
+
+#[test]
+fn otel_propagators_lists_are_parsed_in_order() {
+    assert_eq!(
+        "tracecontext, b3multi,jaeger,b3multi".parse(),
+        Ok(Propagators(vec![
+            Propagator::TraceContext,
+            Propagator::B3Multi,
+            Propagator::Jaeger,
+        ]))
+    );
+    assert_eq!("none".parse(), Ok(Propagators(Vec::new())));
+    assert!("tracecontext,xray".parse::<Propagators>().is_err());
+    assert_eq!(
+        Propagators::default().0,
+        [Propagator::TraceContext, Propagator::Baggage]
+    );
+}
+
+#[test]
+fn b3_single_header_round_trips() {
+    let b3 = B3Propagator::single_header();
+    for sampled in [true, false] {
+        let cx = context(sampled);
+        let headers = inject(&b3, &cx);
+        assert_eq!(
+            headers["b3"],
+            format!("{TRACE_ID}-{SPAN_ID}-{}", u8::from(sampled))
+        );
+        assert_eq!(round_trip(&b3, &cx), *cx.span().span_context());
+    }
+}
+
+#[test]
+fn b3_multiple_headers_round_trip() {
+    let b3 = B3Propagator::multiple_headers();
+    let cx = context(true);
+    let headers = inject(&b3, &cx);
+    assert_eq!(headers["x-b3-traceid"], TRACE_ID);
+    assert_eq!(headers["x-b3-spanid"], SPAN_ID);
+    assert_eq!(headers["x-b3-sampled"], "1");
+    assert_eq!(round_trip(&b3, &cx), *cx.span().span_context());
+
+    // 64 bit trace ids, and the debug flag overriding a sampled 0.
+    let span_context = extract(
+        &b3,
+        &[
+            ("x-b3-traceid", "a3ce929d0e0e4736"),
+            ("x-b3-spanid", SPAN_ID),
+            ("x-b3-sampled", "0"),
+            ("x-b3-flags", "1"),
+        ],
+    );
+    assert_eq!(
+        span_context.trace_id(),
+        TraceId::from_hex("a3ce929d0e0e4736").unwrap()
+    );
+    assert!(span_context.is_sampled());
+}
+
+#[test]
+fn jaeger_round_trips() {
+    let jaeger = JaegerPropagator::new();
+    let cx = context(true);
+    let headers = inject(&jaeger, &cx);
+    assert_eq!(
+        headers["uber-trace-id"],
+        format!("{TRACE_ID}:{SPAN_ID}:0:1")
+    );
+    assert_eq!(round_trip(&jaeger, &cx), *cx.span().span_context());
+
+    // URL encoded, with the debug flag alone.
+    let span_context = extract(
+        &jaeger,
+        &[("uber-trace-id", &format!("{TRACE_ID}%3A{SPAN_ID}%3A0%3A2"))],
+    );
+    assert!(span_context.is_valid());
+    assert!(span_context.is_sampled());
+    assert!(!extract(&jaeger, &[("uber-trace-id", "0:0:0:1")]).is_valid());
+}
+
+#[test]
+fn composite_propagators_write_every_format_and_read_any() {
+    let propagator = "tracecontext,b3multi,jaeger"
+        .parse::<Propagators>()
+        .unwrap()
+        .build();
+    let cx = context(true);
+    let mut headers: Vec<_> = inject(&propagator, &cx).into_keys().collect();
+    headers.sort();
+    assert_eq!(
+        headers,
+        [
+            "traceparent",
+            "tracestate",
+            "uber-trace-id",
+            "x-b3-sampled",
+            "x-b3-spanid",
+            "x-b3-traceid",
+        ]
+    );
+    assert_eq!(round_trip(&propagator, &cx), *cx.span().span_context());
+
+    let only_jaeger = extract(
+        &propagator,
+        &[("uber-trace-id", &format!("{TRACE_ID}:{SPAN_ID}:0:1"))],
+    );
+    assert_eq!(only_jaeger, *cx.span().span_context());
+}
@@ -0,0 +1,533 @@
+use axum_otel_tempo::resource::{
+    self, CloudAttributes, Detector, ResourcePrecedence, ServiceAttributes,
+};
+use opentelemetry::{sdk::Resource, Key, KeyValue, Value};
+
+fn get<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a Value> {
+    attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| &kv.value)
+}
+
+fn string(value: &str) -> Option<Value> {
+    Some(Value::from(value.to_owned()))
+}
+
+const ECS_TASK: &str = r#"{
+    "Cluster": "arn:aws:ecs:eu-west-1:123456789012:cluster/shop",
+    "TaskARN": "arn:aws:ecs:eu-west-1:123456789012:task/shop/0f6d7b2d",
+    "Family": "checkout",
+    "LaunchType": "FARGATE",
+    "AvailabilityZone": "eu-west-1b"
+}"#;
+
+const ECS_CONTAINER: &str = r#"{
+    "DockerId": "cd189a933e5849daa93386466019ab50-2495160603",
+    "Name": "checkout",
+    "ContainerARN": "arn:aws:ecs:eu-west-1:123456789012:container/shop/0f6d7b2d/1a2b"
+}"#;
+
+#[test]
+fn ecs_task_and_container_metadata() {
+    let attributes =
+        resource::ecs_attributes(ECS_TASK.as_bytes(), Some(ECS_CONTAINER.as_bytes())).unwrap();
+
+    for (key, value) in [
+        ("cloud.provider", "aws"),
+        ("cloud.platform", "aws_ecs"),
+        ("cloud.region", "eu-west-1"),
+        ("cloud.account.id", "123456789012"),
+        ("cloud.availability_zone", "eu-west-1b"),
+        (
+            "aws.ecs.task.arn",
+            "arn:aws:ecs:eu-west-1:123456789012:task/shop/0f6d7b2d",
+        ),
+        (
+            "aws.ecs.cluster.arn",
+            "arn:aws:ecs:eu-west-1:123456789012:cluster/shop",
+        ),
+        ("aws.ecs.launchtype", "fargate"),
+        (
+            "container.id",
+            "cd189a933e5849daa93386466019ab50-2495160603",
+        ),
+        ("container.name", "checkout"),
+    ] {
+        assert_eq!(get(&attributes, key).cloned(), string(value), "{key}");
+    }
+}
+
+#[test]
+fn ecs_task_without_container_metadata() {
+    let attributes = resource::ecs_attributes(ECS_TASK.as_bytes(), None).unwrap();
+
+    assert_eq!(
+        get(&attributes, "aws.ecs.launchtype").cloned(),
+        string("fargate")
+    );
+    assert_eq!(get(&attributes, "container.id"), None);
+}
+
+#[test]
+fn ecs_rejects_a_task_without_arn() {
+    assert!(resource::ecs_attributes(br#"{"Cluster": "shop"}"#, None).is_none());
+    assert!(resource::ecs_attributes(b"not json", None).is_none());
+}
+
+#[test]
+fn ec2_instance_identity_document() {
+    let identity = br#"{
+        "accountId": "123456789012",
+        "architecture": "x86_64",
+        "availabilityZone": "us-east-1c",
+        "imageId": "ami-0abcdef1234567890",
+        "instanceId": "i-1234567890abcdef0",
+        "instanceType": "t3.micro",
+        "region": "us-east-1"
+    }"#;
+    let attributes = resource::ec2_attributes(identity, Some("ip-10-0-0-5.ec2.internal")).unwrap();
+
+    for (key, value) in [
+        ("cloud.provider", "aws"),
+        ("cloud.platform", "aws_ec2"),
+        ("cloud.region", "us-east-1"),
+        ("cloud.account.id", "123456789012"),
+        ("cloud.availability_zone", "us-east-1c"),
+        ("host.id", "i-1234567890abcdef0"),
+        ("host.type", "t3.micro"),
+        ("host.image.id", "ami-0abcdef1234567890"),
+        ("host.name", "ip-10-0-0-5.ec2.internal"),
+    ] {
+        assert_eq!(get(&attributes, key).cloned(), string(value), "{key}");
+    }
+}
+
+#[test]
+fn ec2_rejects_a_document_without_instance_id() {
+    assert!(resource::ec2_attributes(br#"{"region": "us-east-1"}"#, None).is_none());
+}
+
+const CONTAINER_ID: &str = "cd189a933e5849daa93386466019ab50cd189a933e5849daa93386466019ab50";
+
+#[test]
+fn container_id_from_cgroup_v1() {
+    let docker = format!(
+        "12:memory:/docker/{CONTAINER_ID}\n11:cpu,cpuacct:/docker/{CONTAINER_ID}\n1:name=systemd:/docker/{CONTAINER_ID}\n"
+    );
+    assert_eq!(
+        resource::container_id_from_cgroup(&docker).as_deref(),
+        Some(CONTAINER_ID)
+    );
+
+    let kubernetes = format!(
+        "4:pids:/kubepods/burstable/pod2c48913c-b29f-11e7-9350-020968147796/{CONTAINER_ID}\n"
+    );
+    assert_eq!(
+        resource::container_id_from_cgroup(&kubernetes).as_deref(),
+        Some(CONTAINER_ID)
+    );
+}
+
+#[test]
+fn container_id_from_cgroup_v2() {
+    let systemd = format!("0::/system.slice/docker-{CONTAINER_ID}.scope\n");
+    assert_eq!(
+        resource::container_id_from_cgroup(&systemd).as_deref(),
+        Some(CONTAINER_ID)
+    );
+
+    let containerd = format!(
+        "0::/kubepods.slice/kubepods-besteffort.slice/cri-containerd-{CONTAINER_ID}.scope\n"
+    );
+    assert_eq!(
+        resource::container_id_from_cgroup(&containerd).as_deref(),
+        Some(CONTAINER_ID)
+    );
+}
+
+#[test]
+fn no_container_id_outside_containers() {
+    assert_eq!(resource::container_id_from_cgroup("0::/\n"), None);
+    assert_eq!(
+        resource::container_id_from_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n"),
+        None
+    );
+}
+
+#[test]
+fn container_id_from_mountinfo() {
+    let mounts = format!(
+        "736 724 0:47 / / rw,relatime - overlay overlay rw\n\
+         751 736 254:1 /var/lib/docker/containers/{CONTAINER_ID}/hostname /etc/hostname rw,relatime - ext4 /dev/vda1 rw\n"
+    );
+    assert_eq!(
+        resource::container_id_from_mountinfo(&mounts).as_deref(),
+        Some(CONTAINER_ID)
+    );
+    assert_eq!(
+        resource::container_id_from_mountinfo("736 724 0:47 / / rw - overlay overlay rw\n"),
+        None
+    );
+}
+
+fn attribute(resource: &Resource, key: &str) -> Option<String> {
+    resource
+        .get(Key::from(key.to_owned()))
+        .map(|v| v.to_string())
+}
+
+#[test]
+fn parses_resource_attributes() {
+    let attributes =
+        resource::parse_resource_attributes("team=payments, deployment.region = eu%2Cwest ,")
+            .unwrap();
+    assert_eq!(
+        attributes,
+        vec![
+            KeyValue::new("team", "payments"),
+            KeyValue::new("deployment.region", "eu,west"),
+        ]
+    );
+
+    assert!(resource::parse_resource_attributes("team").is_err());
+    assert!(resource::parse_resource_attributes("team=%zz").is_err());
+}
+
+#[test]
+fn resource_attributes_fill_in_below_explicit_settings() {
+    let from_env = resource::parse_resource_attributes(
+        "service.name=from-env,environment=staging,team=payments",
+    )
+    .unwrap();
+    let resource = resource::base_resource(
+        &ServiceAttributes {
+            name: Some("checkout"),
+            version: Some("1.2.3"),
+            ..Default::default()
+        },
+        &CloudAttributes::default(),
+        &from_env,
+        &[],
+        ResourcePrecedence::Explicit,
+    );
+
+    assert_eq!(
+        attribute(&resource, "service.name").as_deref(),
+        Some("checkout")
+    );
+    // Not configured, so the variable wins over the placeholder.
+    assert_eq!(
+        attribute(&resource, "environment").as_deref(),
+        Some("staging")
+    );
+    assert_eq!(attribute(&resource, "team").as_deref(), Some("payments"));
+}
+
+#[test]
+fn resource_attributes_sit_between_explicit_and_detected() {
+    let from_env = resource::parse_resource_attributes("os.type=from-env").unwrap();
+    let base = |precedence| {
+        resource::base_resource(
+            &ServiceAttributes {
+                version: Some("1.2.3"),
+                ..Default::default()
+            },
+            &CloudAttributes::default(),
+            &from_env,
+            &[Detector::Os],
+            precedence,
+        )
+    };
+
+    assert_eq!(
+        attribute(&base(ResourcePrecedence::Explicit), "os.type").as_deref(),
+        Some("from-env")
+    );
+    assert_eq!(
+        attribute(&base(ResourcePrecedence::Detected), "os.type").as_deref(),
+        Some(std::env::consts::OS)
+    );
+}
+
+#[test]
+fn conflicting_keys_resolve_by_layer_precedence() {
+    // `cloud.region` is the one key every layer can set: configured, from
+    // `OTEL_RESOURCE_ATTRIBUTES`, and detected from `AWS_REGION`.
+    std::env::set_var("AWS_REGION", "detected");
+    let region = |configured: Option<&str>, from_env: Option<&str>, precedence| {
+        let from_env: Vec<_> = from_env
+            .map(|region| KeyValue::new("cloud.region", region.to_owned()))
+            .into_iter()
+            .collect();
+        let resource = resource::base_resource(
+            &ServiceAttributes::default(),
+            &CloudAttributes {
+                region: configured.map(str::to_owned),
+                ..Default::default()
+            },
+            &from_env,
+            &[],
+            precedence,
+        );
+        attribute(&resource, "cloud.region")
+    };
+
+    // Configured > OTEL_RESOURCE_ATTRIBUTES > detected, by default.
+    let explicit = ResourcePrecedence::Explicit;
+    assert_eq!(
+        region(Some("configured"), Some("from-env"), explicit).as_deref(),
+        Some("configured")
+    );
+    assert_eq!(
+        region(None, Some("from-env"), explicit).as_deref(),
+        Some("from-env")
+    );
+    assert_eq!(region(None, None, explicit).as_deref(), Some("detected"));
+
+    // Detected > OTEL_RESOURCE_ATTRIBUTES > configured, when flipped.
+    let detected = ResourcePrecedence::Detected;
+    assert_eq!(
+        region(Some("configured"), Some("from-env"), detected).as_deref(),
+        Some("detected")
+    );
+    std::env::remove_var("AWS_REGION");
+    assert_eq!(
+        region(Some("configured"), Some("from-env"), detected).as_deref(),
+        Some("from-env")
+    );
+    assert_eq!(
+        region(Some("configured"), None, detected).as_deref(),
+        Some("configured")
+    );
+}
+
+#[test]
+fn build_service_version_fills_in_below_every_layer() {
+    let resource = |version, from_env: &[KeyValue]| {
+        resource::base_resource(
+            &ServiceAttributes {
+                version,
+                ..Default::default()
+            },
+            &CloudAttributes::default(),
+            from_env,
+            &[],
+            ResourcePrecedence::Explicit,
+        )
+    };
+    let from_env = [KeyValue::new("service.version", "from-env")];
+
+    assert_eq!(
+        attribute(&resource(None, &[]), "service.version").as_deref(),
+        Some(resource::BUILD_SERVICE_VERSION)
+    );
+    assert_eq!(
+        attribute(&resource(None, &from_env), "service.version").as_deref(),
+        Some("from-env")
+    );
+    assert_eq!(
+        attribute(&resource(Some("1.2.3"), &from_env), "service.version").as_deref(),
+        Some("1.2.3")
+    );
+}
+
+#[test]
+fn build_git_sha_is_overridable() {
+    let resource = |from_env: &[KeyValue]| {
+        resource::base_resource(
+            &ServiceAttributes {
+                version: Some("1.2.3"),
+                ..Default::default()
+            },
+            &CloudAttributes::default(),
+            from_env,
+            &[],
+            ResourcePrecedence::Explicit,
+        )
+    };
+
+    assert_eq!(
+        attribute(&resource(&[]), "service.git.sha").as_deref(),
+        resource::BUILD_GIT_SHA
+    );
+    assert_eq!(
+        attribute(
+            &resource(&[KeyValue::new("service.git.sha", "abc123")]),
+            "service.git.sha"
+        )
+        .as_deref(),
+        Some("abc123")
+    );
+}
+
+#[test]
+fn service_namespace_and_instance_id() {
+    let resource = |instance_id| {
+        resource::base_resource(
+            &ServiceAttributes {
+                name: Some("checkout"),
+                namespace: Some("shop"),
+                version: Some("1.2.3"),
+                instance_id,
+                environment: Some("production"),
+            },
+            &CloudAttributes::default(),
+            &[],
+            &[],
+            ResourcePrecedence::Explicit,
+        )
+    };
+
+    let generated = resource(None);
+    assert_eq!(
+        attribute(&generated, "service.namespace").as_deref(),
+        Some("shop")
+    );
+    assert_eq!(
+        attribute(&generated, "environment").as_deref(),
+        Some("production")
+    );
+    let id = attribute(&generated, "service.instance.id").unwrap();
+    assert_eq!(id, resource::process_instance_id());
+    let groups: Vec<usize> = id.split('-').map(str::len).collect();
+    assert_eq!(groups, [8, 4, 4, 4, 12]);
+    assert_eq!(&id[14..15], "4");
+
+    assert_eq!(
+        attribute(&resource(Some("checkout-1")), "service.instance.id").as_deref(),
+        Some("checkout-1")
+    );
+}
+
+/// The resource with only the attributes of `detectors` on top of the
+/// placeholders and detected cloud attributes.
+fn detected(detectors: &[Detector]) -> Resource {
+    resource::base_resource(
+        &ServiceAttributes::default(),
+        &CloudAttributes::default(),
+        &[],
+        detectors,
+        ResourcePrecedence::Explicit,
+    )
+}
+
+#[test]
+fn detectors_are_parsed_from_a_list() {
+    assert_eq!(
+        resource::parse_detectors("host, os,process,none"),
+        Ok(vec![Detector::Host, Detector::Os, Detector::Process])
+    );
+    assert_eq!(resource::parse_detectors("none"), Ok(Vec::new()));
+    assert!(resource::parse_detectors("host,gcp").is_err());
+}
+
+#[test]
+fn host_os_and_process_detectors() {
+    let resource = detected(&[Detector::Host, Detector::Os, Detector::Process]);
+
+    let arch = attribute(&resource, "host.arch").unwrap();
+    assert!(!["x86_64", "aarch64"].contains(&arch.as_str()), "{arch}");
+    assert!(attribute(&resource, "host.name").is_some_and(|name| !name.is_empty()));
+    assert_eq!(
+        attribute(&resource, "os.type").as_deref(),
+        Some(std::env::consts::OS)
+    );
+    assert_eq!(
+        resource.get(Key::from_static_str("process.pid")),
+        Some(Value::I64(i64::from(std::process::id())))
+    );
+    assert_eq!(
+        attribute(&resource, "process.runtime.name").as_deref(),
+        Some("rustc")
+    );
+    let exe = std::env::current_exe().unwrap();
+    assert_eq!(
+        attribute(&resource, "process.executable.name"),
+        Some(exe.file_name().unwrap().to_string_lossy().into_owned())
+    );
+
+    let none = detected(&[]);
+    for key in ["host.arch", "os.type", "process.pid"] {
+        assert_eq!(attribute(&none, key), None, "{key}");
+    }
+}
+
+/// Runs the Kubernetes detector with `vars` set, returning its attributes.
+fn k8s_with(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    for (var, value) in vars {
+        std::env::set_var(var, value);
+    }
+    let resource = detected(&[Detector::K8s]);
+    for (var, _) in vars {
+        std::env::remove_var(var);
+    }
+    let mut attributes: Vec<_> = resource
+        .iter()
+        .filter(|(key, _)| key.as_str().starts_with("k8s."))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    attributes.sort();
+    attributes
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+// One test, as the variables are process wide.
+#[test]
+fn k8s_detector_maps_the_downward_api_variables() {
+    // Outside a cluster, even with the variables set.
+    assert!(k8s_with(&[("K8S_POD_NAME", "checkout-7d4b9c8f6d-x2x4z")]).is_empty());
+
+    let cluster = ("KUBERNETES_SERVICE_HOST", "10.0.0.1");
+    assert_eq!(
+        k8s_with(&[
+            cluster,
+            ("K8S_POD_NAME", "checkout-7d4b9c8f6d-x2x4z"),
+            ("K8S_NAMESPACE_NAME", "shop"),
+            ("K8S_NODE_NAME", "node-1"),
+        ]),
+        pairs(&[
+            ("k8s.deployment.name", "checkout"),
+            ("k8s.namespace.name", "shop"),
+            ("k8s.node.name", "node-1"),
+            ("k8s.pod.name", "checkout-7d4b9c8f6d-x2x4z"),
+        ])
+    );
+
+    // The common unprefixed names, and an explicit deployment.
+    assert_eq!(
+        k8s_with(&[
+            cluster,
+            ("POD_NAME", "checkout-7d4b9c8f6d-x2x4z"),
+            ("POD_NAMESPACE", "shop"),
+            ("NODE_NAME", "node-1"),
+            ("K8S_DEPLOYMENT_NAME", "checkout-canary"),
+        ]),
+        pairs(&[
+            ("k8s.deployment.name", "checkout-canary"),
+            ("k8s.namespace.name", "shop"),
+            ("k8s.node.name", "node-1"),
+            ("k8s.pod.name", "checkout-7d4b9c8f6d-x2x4z"),
+        ])
+    );
+
+    // Stateful set pods belong to no deployment.
+    assert_eq!(
+        k8s_with(&[
+            cluster,
+            ("K8S_POD_NAME", "postgres-0"),
+            ("POD_NAME", "ignored"),
+            ("K8S_NAMESPACE_NAME", "shop"),
+        ]),
+        pairs(&[
+            ("k8s.namespace.name", "shop"),
+            ("k8s.pod.name", "postgres-0"),
+        ])
+    );
+}
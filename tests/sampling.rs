@@ -0,0 +1,195 @@
+use axum_otel_tempo::{
+    clock::{self, ManualClock},
+    sampling::{self, RateLimitingSampler, RouteSampling, ScheduledSampler, TokenBucket},
+};
+use opentelemetry::{
+    sdk::trace::{Sampler, ShouldSample},
+    trace::{SamplingDecision, SpanKind, TraceId},
+    OrderMap,
+};
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The samplers read the process wide clock, which the schedule tests move.
+static CLOCK: Mutex<()> = Mutex::new(());
+
+fn start() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+#[test]
+fn bucket_allows_a_burst_of_one_seconds_worth() {
+    let mut bucket = TokenBucket::new(5.0, start());
+    let taken = (0..10).filter(|_| bucket.try_take(start())).count();
+    assert_eq!(taken, 5);
+}
+
+#[test]
+fn bucket_refills_at_the_rate() {
+    let mut bucket = TokenBucket::new(4.0, start());
+    while bucket.try_take(start()) {}
+
+    let later = start() + Duration::from_millis(500);
+    let taken = (0..10).filter(|_| bucket.try_take(later)).count();
+    assert_eq!(taken, 2);
+}
+
+#[test]
+fn bucket_never_holds_more_than_the_rate() {
+    let mut bucket = TokenBucket::new(3.0, start());
+    let much_later = start() + Duration::from_secs(60);
+    let taken = (0..10).filter(|_| bucket.try_take(much_later)).count();
+    assert_eq!(taken, 3);
+}
+
+#[test]
+fn bucket_ignores_time_going_backwards() {
+    let mut bucket = TokenBucket::new(2.0, start());
+    while bucket.try_take(start()) {}
+
+    assert!(!bucket.try_take(start() - Duration::from_secs(5)));
+    assert!(!bucket.try_take(start()));
+    assert!(bucket.try_take(start() + Duration::from_millis(500)));
+}
+
+#[test]
+fn bucket_below_one_per_second_lets_one_through_per_period() {
+    let mut bucket = TokenBucket::new(0.5, start());
+    assert!(bucket.try_take(start()));
+    assert!(!bucket.try_take(start()));
+
+    assert!(!bucket.try_take(start() + Duration::from_secs(1)));
+    assert!(bucket.try_take(start() + Duration::from_secs(2)));
+
+    let much_later = start() + Duration::from_secs(60);
+    let taken = (0..10).filter(|_| bucket.try_take(much_later)).count();
+    assert_eq!(taken, 1);
+}
+
+#[test]
+fn rate_limiting_sampler_rejects_invalid_rates() {
+    for rate in ["0", "-1", "NaN", "inf"] {
+        let sampler = format!("parentbased_ratelimiting:{rate}");
+        assert!(
+            sampling::parse_sampler(&sampler).is_err(),
+            "{sampler} was accepted"
+        );
+    }
+    assert!(sampling::parse_sampler("parentbased_ratelimiting:0.5").is_ok());
+}
+
+#[test]
+fn sampler_drops_new_traces_over_the_rate() {
+    let _guard = CLOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let sampler = RateLimitingSampler::new(3.0);
+    let sampled = (0..10u128)
+        .filter(|&id| {
+            let result = sampler.should_sample(
+                None,
+                TraceId::from(id + 1),
+                "request",
+                &SpanKind::Server,
+                &OrderMap::default(),
+                &[],
+            );
+            result.decision == SamplingDecision::RecordAndSample
+        })
+        .count();
+    assert_eq!(sampled, 3);
+}
+
+#[test]
+fn parses_rate_limiting_sampler() {
+    let sampler = sampling::parse_sampler("parentbased_ratelimiting:100").unwrap();
+    assert!(matches!(sampler, Sampler::ParentBased(_)));
+
+    assert!(sampling::parse_sampler("parentbased_ratelimiting").is_err());
+    assert!(sampling::parse_sampler("parentbased_ratelimiting:fast").is_err());
+}
+
+#[test]
+fn route_sampling_prefers_exact_then_longest_prefix() {
+    let routes: RouteSampling = "/api/*=0.5, /api/users/*=1.0, /api/health=0, *=0.1"
+        .parse()
+        .unwrap();
+    assert_eq!(routes.ratio_for("/api/health"), Some(0.0));
+    assert_eq!(routes.ratio_for("/api/users/:id"), Some(1.0));
+    assert_eq!(routes.ratio_for("/api/orders"), Some(0.5));
+    assert_eq!(routes.ratio_for("/"), Some(0.1));
+}
+
+#[test]
+fn route_sampling_without_a_match() {
+    let routes: RouteSampling = "/healthz=0".parse().unwrap();
+    assert_eq!(routes.ratio_for("/healthz/deep"), None);
+    assert!("/healthz".parse::<RouteSampling>().is_err());
+    assert!("/healthz=never".parse::<RouteSampling>().is_err());
+}
+
+/// `hours:minutes` UTC on the day of [`start`].
+fn at(hours: u64, minutes: u64) -> SystemTime {
+    let midnight = 1_700_000_000 - 1_700_000_000 % 86_400;
+    UNIX_EPOCH + Duration::from_secs(midnight + hours * 3600 + minutes * 60)
+}
+
+/// Whether `sampler` samples a new trace at `time`.
+fn sampled_at(sampler: &ScheduledSampler, clock: &ManualClock, time: SystemTime) -> bool {
+    clock.set(time);
+    let result = sampler.should_sample(
+        None,
+        TraceId::from(1),
+        "request",
+        &SpanKind::Server,
+        &OrderMap::default(),
+        &[],
+    );
+    result.decision == SamplingDecision::RecordAndSample
+}
+
+#[test]
+fn schedule_samples_by_the_window_containing_the_time() {
+    let _guard = CLOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let clock = ManualClock::new(start());
+    clock::set_clock(clock.clone());
+    let sampler: ScheduledSampler = "08:00-18:00=1.0, *=0.0".parse().unwrap();
+
+    assert!(!sampled_at(&sampler, &clock, at(7, 59)));
+    assert!(sampled_at(&sampler, &clock, at(8, 0)));
+    assert!(sampled_at(&sampler, &clock, at(12, 30)));
+    assert!(!sampled_at(&sampler, &clock, at(18, 0)));
+}
+
+#[test]
+fn schedule_windows_can_run_past_midnight() {
+    let _guard = CLOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let clock = ManualClock::new(start());
+    clock::set_clock(clock.clone());
+    let sampler: ScheduledSampler = "22:00-02:00=0.0".parse().unwrap();
+
+    assert!(sampled_at(&sampler, &clock, at(21, 59)));
+    assert!(!sampled_at(&sampler, &clock, at(23, 30)));
+    assert!(!sampled_at(&sampler, &clock, at(0, 0)));
+    assert!(!sampled_at(&sampler, &clock, at(1, 59)));
+    // The rest of the day samples everything when `*` is left out.
+    assert!(sampled_at(&sampler, &clock, at(2, 0)));
+}
+
+#[test]
+fn schedule_rejects_malformed_windows() {
+    for schedule in [
+        "08:00-18:00",
+        "08:00=1.0",
+        "08:00-18:00=often",
+        "24:00-02:00=1.0",
+        "08:60-09:00=1.0",
+        "8-18=1.0",
+    ] {
+        assert!(
+            schedule.parse::<ScheduledSampler>().is_err(),
+            "{schedule} parsed"
+        );
+    }
+    assert!("*=0.25".parse::<ScheduledSampler>().is_ok());
+}
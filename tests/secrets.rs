@@ -0,0 +1,129 @@
+use axum_otel_tempo::{
+    config::SettingSource,
+    export::HeaderProvider,
+    load_settings,
+    secrets::{self, CredentialFile, FileCredentials},
+    TelemetryError,
+};
+use std::{env, fs, path::PathBuf, process::Command, sync::Arc, time::Duration};
+
+/// A path for the secret file `name`, unique to this test run.
+fn secret_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "axum_otel_tempo-secret-{}-{name}",
+        std::process::id()
+    ))
+}
+
+/// The `Authorization` header `credentials` add to exports.
+fn authorization(credentials: &FileCredentials) -> String {
+    let headers = credentials.headers();
+    headers[0].1.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn secret_files_lose_their_trailing_newline_only() {
+    let path = secret_file("newline");
+    fs::write(&path, " s3cret \r\n").unwrap();
+    let secret = secrets::read_secret_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(secret.unwrap(), " s3cret ");
+    assert!(secrets::read_secret_file(&path)
+        .unwrap_err()
+        .contains(&path.display().to_string()));
+}
+
+#[test]
+fn credentials_are_re_read_on_reload() {
+    let path = secret_file("reload");
+    fs::write(&path, "first\n").unwrap();
+    let credentials = FileCredentials::load(CredentialFile::Basic {
+        username: String::from("1234"),
+        password_file: path.clone(),
+    })
+    .unwrap();
+    // base64("1234:first")
+    assert_eq!(authorization(&credentials), "Basic MTIzNDpmaXJzdA==");
+
+    fs::write(&path, "second\n").unwrap();
+    credentials.reload().unwrap();
+    assert_eq!(authorization(&credentials), "Basic MTIzNDpzZWNvbmQ=");
+
+    // A missing file keeps the credentials read last.
+    fs::remove_file(&path).unwrap();
+    assert!(credentials.reload().is_err());
+    assert_eq!(authorization(&credentials), "Basic MTIzNDpzZWNvbmQ=");
+}
+
+#[tokio::test]
+async fn credentials_are_re_read_on_sighup() {
+    let path = secret_file("sighup");
+    fs::write(&path, "first").unwrap();
+    let credentials =
+        Arc::new(FileCredentials::load(CredentialFile::Bearer(path.clone())).unwrap());
+    credentials.reload_on_sighup();
+    // Lets the task install its handler before the signal arrives.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    fs::write(&path, "second").unwrap();
+    let status = Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    for _ in 0..50 {
+        if authorization(&credentials) == "Bearer second" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(authorization(&credentials), "Bearer second");
+}
+
+// One test, as the variables are process wide.
+#[test]
+fn file_variables_stand_in_for_secret_settings() {
+    let path = secret_file("settings");
+    fs::write(&path, "token\n").unwrap();
+    env::set_var("OtelTempoEndpoint", "https://tempo.example.com/v1/traces");
+    env::set_var("OtelTempoBearerToken_FILE", &path);
+    let settings = load_settings().unwrap();
+
+    assert_eq!(settings.bearer_token.as_deref(), Some("token"));
+    assert!(matches!(
+        &settings.credential_file,
+        Some(CredentialFile::Bearer(file)) if *file == path
+    ));
+    let resolved = settings
+        .resolution
+        .iter()
+        .find(|setting| setting.name == "bearer_token")
+        .unwrap();
+    assert_eq!(
+        resolved.source,
+        SettingSource::SecretFile("OtelTempoBearerToken_FILE")
+    );
+    assert_eq!(resolved.value, "<redacted>");
+
+    // The variable itself wins over the file.
+    env::set_var("OtelTempoBearerToken", "from-env");
+    let settings = load_settings().unwrap();
+    assert_eq!(settings.bearer_token.as_deref(), Some("from-env"));
+    assert!(settings.credential_file.is_none());
+
+    // An unreadable file is an invalid setting rather than a missing one.
+    env::remove_var("OtelTempoBearerToken");
+    fs::remove_file(&path).unwrap();
+    match load_settings() {
+        Err(TelemetryError::InvalidSetting { var, .. }) => {
+            assert_eq!(var, "OtelTempoBearerToken_FILE")
+        }
+        Err(e) => panic!("failed with {e:?}"),
+        Ok(_) => panic!("loaded without the secret file"),
+    }
+    env::remove_var("OtelTempoBearerToken_FILE");
+    env::remove_var("OtelTempoEndpoint");
+}
@@ -0,0 +1,65 @@
+use axum::{body::Bytes, extract::State, routing::post, Router};
+use axum_otel_tempo::TelemetryBuilder;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use prost::Message;
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Serves an OTLP/HTTP traces endpoint counting the spans it receives.
+fn collector(received: Arc<AtomicUsize>) -> SocketAddr {
+    let app = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |State(received): State<Arc<AtomicUsize>>, body: Bytes| async move {
+                    let request = ExportTraceServiceRequest::decode(body).unwrap();
+                    let spans = request
+                        .resource_spans
+                        .iter()
+                        .flat_map(|resource| &resource.scope_spans)
+                        .map(|scope| scope.spans.len())
+                        .sum::<usize>();
+                    received.fetch_add(spans, Ordering::SeqCst);
+                },
+            ),
+        )
+        .with_state(received);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_exports_every_queued_span() {
+    let received = Arc::new(AtomicUsize::new(0));
+    let addr = collector(received.clone());
+
+    let mut builder = TelemetryBuilder::new()
+        .local_collector()
+        .endpoint(format!("http://{addr}/v1/traces"));
+    let settings = builder.settings_mut();
+    settings.export_filter = Some(String::from("info"));
+    // Nothing leaves the queue on schedule, only through the final flush.
+    settings.batch_scheduled_delay = Some(Duration::from_secs(3600));
+    let telemetry = builder.install().unwrap();
+
+    for i in 0..100 {
+        tracing::info_span!("work", i).in_scope(|| {});
+    }
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+
+    telemetry.shutdown().await;
+
+    assert_eq!(received.load(Ordering::SeqCst), 100);
+}
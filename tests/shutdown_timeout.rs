@@ -0,0 +1,71 @@
+use axum::{routing::post, Router};
+use axum_otel_tempo::TelemetryBuilder;
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Serves an OTLP/HTTP traces endpoint that never answers.
+fn unresponsive_collector() -> SocketAddr {
+    let app = Router::new().route("/v1/traces", post(std::future::pending::<()>));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+/// Log output collected in memory.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_gives_up_on_an_unresponsive_backend_with_a_warning() {
+    let addr = unresponsive_collector();
+
+    let mut builder = TelemetryBuilder::new()
+        .local_collector()
+        .endpoint(format!("http://{addr}/v1/traces"));
+    let settings = builder.settings_mut();
+    settings.export_filter = Some(String::from("info"));
+    settings.batch_scheduled_delay = Some(Duration::from_secs(3600));
+    settings.shutdown_timeout = Duration::from_millis(500);
+    let telemetry = builder.install().unwrap();
+
+    tracing::info_span!("work").in_scope(|| {});
+
+    // The test runs on this thread, so its log lines land here rather than in
+    // the subscriber the telemetry installed.
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _guard = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let started = Instant::now();
+    telemetry.shutdown().await;
+
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        logs.contains("WARN") && logs.contains("Telemetry shutdown timed out"),
+        "no timeout warning in {logs:?}"
+    );
+}
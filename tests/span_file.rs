@@ -0,0 +1,227 @@
+use axum_otel_tempo::span_file::SpanFileExporter;
+use opentelemetry::{
+    sdk::{
+        export::trace::{SpanData, SpanExporter},
+        trace::{self, SpanProcessor, TracerProvider},
+        Resource,
+    },
+    trace::{
+        Link, Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId,
+        TraceResult, TraceState, Tracer, TracerProvider as _,
+    },
+    Context, KeyValue,
+};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// A batch of one span per name, each with a short attribute.
+fn batch(names: &[&'static str]) -> Vec<SpanData> {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(collected.clone())
+        .build();
+    let tracer = provider.tracer("test");
+    for name in names {
+        tracer
+            .span_builder(*name)
+            .with_attributes(vec![KeyValue::new("n", 1)])
+            .start(&tracer)
+            .end();
+    }
+    let spans = collected.0.lock().unwrap().clone();
+    spans
+}
+
+/// An empty directory for the test `name`.
+fn span_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "axum_otel_tempo-span-file-{}-{name}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The `name` of every line in `path`.
+fn names(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let span: serde_json::Value = serde_json::from_str(line).unwrap();
+            span["name"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn spans_are_written_as_json_lines() {
+    let dir = span_dir("schema");
+    let path = dir.join("spans.jsonl");
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", "checkout")])),
+        )
+        .with_span_processor(collected.clone())
+        .build();
+    let tracer = provider.tracer("test");
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let linked = SpanContext::new(
+        TraceId::from(7),
+        SpanId::from(8),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    let parent = Context::new().with_remote_span_context(SpanContext::new(
+        TraceId::from(1),
+        SpanId::from(2),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    ));
+    let mut span = tracer
+        .span_builder("GET /users/:id")
+        .with_kind(SpanKind::Server)
+        .with_start_time(start)
+        .with_attributes(vec![
+            KeyValue::new("http.response.status_code", 500),
+            KeyValue::new("http.route", "/users/:id"),
+            KeyValue::new("retried", true),
+        ])
+        .with_links(vec![Link::new(linked, Vec::new())])
+        .start_with_context(&tracer, &parent);
+    span.add_event_with_timestamp(
+        "exception",
+        start + Duration::from_millis(1),
+        vec![KeyValue::new("exception.message", "boom")],
+    );
+    span.set_status(Status::error("boom"));
+    span.end_with_timestamp(start + Duration::from_micros(1500));
+    let batch = collected.0.lock().unwrap().clone();
+
+    let mut exporter = SpanFileExporter::open(path.clone(), u64::MAX, 1).unwrap();
+    exporter.export(batch).await.unwrap();
+
+    let contents = fs::read_to_string(&path).unwrap();
+    let line: serde_json::Value =
+        serde_json::from_str(contents.strip_suffix('\n').unwrap()).unwrap();
+    assert_eq!(
+        line,
+        json!({
+            "trace_id": "00000000000000000000000000000001",
+            "span_id": line["span_id"],
+            "parent_span_id": "0000000000000002",
+            "name": "GET /users/:id",
+            "kind": "server",
+            "start_unix_nano": 1_700_000_000_000_000_000u64,
+            "end_unix_nano": 1_700_000_000_001_500_000u64,
+            "duration_ms": 1.5,
+            "status": "error",
+            "status_message": "boom",
+            "attributes": {
+                "http.response.status_code": 500,
+                "http.route": "/users/:id",
+                "retried": true,
+            },
+            "events": [{
+                "name": "exception",
+                "time_unix_nano": 1_700_000_000_001_000_000u64,
+                "attributes": {"exception.message": "boom"},
+            }],
+            "links": [{
+                "trace_id": "00000000000000000000000000000007",
+                "span_id": "0000000000000008",
+            }],
+            "resource": {"service.name": "checkout"},
+        })
+    );
+    assert_eq!(line["span_id"].as_str().unwrap().len(), 16);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn files_are_rotated_past_the_size_threshold() {
+    let dir = span_dir("rotate");
+    let path = dir.join("spans.jsonl");
+    let mut exporter = SpanFileExporter::open(path.clone(), u64::MAX, 2).unwrap();
+    exporter.export(batch(&["span-0"])).await.unwrap();
+    let line_size = fs::metadata(&path).unwrap().len();
+    fs::remove_file(&path).unwrap();
+
+    // Room for two lines per file, whose durations print to different
+    // lengths, and two rotated files.
+    let max_bytes = line_size * 2 + line_size / 2;
+    let mut exporter = SpanFileExporter::open(path.clone(), max_bytes, 2).unwrap();
+    exporter
+        .export(batch(&["span-1", "span-2", "span-3", "span-4"]))
+        .await
+        .unwrap();
+    exporter.export(batch(&["span-5", "span-6"])).await.unwrap();
+
+    assert_eq!(names(&path), ["span-5", "span-6"]);
+    assert_eq!(names(&dir.join("spans.jsonl.1")), ["span-3", "span-4"]);
+    assert_eq!(names(&dir.join("spans.jsonl.2")), ["span-1", "span-2"]);
+
+    // The oldest file goes once there are more than two.
+    exporter.export(batch(&["span-7"])).await.unwrap();
+    assert_eq!(names(&path), ["span-7"]);
+    assert_eq!(names(&dir.join("spans.jsonl.1")), ["span-5", "span-6"]);
+    assert_eq!(names(&dir.join("spans.jsonl.2")), ["span-3", "span-4"]);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_single_line_over_the_threshold_is_still_written() {
+    let dir = span_dir("oversized");
+    let path = dir.join("spans.jsonl");
+    let mut exporter = SpanFileExporter::open(path.clone(), 1, 0).unwrap();
+    exporter.export(batch(&["span-0", "span-1"])).await.unwrap();
+
+    // Without rotated files to keep, the full file is removed.
+    assert_eq!(names(&path), ["span-1"]);
+    assert!(!dir.join("spans.jsonl.1").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn reopened_files_are_appended_to() {
+    let dir = span_dir("append");
+    let path = dir.join("spans.jsonl");
+    for name in ["first", "second"] {
+        let mut exporter = SpanFileExporter::open(path.clone(), u64::MAX, 1).unwrap();
+        exporter.export(batch(&[name])).await.unwrap();
+    }
+
+    assert_eq!(names(&path), ["first", "second"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
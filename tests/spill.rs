@@ -0,0 +1,201 @@
+use axum_otel_tempo::spill::{self, DiskSpill};
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::{self, SpanProcessor, TracerProvider},
+    },
+    trace::{TraceError, TraceResult, Tracer, TracerProvider as _},
+    Context,
+};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The eviction counter is process wide, so tests evicting files take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// A batch of one span called `name`.
+fn batch(name: &'static str) -> Vec<SpanData> {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(collected.clone())
+        .build();
+    provider.tracer("test").in_span(name, |_| {});
+    let spans = collected.0.lock().unwrap().clone();
+    spans
+}
+
+/// Fails every export while `down` is set, and records the span names of
+/// those it accepts.
+#[derive(Clone, Debug, Default)]
+struct Backend {
+    down: Arc<AtomicBool>,
+    exported: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl Backend {
+    fn exported(&self) -> Vec<Vec<String>> {
+        std::mem::take(&mut *self.exported.lock().unwrap())
+    }
+}
+
+impl SpanExporter for Backend {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if self.down.load(Ordering::Relaxed) {
+            return Box::pin(future::ready(Err(TraceError::from("backend is down"))));
+        }
+        let names = batch.iter().map(|span| span.name.to_string()).collect();
+        self.exported.lock().unwrap().push(names);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+/// An empty spill directory for the test `name`.
+fn spill_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "axum_otel_tempo-spill-{}-{name}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// The names of the files in `dir`, oldest first.
+fn spill_files(dir: &PathBuf) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    files
+}
+
+#[tokio::test]
+async fn failed_exports_are_replayed_once_the_backend_recovers() {
+    let dir = spill_dir("replay");
+    let backend = Backend::default();
+    let mut spill = DiskSpill::new(backend.clone(), dir.clone(), u64::MAX);
+
+    backend.down.store(true, Ordering::Relaxed);
+    spill.export(batch("during outage")).await.unwrap();
+    assert_eq!(spill_files(&dir), ["00000000000000000000-1.otlp"]);
+
+    backend.down.store(false, Ordering::Relaxed);
+    spill.export(batch("first after")).await.unwrap();
+    spill.export(batch("second after")).await.unwrap();
+
+    assert_eq!(
+        backend.exported(),
+        [vec!["first after"], vec!["second after", "during outage"]]
+    );
+    assert!(spill_files(&dir).is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_oldest_files_are_evicted_past_the_byte_cap() {
+    let _guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let dir = spill_dir("evict");
+        let backend = Backend::default();
+        backend.down.store(true, Ordering::Relaxed);
+        DiskSpill::new(backend.clone(), dir.clone(), u64::MAX)
+            .export(batch("span-0"))
+            .await
+            .unwrap();
+        let file_size = fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .len();
+        let evicted = spill::spill_evicted_spans();
+
+        // Picks up the file spilled above, and keeps room for about two.
+        let mut spill = DiskSpill::new(backend.clone(), dir.clone(), file_size * 2 + file_size / 2);
+        spill.export(batch("span-1")).await.unwrap();
+        spill.export(batch("span-2")).await.unwrap();
+
+        assert_eq!(
+            spill_files(&dir),
+            ["00000000000000000001-1.otlp", "00000000000000000002-1.otlp"]
+        );
+        assert_eq!(spill::spill_evicted_spans(), evicted + 1);
+
+        backend.down.store(false, Ordering::Relaxed);
+        for name in ["span-3", "span-4", "span-5"] {
+            spill.export(batch(name)).await.unwrap();
+        }
+        assert_eq!(
+            backend.exported(),
+            [
+                vec!["span-3"],
+                vec!["span-4", "span-1"],
+                vec!["span-5", "span-2"],
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    });
+}
+
+#[test]
+fn the_file_being_replayed_is_not_evicted() {
+    let _guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let dir = spill_dir("held");
+        let backend = Backend::default();
+        backend.down.store(true, Ordering::Relaxed);
+        DiskSpill::new(backend.clone(), dir.clone(), u64::MAX)
+            .export(batch("span-0"))
+            .await
+            .unwrap();
+        let evicted = spill::spill_evicted_spans();
+
+        // Room for one file, which is read ahead once an export succeeds.
+        let mut spill = DiskSpill::new(backend.clone(), dir.clone(), 1);
+        backend.down.store(false, Ordering::Relaxed);
+        spill.export(batch("span-1")).await.unwrap();
+        backend.down.store(true, Ordering::Relaxed);
+        spill.export(batch("span-2")).await.unwrap();
+
+        // The replay failed with span-2, which went over the cap on its own.
+        assert_eq!(spill_files(&dir), ["00000000000000000000-1.otlp"]);
+        assert_eq!(spill::spill_evicted_spans(), evicted + 1);
+
+        backend.down.store(false, Ordering::Relaxed);
+        spill.export(batch("span-3")).await.unwrap();
+        assert_eq!(
+            backend.exported(),
+            [vec!["span-1"], vec!["span-3", "span-0"]]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    });
+}
@@ -0,0 +1,195 @@
+use axum_otel_tempo::{
+    clock::{self, ManualClock},
+    tail_sampling::{self, TailSampler},
+};
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{self, SpanProcessor, Tracer, TracerProvider},
+    },
+    trace::{Span, Status, TraceContextExt, TraceResult, Tracer as _, TracerProvider as _},
+    Context,
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+const LATENCY_THRESHOLD: Duration = Duration::from_millis(500);
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// The clock and the sampler counters are process wide, so tests take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Keeps every span that reaches it for the test to inspect.
+#[derive(Clone, Debug, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl Collected {
+    fn names(&self) -> Vec<String> {
+        let spans = self.0.lock().unwrap();
+        spans.iter().map(|span| span.name.to_string()).collect()
+    }
+}
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _span: &mut trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// A tail sampler keeping at most `max_spans` in front of the returned
+/// collector, on a manual clock.
+fn sampler(
+    max_spans: usize,
+) -> (
+    TracerProvider,
+    Tracer,
+    Collected,
+    ManualClock,
+    MutexGuard<'static, ()>,
+) {
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    clock::set_clock(clock.clone());
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(TailSampler::new(
+            Box::new(collected.clone()),
+            LATENCY_THRESHOLD,
+            WINDOW,
+            max_spans,
+        ))
+        .build();
+    let tracer = provider.tracer("test");
+    (provider, tracer, collected, clock, guard)
+}
+
+/// Starts the local root `name`, at the clock's time.
+fn start_root(tracer: &Tracer, name: &str) -> Context {
+    let span = tracer
+        .span_builder(name.to_owned())
+        .with_start_time(clock::now())
+        .start(tracer);
+    Context::new().with_span(span)
+}
+
+/// Runs a child of `root` for 10ms, ending it with `status`.
+fn child(tracer: &Tracer, clock: &ManualClock, root: &Context, name: &str, status: Status) {
+    let mut span = tracer
+        .span_builder(name.to_owned())
+        .with_start_time(clock::now())
+        .start_with_context(tracer, root);
+    clock.advance(Duration::from_millis(10));
+    span.set_status(status);
+    span.end_with_timestamp(clock::now());
+}
+
+/// Ends the root of `root` after `duration`.
+fn end_root(clock: &ManualClock, root: &Context, duration: Duration) {
+    clock.advance(duration);
+    root.span().end_with_timestamp(clock::now());
+}
+
+#[test]
+fn traces_with_an_error_are_kept() {
+    let (_provider, tracer, collected, clock, _guard) = sampler(100);
+
+    let root = start_root(&tracer, "failing");
+    child(&tracer, &clock, &root, "query", Status::error("timeout"));
+    child(&tracer, &clock, &root, "retry", Status::Unset);
+    end_root(&clock, &root, Duration::from_millis(20));
+
+    assert_eq!(collected.names(), ["query", "retry", "failing"]);
+}
+
+#[test]
+fn traces_are_kept_only_when_their_root_is_slow() {
+    let (_provider, tracer, collected, clock, _guard) = sampler(100);
+    let dropped = tail_sampling::tail_dropped_traces();
+
+    let fast = start_root(&tracer, "fast");
+    child(&tracer, &clock, &fast, "fast child", Status::Unset);
+    end_root(&clock, &fast, LATENCY_THRESHOLD / 2);
+    let slow = start_root(&tracer, "slow");
+    child(&tracer, &clock, &slow, "slow child", Status::Unset);
+    end_root(&clock, &slow, LATENCY_THRESHOLD);
+
+    assert_eq!(collected.names(), ["slow child", "slow"]);
+    assert_eq!(tail_sampling::tail_dropped_traces(), dropped + 1);
+}
+
+#[test]
+fn undecided_traces_are_evicted_once_their_window_runs_out() {
+    let (_provider, tracer, collected, clock, _guard) = sampler(100);
+    let evicted = tail_sampling::tail_evicted_traces();
+
+    let stalled = start_root(&tracer, "stalled");
+    child(&tracer, &clock, &stalled, "stalled child", Status::Unset);
+    clock.advance(WINDOW + Duration::from_secs(1));
+    let next = start_root(&tracer, "next");
+    child(&tracer, &clock, &next, "next child", Status::Unset);
+
+    assert_eq!(tail_sampling::tail_evicted_traces(), evicted + 1);
+
+    end_root(&clock, &next, LATENCY_THRESHOLD);
+    assert_eq!(collected.names(), ["next child", "next"]);
+}
+
+#[test]
+fn the_oldest_traces_are_evicted_beyond_max_spans() {
+    let (_provider, tracer, collected, clock, _guard) = sampler(2);
+    let evicted = tail_sampling::tail_evicted_traces();
+
+    let roots: Vec<_> = ["first", "second", "third"]
+        .into_iter()
+        .map(|name| {
+            let root = start_root(&tracer, name);
+            child(
+                &tracer,
+                &clock,
+                &root,
+                &format!("{name} child"),
+                Status::Unset,
+            );
+            root
+        })
+        .collect();
+    assert_eq!(tail_sampling::tail_evicted_traces(), evicted + 1);
+
+    for root in &roots[1..] {
+        end_root(&clock, root, LATENCY_THRESHOLD);
+    }
+    assert_eq!(
+        collected.names(),
+        ["second child", "second", "third child", "third"]
+    );
+}
+
+#[test]
+fn shutdown_settles_the_pending_traces() {
+    let (provider, tracer, collected, clock, _guard) = sampler(100);
+    let dropped = tail_sampling::tail_dropped_traces();
+
+    let slow = start_root(&tracer, "slow");
+    child(&tracer, &clock, &slow, "slow child", Status::Unset);
+    clock.advance(LATENCY_THRESHOLD);
+    let fast = start_root(&tracer, "fast");
+    child(&tracer, &clock, &fast, "fast child", Status::Unset);
+
+    provider.force_flush();
+    assert_eq!(collected.names(), ["slow child"]);
+    drop(provider);
+    assert_eq!(collected.names(), ["slow child"]);
+    assert_eq!(tail_sampling::tail_dropped_traces(), dropped + 1);
+}